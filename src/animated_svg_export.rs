@@ -0,0 +1,518 @@
+use std::{fmt::Write, fs, io, path::Path, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bevy::{
+    prelude::*,
+    render::mesh::{Mesh, VertexAttributeValues},
+};
+
+use crate::{
+    future_vis::{UnitShape, UNIT_STROKE_WIDTH, UNIT_WIDTH},
+    gantt_export::{escape, svg_color},
+    recording::RecordedEvent,
+    replay::{replay, UnitLook, UnitTrack, TWEEN_DURATION},
+    stream_vis::{
+        crecent_mesh, dashed_line, layout_blocks, StreamBlock, FONT_SIZE, SECTION_HEIGHT,
+        SINK_ROTATION, SOURCE_RAD, SOURCE_ROTATION, TEXT_MARGIN, UNIT_SIZE, VIEW_HEIGHT,
+        VIEW_WIDTH,
+    },
+    theme::theme,
+};
+
+pub const VIRGIL: &[u8] = include_bytes!("../assets/Virgil.woff2");
+
+const FPS: f32 = 30.;
+const LINE_HEIGHT: f32 = FONT_SIZE * 1.2;
+
+/// Self-contained animated SVG of a run, looping the same animation the window shows.
+/// Positions are sampled from the replayed tweens, colors switch at the recorded events
+pub fn write_animated_svg(
+    path: &Path,
+    blocks: &[StreamBlock],
+    events: &[RecordedEvent],
+) -> io::Result<()> {
+    let layout = layout_blocks(blocks.to_vec());
+    let units = replay(&layout, events);
+
+    // let the last tweens settle before looping
+    let total = events.last().map(|event| event.at).unwrap_or_default() + TWEEN_DURATION;
+
+    let mut svg = String::new();
+    _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{VIEW_WIDTH}" height="{VIEW_HEIGHT}" viewBox="{} {} {VIEW_WIDTH} {VIEW_HEIGHT}" font-family="Virgil" font-size="{FONT_SIZE}">"#,
+        layout.end / 2. - VIEW_WIDTH / 2.,
+        -VIEW_HEIGHT / 2.
+    );
+    _ = writeln!(
+        svg,
+        r#"<style>@font-face {{ font-family: "Virgil"; src: url(data:font/woff2;base64,{}) format("woff2"); }}</style>"#,
+        STANDARD.encode(VIRGIL)
+    );
+    _ = writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{VIEW_WIDTH}" height="{VIEW_HEIGHT}" fill="{}"/>"#,
+        layout.end / 2. - VIEW_WIDTH / 2.,
+        -VIEW_HEIGHT / 2.,
+        svg_color(theme().background)
+    );
+
+    let divider = dashed_line(SECTION_HEIGHT, 5., 2.);
+    for transform in &layout.dividers {
+        mesh_element(&mut svg, &divider, *transform, theme().divider);
+    }
+
+    for (block, transform) in &layout.blocks {
+        let Some(size) = block.size() else {
+            continue;
+        };
+
+        _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            transform.translation.x,
+            svg_y(transform.translation.y) - size.y / 2.,
+            size.x,
+            size.y,
+            svg_color(block.color())
+        );
+    }
+
+    for unit in &units {
+        unit_element(&mut svg, unit, total);
+    }
+
+    let crescent = crecent_mesh(64, SOURCE_RAD / 2.);
+    for (block, transform) in &layout.blocks {
+        let rotation = match block {
+            StreamBlock::Source(_) => SOURCE_ROTATION,
+            StreamBlock::Sink(_) => SINK_ROTATION,
+            _ => continue,
+        };
+
+        let mut transform = *transform;
+        transform.rotate_z(rotation);
+        mesh_element(&mut svg, &crescent, transform, theme().source);
+    }
+
+    for (block, transform) in &layout.blocks {
+        let Some(size) = block.size() else {
+            continue;
+        };
+
+        label_element(
+            &mut svg,
+            block,
+            transform.translation.x + size.x / 2.,
+            svg_y(transform.translation.y - TEXT_MARGIN),
+        );
+    }
+
+    svg.push_str("</svg>\n");
+
+    fs::write(path, svg)
+}
+
+// svg's y axis points down
+fn svg_y(y: f32) -> f32 {
+    0. - y
+}
+
+/// Triangles of a `TriangleList` mesh as a path. A thin outline of the same color hides the
+/// seams between neighbouring triangles
+fn mesh_element(svg: &mut String, mesh: &Mesh, transform: Transform, color: Color) {
+    let d = mesh_path(mesh, transform);
+    let color_hex = svg_color(color);
+    _ = writeln!(
+        svg,
+        r#"<path d="{d}" fill="{color_hex}" stroke="{color_hex}" stroke-width="0.5" stroke-linejoin="round" opacity="{}"/>"#,
+        color.a()
+    );
+}
+
+/// Corners of every triangle of a `TriangleList` mesh, placed by `transform`
+pub fn mesh_triangles(mesh: &Mesh, transform: Transform) -> Vec<Vec3> {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Vec::new();
+    };
+    let Some(indices) = mesh.indices() else {
+        return Vec::new();
+    };
+
+    indices
+        .iter()
+        .map(|i| transform.transform_point(Vec3::from(positions[i])))
+        .collect()
+}
+
+fn mesh_path(mesh: &Mesh, transform: Transform) -> String {
+    let mut d = String::new();
+    for triangle in mesh_triangles(mesh, transform).chunks_exact(3) {
+        for (i, point) in triangle.iter().enumerate() {
+            _ = write!(
+                d,
+                "{}{:.2} {:.2}",
+                if i == 0 { "M" } else { "L" },
+                point.x,
+                svg_y(point.y)
+            );
+        }
+        d.push('Z');
+    }
+
+    d
+}
+
+fn label_element(svg: &mut String, block: &StreamBlock, x: f32, y: f32) {
+    let mut lines = vec![Vec::new()];
+    for (text, color) in block.label_sections() {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Vec::new());
+            }
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push((part.to_string(), color));
+            }
+        }
+    }
+
+    // centered like `Anchor::Center`
+    let top = y - lines.len() as f32 * LINE_HEIGHT / 2.;
+    for (i, line) in lines.iter().enumerate() {
+        _ = write!(
+            svg,
+            r#"<text x="{x}" y="{}" text-anchor="middle" xml:space="preserve">"#,
+            top + i as f32 * LINE_HEIGHT + FONT_SIZE
+        );
+        for (text, color) in line {
+            _ = write!(
+                svg,
+                r#"<tspan fill="{}">{}</tspan>"#,
+                svg_color(*color),
+                escape(text)
+            );
+        }
+        svg.push_str("</text>\n");
+    }
+}
+
+fn unit_element(svg: &mut String, unit: &UnitTrack, total: Duration) {
+    let samples = (0..=(total.as_secs_f32() * FPS) as u32)
+        .map(|frame| Duration::from_secs_f32(frame as f32 / FPS))
+        .chain([total])
+        .collect::<Vec<_>>();
+
+    let start = unit.position(Duration::ZERO);
+    _ = writeln!(
+        svg,
+        r#"<g id="unit-{}" visibility="hidden" transform="translate({:.1} {:.1})">"#,
+        unit.id,
+        start.x,
+        svg_y(start.y)
+    );
+
+    discrete(
+        svg,
+        "visibility",
+        &[
+            (Duration::ZERO, "hidden".to_string()),
+            (unit.created, "visible".to_string()),
+        ],
+        total,
+    );
+
+    let positions = samples
+        .iter()
+        .map(|at| {
+            let position = unit.position(*at);
+            (*at, format!("{:.1} {:.1}", position.x, svg_y(position.y)))
+        })
+        .collect::<Vec<_>>();
+    linear(svg, "animateTransform", "transform", &positions, total);
+
+    if unit.faded().is_some() {
+        let opacities = samples
+            .iter()
+            .map(|at| (*at, format!("{:.3}", unit.opacity(*at))))
+            .collect::<Vec<_>>();
+        linear(svg, "animate", "opacity", &opacities, total);
+    }
+
+    let half = UNIT_WIDTH / 2.;
+    let initial = UnitLook::default();
+
+    // units staying square need no clipping
+    let shaped = unit
+        .looks
+        .iter()
+        .any(|(_, look)| look.shape != UnitShape::Square);
+    if shaped {
+        _ = writeln!(
+            svg,
+            r#"<clipPath id="unit-{}-shape"><path d="{}">"#,
+            unit.id,
+            outline_path(initial.shape)
+        );
+        discrete(
+            svg,
+            "d",
+            &looks(unit, |look| outline_path(look.shape)),
+            total,
+        );
+        _ = writeln!(
+            svg,
+            r#"</path></clipPath><g clip-path="url(#unit-{}-shape)">"#,
+            unit.id
+        );
+    }
+
+    // background
+    _ = writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{UNIT_WIDTH}" height="{UNIT_WIDTH}" fill="{}">"#,
+        -half,
+        -half,
+        svg_color(initial.background)
+    );
+    color_animations(svg, "fill", unit, total, |look| look.background);
+    svg.push_str("</rect>\n");
+
+    // future progress, a box scaled from the bottom like in `update_units`
+    let progress_y = |look: &UnitLook| UNIT_SIZE * (1. - look.progress) / 2. - half * look.progress;
+    let progress_height = |look: &UnitLook| UNIT_WIDTH * look.progress;
+    _ = writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{UNIT_WIDTH}" height="{}" fill="{}">"#,
+        -half,
+        progress_y(&initial),
+        progress_height(&initial),
+        svg_color(initial.progress_color)
+    );
+    color_animations(svg, "fill", unit, total, |look| look.progress_color);
+    discrete(
+        svg,
+        "y",
+        &looks(unit, |look| format!("{:.2}", progress_y(look))),
+        total,
+    );
+    discrete(
+        svg,
+        "height",
+        &looks(unit, |look| format!("{:.2}", progress_height(look))),
+        total,
+    );
+    svg.push_str("</rect>\n");
+    if shaped {
+        svg.push_str("</g>\n");
+    }
+
+    // stroke
+    let stroke_path = |shape: UnitShape| {
+        mesh_path(
+            &shape.stroke_mesh(UNIT_WIDTH, UNIT_STROKE_WIDTH),
+            Transform::IDENTITY,
+        )
+    };
+    _ = writeln!(
+        svg,
+        r#"<path d="{}" fill="{}" stroke="{}" stroke-width="0.3">"#,
+        stroke_path(initial.shape),
+        svg_color(initial.stroke),
+        svg_color(initial.stroke)
+    );
+    color_animations(svg, "fill", unit, total, |look| look.stroke);
+    color_animations(svg, "stroke", unit, total, |look| look.stroke);
+    if shaped {
+        discrete(
+            svg,
+            "d",
+            &looks(unit, |look| stroke_path(look.shape)),
+            total,
+        );
+    }
+    svg.push_str("</path>\n");
+
+    svg.push_str("</g>\n");
+}
+
+/// Polygon of a unit's shape, the area its background and future progress are drawn in
+fn outline_path(shape: UnitShape) -> String {
+    let mut d = String::new();
+    for (i, point) in shape.outline(UNIT_WIDTH).iter().enumerate() {
+        _ = write!(
+            d,
+            "{}{:.2} {:.2}",
+            if i == 0 { "M" } else { "L" },
+            point.x,
+            svg_y(point.y)
+        );
+    }
+    d.push('Z');
+    d
+}
+
+fn looks(unit: &UnitTrack, value: impl Fn(&UnitLook) -> String) -> Vec<(Duration, String)> {
+    let mut values = vec![(Duration::ZERO, value(&UnitLook::default()))];
+    values.extend(unit.looks.iter().map(|(at, look)| (*at, value(look))));
+    values
+}
+
+fn color_animations(
+    svg: &mut String,
+    attribute: &str,
+    unit: &UnitTrack,
+    total: Duration,
+    color: impl Fn(&UnitLook) -> Color,
+) {
+    discrete(
+        svg,
+        attribute,
+        &looks(unit, |look| svg_color(color(look))),
+        total,
+    );
+    discrete(
+        svg,
+        &format!("{attribute}-opacity"),
+        &looks(unit, |look| format!("{:.2}", color(look).a())),
+        total,
+    );
+}
+
+fn key_time(at: Duration, total: Duration) -> String {
+    format!("{:.4}", (at.as_secs_f32() / total.as_secs_f32()).min(1.))
+}
+
+/// Values switching at the given times, looping with the rest of the animation
+fn discrete(svg: &mut String, attribute: &str, values: &[(Duration, String)], total: Duration) {
+    let mut keyframes = Vec::<(String, &str)>::new();
+    for (at, value) in values {
+        let key_time = key_time(*at, total);
+        if keyframes.last().is_some_and(|(last, _)| *last == key_time) {
+            keyframes.pop();
+        }
+        if keyframes.last().is_some_and(|(_, last)| last == value) {
+            continue;
+        }
+        keyframes.push((key_time, value));
+    }
+
+    _ = writeln!(
+        svg,
+        r#"<animate attributeName="{attribute}" values="{}" keyTimes="{}" dur="{:.3}s" calcMode="discrete" repeatCount="indefinite"/>"#,
+        keyframes
+            .iter()
+            .map(|(_, value)| *value)
+            .collect::<Vec<_>>()
+            .join(";"),
+        keyframes
+            .iter()
+            .map(|(key_time, _)| key_time.as_str())
+            .collect::<Vec<_>>()
+            .join(";"),
+        total.as_secs_f32()
+    );
+}
+
+/// Values interpolated between samples, leaving out samples where nothing moves
+fn linear(
+    svg: &mut String,
+    element: &str,
+    attribute: &str,
+    samples: &[(Duration, String)],
+    total: Duration,
+) {
+    let keyframes = samples
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, value))| {
+            let same_as_previous = *i > 0 && samples[i - 1].1 == *value;
+            let same_as_next = samples.get(i + 1).is_some_and(|(_, next)| next == value);
+            !(same_as_previous && same_as_next)
+        })
+        .map(|(_, (at, value))| (key_time(*at, total), value.as_str()))
+        .collect::<Vec<_>>();
+
+    let kind = if element == "animateTransform" {
+        r#" type="translate""#
+    } else {
+        ""
+    };
+
+    _ = writeln!(
+        svg,
+        r#"<{element} attributeName="{attribute}"{kind} values="{}" keyTimes="{}" dur="{:.3}s" calcMode="linear" repeatCount="indefinite"/>"#,
+        keyframes
+            .iter()
+            .map(|(_, value)| *value)
+            .collect::<Vec<_>>()
+            .join(";"),
+        keyframes
+            .iter()
+            .map(|(key_time, _)| key_time.as_str())
+            .collect::<Vec<_>>()
+            .join(";"),
+        total.as_secs_f32()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::tests::run;
+
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn values(svg: &str) -> (&str, &str) {
+        let attribute = |name: &str| {
+            let start = svg.find(&format!(r#"{name}=""#)).unwrap() + name.len() + 2;
+            &svg[start..start + svg[start..].find('"').unwrap()]
+        };
+        (attribute("values"), attribute("keyTimes"))
+    }
+
+    #[test]
+    fn discrete_keeps_only_changes() {
+        let mut svg = String::new();
+        let switches = [
+            (ms(0), "a".to_string()),
+            (ms(100), "a".to_string()),
+            (ms(200), "b".to_string()),
+            (ms(200), "c".to_string()),
+        ];
+        discrete(&mut svg, "fill", &switches, ms(400));
+        assert_eq!(values(&svg), ("a;c", "0.0000;0.5000"));
+    }
+
+    #[test]
+    fn linear_leaves_out_still_samples() {
+        let mut svg = String::new();
+        let samples = [0, 100, 200, 300, 400]
+            .map(|at| (ms(at), if at < 300 { "0 0" } else { "5 0" }.to_string()));
+        linear(&mut svg, "animateTransform", "transform", &samples, ms(400));
+        assert!(svg.contains(r#"type="translate""#));
+        assert_eq!(
+            values(&svg),
+            ("0 0;0 0;5 0;5 0", "0.0000;0.5000;0.7500;1.0000")
+        );
+    }
+
+    #[test]
+    fn animates_every_unit() {
+        let (blocks, events) = run();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.svg");
+        write_animated_svg(&path, &blocks, &events).unwrap();
+
+        let svg = fs::read_to_string(path).unwrap();
+        assert!(svg.contains(r#"<g id="unit-1""#));
+        assert!(svg.contains(r#"<g id="unit-2""#));
+        // only the filtered out unit fades
+        assert_eq!(svg.matches(r#"attributeName="opacity""#).count(), 1);
+        assert!(svg.contains(r#"dur="1.070s""#));
+    }
+}
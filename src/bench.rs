@@ -0,0 +1,197 @@
+use std::time::Instant;
+
+use crate::{
+    stream_vis_builder::StreamVisBuilder,
+    timeline::{latencies, percentile, run_end, throughput, unit_timelines},
+};
+
+/// z score of a two sided 95% confidence interval, the runs are assumed to be enough for the
+/// normal approximation
+const Z_95: f32 = 1.96;
+
+/// Numbers of a single headless run
+pub struct RunMetrics {
+    pub total_secs: f32,
+    pub arrived: f32,
+    pub throughput: f32,
+    pub mean_latency: f32,
+    pub p95_latency: f32,
+}
+
+/// Mean of a metric over many runs, with its 95% confidence interval
+#[derive(Clone, Copy)]
+pub struct Estimate {
+    pub mean: f32,
+    /// Half the width of the confidence interval
+    pub margin: f32,
+    pub std_dev: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Estimate {
+    pub fn of(metrics: &[RunMetrics], metric: impl Fn(&RunMetrics) -> f32) -> Self {
+        let values = metrics.iter().map(metric).collect::<Vec<_>>();
+        let mean = mean(&values);
+        let std_dev = std_dev(&values, mean);
+
+        Self {
+            mean,
+            margin: Z_95 * std_dev / (values.len().max(1) as f32).sqrt(),
+            std_dev,
+            min: values.iter().copied().fold(f32::INFINITY, f32::min),
+            max: values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+
+    pub fn low(&self) -> f32 {
+        self.mean - self.margin
+    }
+
+    pub fn high(&self) -> f32 {
+        self.mean + self.margin
+    }
+}
+
+/// Run the pipeline built by `build` `runs` times, each on a fresh paused tokio runtime.
+/// Returns the pipeline's description and the metrics of every run
+pub fn simulate(
+    build: impl Fn() -> StreamVisBuilder + Sync,
+    runs: usize,
+) -> (String, Vec<RunMetrics>) {
+    // a runtime can't be started from within main's
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let mut description = String::new();
+                let metrics = (0..runs)
+                    .map(|_| {
+                        let rt = tokio::runtime::Builder::new_current_thread()
+                            .enable_time()
+                            .start_paused(true)
+                            .build()
+                            .unwrap();
+                        let (blocks, events) = rt.block_on(async { build().run().await });
+
+                        description = blocks
+                            .iter()
+                            .map(|block| block.name())
+                            .collect::<Vec<_>>()
+                            .join(" -> ");
+
+                        let units = unit_timelines(&events, &blocks);
+                        let mut latencies = latencies(&units);
+                        latencies.sort_by(f32::total_cmp);
+
+                        RunMetrics {
+                            total_secs: run_end(&events).as_secs_f32(),
+                            arrived: latencies.len() as f32,
+                            throughput: throughput(&units),
+                            mean_latency: mean(&latencies),
+                            p95_latency: percentile(&latencies, 95.).unwrap_or_default(),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                (description, metrics)
+            })
+            .join()
+            .unwrap()
+    })
+}
+
+/// A metric's name, unit and how to read it off a run
+type MetricRow = (&'static str, &'static str, fn(&RunMetrics) -> f32);
+
+/// Run the pipeline built by `build` `runs` times in virtual time, then print the
+/// confidence intervals of its metrics
+pub fn run(build: impl Fn() -> StreamVisBuilder + Sync, runs: usize) {
+    let started = Instant::now();
+    let (description, metrics) = simulate(build, runs);
+
+    println!(
+        "{runs} runs of {description} in {:.2}s",
+        started.elapsed().as_secs_f32()
+    );
+    println!(
+        "{:<16}{:>10}  {:>23}{:>10}{:>10}{:>10}",
+        "", "mean", "95% ci", "std dev", "min", "max"
+    );
+
+    let rows: [MetricRow; 5] = [
+        ("total runtime", "s", |run| run.total_secs),
+        ("throughput", "/s", |run| run.throughput),
+        ("latency mean", "s", |run| run.mean_latency),
+        ("latency p95", "s", |run| run.p95_latency),
+        ("units arrived", "", |run| run.arrived),
+    ];
+    for (name, unit, metric) in rows {
+        let estimate = Estimate::of(&metrics, metric);
+
+        let value = |value: f32| format!("{value:.3}{unit}");
+        println!(
+            "{name:<16}{:>10}  {:>23}{:>10}{:>10}{:>10}",
+            value(estimate.mean),
+            format!("[{}, {}]", value(estimate.low()), value(estimate.high())),
+            value(estimate.std_dev),
+            value(estimate.min),
+            value(estimate.max),
+        );
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return 0.;
+    }
+
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+/// Sample standard deviation
+fn std_dev(values: &[f32], mean: f32) -> f32 {
+    if values.len() < 2 {
+        return 0.;
+    }
+
+    let squares = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f32>();
+    (squares / (values.len() - 1) as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_and_sample_std_dev() {
+        let values = [2., 4., 4., 4., 5., 5., 7., 9.];
+        let mean = mean(&values);
+        assert_eq!(mean, 5.);
+        assert_eq!(std_dev(&values, mean), (32. / 7_f32).sqrt());
+    }
+
+    #[test]
+    fn estimates_a_confidence_interval() {
+        let metrics = [1., 2., 3.].map(|throughput| RunMetrics {
+            total_secs: 0.,
+            arrived: 0.,
+            throughput,
+            mean_latency: 0.,
+            p95_latency: 0.,
+        });
+        let estimate = Estimate::of(&metrics, |run| run.throughput);
+        assert_eq!((estimate.mean, estimate.std_dev), (2., 1.));
+        assert_eq!((estimate.min, estimate.max), (1., 3.));
+        assert_eq!(estimate.margin, Z_95 / 3_f32.sqrt());
+        assert_eq!(estimate.high() - estimate.low(), 2. * estimate.margin);
+    }
+
+    #[test]
+    fn no_spread_without_enough_runs() {
+        assert_eq!(mean(&[]), 0.);
+        assert_eq!(std_dev(&[3.], 3.), 0.);
+    }
+}
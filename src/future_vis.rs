@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, SQRT_2, TAU};
 
 use bevy::{
     prelude::*,
@@ -6,21 +6,49 @@ use bevy::{
     sprite::MaterialMesh2dBundle,
 };
 
-#[derive(Debug)]
+use crate::{lanes::id_in_lane, replay::UnitLook, stream_vis::UNIT_SIZE, UnitValueKind};
+
+/// State of a unit's future in its current block
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FutureState {
+    /// Not polled yet, or not created yet for units still in the source
     Pending,
-    Running(Timer),
+    /// Progress between 0 and 1
+    Running(f32),
     Done,
+    /// Resolved to an error
+    Failed,
+}
+
+impl FutureState {
+    pub fn of(value: &UnitValueKind) -> Option<Self> {
+        match value {
+            UnitValueKind::PendingFuture(_) => Some(FutureState::Pending),
+            UnitValueKind::RunningFuture(progress) if *progress >= 1. => Some(FutureState::Done),
+            UnitValueKind::RunningFuture(progress) => Some(FutureState::Running(*progress)),
+            UnitValueKind::Error => Some(FutureState::Failed),
+            UnitValueKind::Value(_) | UnitValueKind::Shape(_) | UnitValueKind::Label(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, Component)]
 pub struct StreamUnit {
     pub id: u32,
     pub cur_block: u32,
-    pub value: Color,
     pub future_state: FutureState,
 }
 
+impl StreamUnit {
+    pub fn new(id: u32, cur_block: u32) -> Self {
+        Self {
+            id,
+            cur_block,
+            future_state: FutureState::Pending,
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct UnitStroke;
 
@@ -29,10 +57,147 @@ pub struct UnitFutureProgress;
 
 #[derive(Component)]
 pub struct UnitBackground;
+
+#[derive(Component)]
+pub struct UnitLabel;
+
 pub const UNIT_WIDTH: f32 = 12.;
 pub const UNIT_STROKE_WIDTH: f32 = 1.;
+pub const UNIT_LABEL_FONT_SIZE: f32 = UNIT_WIDTH * 0.75;
+pub const UNIT_LABEL_COLOR: Color = Color::BLACK;
+const CIRCLE_SIDES: usize = 24;
+
+/// Outline of a unit, every one of its layers takes this shape
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnitShape {
+    #[default]
+    Square,
+    Circle,
+    Diamond,
+}
+
+impl UnitShape {
+    /// Corners of the shape, counterclockwise around the unit's center and within its square
+    pub fn outline(self, width: f32) -> Vec<Vec2> {
+        let (sides, radius, rotation) = match self {
+            UnitShape::Square => (4, width / 2. * SQRT_2, FRAC_PI_4),
+            UnitShape::Circle => (CIRCLE_SIDES, width / 2., 0.),
+            UnitShape::Diamond => (4, width / 2., FRAC_PI_2),
+        };
+
+        (0..sides)
+            .map(|i| Vec2::from_angle(rotation + TAU * i as f32 / sides as f32) * radius)
+            .collect()
+    }
+
+    /// The shape filled, for the unit's background and future progress
+    pub fn mesh(self, width: f32) -> Mesh {
+        let outline = self.outline(width);
+
+        let mut positions = vec![Vec3::ZERO];
+        positions.extend(outline.iter().map(|point| point.extend(0.)));
+        let indices = (0..outline.len() as u32)
+            .flat_map(|i| [0, i + 1, (i + 1) % outline.len() as u32 + 1])
+            .collect();
+
+        flat_mesh(positions, indices)
+    }
+
+    /// A stroke inside the shape's outline
+    pub fn stroke_mesh(self, width: f32, stroke_width: f32) -> Mesh {
+        if self == UnitShape::Square {
+            return stroke_mesh(width, stroke_width);
+        }
+
+        let outline = self.outline(width);
+        let sides = outline.len();
+        // the corners move in by the stroke width along the distance from the center to the sides
+        let apothem = outline[0].length() * (TAU / 2. / sides as f32).cos();
+        let inset = 1. - stroke_width / apothem;
+
+        let positions = outline
+            .iter()
+            .flat_map(|point| [point.extend(0.), (*point * inset).extend(0.)])
+            .collect();
+        let indices = (0..sides as u32)
+            .flat_map(|i| {
+                let next = (i + 1) % sides as u32;
+                [
+                    i * 2,
+                    next * 2,
+                    i * 2 + 1,
+                    next * 2,
+                    next * 2 + 1,
+                    i * 2 + 1,
+                ]
+            })
+            .collect();
+
+        flat_mesh(positions, indices)
+    }
+}
+
+/// Meshes of a unit shape, filled and stroked
+#[derive(Clone)]
+pub struct ShapeMeshes {
+    pub body: Handle<Mesh>,
+    pub stroke: Handle<Mesh>,
+}
+
+/// Meshes of every unit shape, added once and shared by all the units
+#[derive(Resource)]
+pub struct UnitMeshes([ShapeMeshes; 3]);
+
+impl UnitMeshes {
+    pub fn of(&self, shape: UnitShape) -> &ShapeMeshes {
+        &self.0[shape as usize]
+    }
+}
+
+pub fn add_unit_meshes(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let shapes = [UnitShape::Square, UnitShape::Circle, UnitShape::Diamond];
+    commands.insert_resource(UnitMeshes(shapes.map(|shape| ShapeMeshes {
+        body: meshes.add(shape.mesh(UNIT_WIDTH)),
+        stroke: meshes.add(shape.stroke_mesh(UNIT_WIDTH, UNIT_STROKE_WIDTH)),
+    })));
+}
+
+fn flat_mesh(positions: Vec<Vec3>, indices: Vec<u32>) -> Mesh {
+    let normals = vec![Vec3::Z; positions.len()];
+    let uvs = vec![Vec2::ZERO; positions.len()];
 
-pub fn stroke_mesh(width: f32, stroke_width: f32) -> Mesh {
+    Mesh::new(PrimitiveTopology::TriangleList)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_indices(Some(Indices::U32(indices)))
+}
+
+/// Font of the labels drawn on the units, only present when they have labels
+#[derive(Resource)]
+pub struct UnitLabels {
+    pub font: Handle<Font>,
+}
+
+impl UnitLabels {
+    /// The payload unit `id` in block `block_id` is labelled with, else its id within its lane
+    pub fn text(&self, id: u32, block_id: u32, label: Option<&str>, color: Color) -> Text {
+        let label = label
+            .map(str::to_string)
+            .unwrap_or_else(|| id_in_lane(id, block_id).to_string());
+
+        Text::from_section(
+            label,
+            TextStyle {
+                font: self.font.clone(),
+                font_size: UNIT_LABEL_FONT_SIZE,
+                color,
+            },
+        )
+    }
+}
+
+fn stroke_mesh(width: f32, stroke_width: f32) -> Mesh {
     let mut positions = Vec::with_capacity(8);
     let mut normals = Vec::with_capacity(8);
     let mut uvs = Vec::with_capacity(8);
@@ -80,60 +245,152 @@ pub fn stroke_mesh(width: f32, stroke_width: f32) -> Mesh {
         .with_indices(Some(Indices::U32(indices)))
 }
 
+/// Entities of a unit and of its layers
+pub struct UnitParts {
+    pub unit: Entity,
+    pub stroke: Entity,
+    pub background: Entity,
+    pub progress: Entity,
+    pub label: Option<Entity>,
+}
+
 pub fn spawn_unit(
     commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
+    meshes: &UnitMeshes,
     materials: &mut ResMut<Assets<ColorMaterial>>,
-    id: u32,
-    cur_block: u32,
+    unit: StreamUnit,
     transform: Transform,
-) {
-    commands
-        .spawn((
-            StreamUnit {
-                future_state: FutureState::Running(Timer::new(
-                    Duration::from_secs(1),
-                    TimerMode::Repeating,
-                )),
-                id,
-                cur_block,
-                value: Color::RED,
-            },
-            SpatialBundle::from_transform(transform),
-        ))
+    look: &UnitLook,
+    label: Option<Text>,
+) -> UnitParts {
+    let shape = meshes.of(look.shape);
+    let mut parts = UnitParts {
+        unit: Entity::PLACEHOLDER,
+        stroke: Entity::PLACEHOLDER,
+        background: Entity::PLACEHOLDER,
+        progress: Entity::PLACEHOLDER,
+        label: None,
+    };
+
+    parts.unit = commands
+        .spawn((unit, SpatialBundle::from_transform(transform)))
         .with_children(|parent| {
-            parent.spawn((
-                MaterialMesh2dBundle {
-                    mesh: meshes
-                        .add(stroke_mesh(UNIT_WIDTH, UNIT_STROKE_WIDTH))
-                        .into(),
-                    material: materials.add(ColorMaterial::from(Color::BLACK)),
-                    transform: Transform::from_xyz(0., 0., 20.),
-                    ..Default::default()
-                },
-                UnitStroke,
-            ));
+            parts.stroke = parent
+                .spawn((
+                    MaterialMesh2dBundle {
+                        mesh: shape.stroke.clone().into(),
+                        material: materials.add(ColorMaterial::from(look.stroke)),
+                        transform: Transform::from_xyz(0., 0., 20.),
+                        ..Default::default()
+                    },
+                    UnitStroke,
+                ))
+                .id();
 
-            parent.spawn((
-                MaterialMesh2dBundle {
-                    mesh: meshes.add(Mesh::from(shape::Cube::new(UNIT_WIDTH))).into(),
-                    material: materials.add(ColorMaterial::from(Color::WHITE)),
-                    // transform,
-                    ..Default::default()
-                },
-                UnitBackground,
-            ));
+            parts.background = parent
+                .spawn((
+                    MaterialMesh2dBundle {
+                        mesh: shape.body.clone().into(),
+                        material: materials.add(ColorMaterial::from(look.background)),
+                        // transform,
+                        ..Default::default()
+                    },
+                    UnitBackground,
+                ))
+                .id();
 
-            parent.spawn((
-                MaterialMesh2dBundle {
-                    mesh: meshes
-                        .add(Mesh::from(shape::Box::new(UNIT_WIDTH, UNIT_WIDTH, 1.)))
-                        .into(),
-                    material: materials.add(ColorMaterial::from(Color::BLACK)),
-                    transform: Transform::from_xyz(0., 0., 10.),
-                    ..Default::default()
-                },
-                UnitFutureProgress,
-            ));
-        });
+            parts.progress = parent
+                .spawn((
+                    MaterialMesh2dBundle {
+                        mesh: shape.body.clone().into(),
+                        material: materials.add(ColorMaterial::from(look.progress_color)),
+                        transform: Transform::from_xyz(
+                            0.,
+                            -UNIT_SIZE * (1. - look.progress) / 2.,
+                            10.,
+                        )
+                        .with_scale(Vec3::new(1., look.progress, 1.)),
+                        ..Default::default()
+                    },
+                    UnitFutureProgress,
+                ))
+                .id();
+
+            if let Some(text) = label {
+                parts.label = Some(
+                    parent
+                        .spawn((
+                            Text2dBundle {
+                                text,
+                                transform: Transform::from_xyz(0., 0., 30.),
+                                ..default()
+                            },
+                            UnitLabel,
+                        ))
+                        .id(),
+                );
+            }
+        })
+        .id();
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use crate::lanes::LANE_ID_STRIDE;
+
+    use super::*;
+
+    #[test]
+    fn labels_fall_back_to_the_id_within_the_lane() {
+        let labels = UnitLabels {
+            font: Handle::default(),
+        };
+        let text = |id, block_id, label| {
+            labels.text(id, block_id, label, Color::BLACK).sections[0]
+                .value
+                .clone()
+        };
+
+        assert_eq!(text(7, 1, None), "7");
+        assert_eq!(text(LANE_ID_STRIDE + 7, LANE_ID_STRIDE + 1, None), "7");
+        // a lone lane isn't offset, its ids can go past the stride
+        assert_eq!(text(LANE_ID_STRIDE + 7, 1, None), "1007");
+        assert_eq!(text(7, 1, Some("user 42")), "user 42");
+    }
+
+    #[test]
+    fn shapes_fit_the_unit() {
+        for shape in [UnitShape::Square, UnitShape::Circle, UnitShape::Diamond] {
+            let outline = shape.outline(UNIT_WIDTH);
+            assert!(outline.len() >= 4);
+            for point in outline {
+                assert!(
+                    point.abs().max_element() <= UNIT_WIDTH / 2. + 1e-4,
+                    "{shape:?}"
+                );
+            }
+        }
+        assert_eq!(UnitShape::Circle.outline(UNIT_WIDTH).len(), CIRCLE_SIDES);
+    }
+
+    #[test]
+    fn every_shape_has_its_own_meshes() {
+        let mut world = World::new();
+        world.init_resource::<Assets<Mesh>>();
+        world.run_system_once(add_unit_meshes);
+
+        let meshes = world.resource::<Assets<Mesh>>();
+        let unit_meshes = world.resource::<UnitMeshes>();
+        assert_eq!(meshes.len(), 6);
+        let circle = meshes.get(&unit_meshes.of(UnitShape::Circle).body).unwrap();
+        assert_eq!(circle.count_vertices(), CIRCLE_SIDES + 1);
+        assert_ne!(
+            unit_meshes.of(UnitShape::Square).stroke,
+            unit_meshes.of(UnitShape::Diamond).stroke
+        );
+    }
 }
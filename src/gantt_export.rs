@@ -0,0 +1,298 @@
+use std::{fmt::Write, fs, io, path::Path, time::Duration};
+
+use bevy::render::color::Color;
+
+use crate::{
+    recording::RecordedEvent,
+    stream_vis::StreamBlock,
+    theme::theme,
+    timeline::{run_end, unit_timelines},
+};
+
+const PX_PER_SEC: f32 = 120.;
+const ROW_HEIGHT: f32 = 18.;
+const BAR_HEIGHT: f32 = 12.;
+const LABEL_WIDTH: f32 = 70.;
+const AXIS_HEIGHT: f32 = 30.;
+const LEGEND_ROW_HEIGHT: f32 = 20.;
+const MARGIN: f32 = 20.;
+
+/// Static Gantt chart of a run: a row per unit, with its time pending, running and
+/// blocked in every stage
+pub fn write_gantt_svg(
+    path: &Path,
+    blocks: &[StreamBlock],
+    events: &[RecordedEvent],
+) -> io::Result<()> {
+    let end = run_end(events);
+    let units = unit_timelines(events, blocks);
+
+    let chart_width = end.as_secs_f32() * PX_PER_SEC;
+    let chart_height = units.len() as f32 * ROW_HEIGHT;
+    let stages = blocks
+        .iter()
+        .filter(|block| !matches!(block, StreamBlock::Source(_) | StreamBlock::Sink(_)))
+        .collect::<Vec<_>>();
+
+    let width = LABEL_WIDTH + chart_width + MARGIN * 2.;
+    let height =
+        AXIS_HEIGHT + chart_height + MARGIN * 2. + (stages.len() + 1) as f32 * LEGEND_ROW_HEIGHT;
+
+    let mut svg = String::new();
+    _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="11">"#
+    );
+    _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        svg_color(theme().background)
+    );
+    _ = writeln!(
+        svg,
+        r#"<defs><pattern id="blocked" width="4" height="4" patternUnits="userSpaceOnUse" patternTransform="rotate(45)"><rect width="2" height="4" fill="white" fill-opacity="0.6"/></pattern></defs>"#
+    );
+
+    let chart_x = MARGIN + LABEL_WIDTH;
+    let chart_y = MARGIN + AXIS_HEIGHT;
+    let x = |at: Duration| chart_x + at.as_secs_f32() * PX_PER_SEC;
+
+    // time axis, a tick per 100ms and a label per second
+    for tick in 0..=(end.as_millis() / 100) as u64 {
+        let tick_x = x(Duration::from_millis(tick * 100));
+        let is_second = tick % 10 == 0;
+        _ = writeln!(
+            svg,
+            r#"<line x1="{tick_x}" y1="{}" x2="{tick_x}" y2="{}" stroke="white" stroke-opacity="{}"/>"#,
+            chart_y - if is_second { 8. } else { 4. },
+            chart_y + chart_height,
+            if is_second { 0.3 } else { 0.08 }
+        );
+        if is_second {
+            _ = writeln!(
+                svg,
+                r#"<text x="{tick_x}" y="{}" fill="white" text-anchor="middle">{}s</text>"#,
+                chart_y - 12.,
+                tick / 10
+            );
+        }
+    }
+
+    for (row, unit) in units.iter().enumerate() {
+        let row_y = chart_y + row as f32 * ROW_HEIGHT;
+        let bar_y = row_y + (ROW_HEIGHT - BAR_HEIGHT) / 2.;
+
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" fill="white" dominant-baseline="middle">unit {}</text>"#,
+            MARGIN,
+            row_y + ROW_HEIGHT / 2.,
+            unit.id
+        );
+
+        // waiting in the source to be pulled by the first stage
+        let left_source = unit.started().unwrap_or(end);
+        bar(
+            &mut svg,
+            x(unit.created),
+            x(left_source),
+            bar_y,
+            &svg_color(Color::WHITE),
+            0.15,
+        );
+
+        for stage in &unit.stages {
+            let left = stage.left.unwrap_or(end);
+            let future_color = svg_color(theme().future_color(stage.block_id));
+
+            bar(
+                &mut svg,
+                x(stage.entered),
+                x(stage.started.unwrap_or(left)),
+                bar_y,
+                &future_color,
+                0.35,
+            );
+
+            if let Some(started) = stage.started {
+                bar(
+                    &mut svg,
+                    x(started),
+                    x(stage.finished.unwrap_or(left)),
+                    bar_y,
+                    &future_color,
+                    1.,
+                );
+            }
+
+            // done, but held back by the block, e.g. head of line blocking in `buffered`
+            if let Some(finished) = stage.finished {
+                let block_color = blocks
+                    .iter()
+                    .find(|block| block.id() == stage.block_id)
+                    .map(|block| svg_color(block.color()))
+                    .unwrap_or_else(|| svg_color(Color::WHITE));
+
+                bar(&mut svg, x(finished), x(left), bar_y, &block_color, 1.);
+                if x(left) > x(finished) {
+                    _ = writeln!(
+                        svg,
+                        r#"<rect x="{}" y="{bar_y}" width="{}" height="{BAR_HEIGHT}" fill="url(#blocked)"/>"#,
+                        x(finished),
+                        x(left) - x(finished)
+                    );
+                }
+            }
+        }
+
+        // dropped along with the stream, rather than by a filter
+        let crossed = match (unit.filtered_out, unit.dropped) {
+            (Some(at), _) => Some((at, Color::RED)),
+            (None, Some(at)) => Some((at, Color::GRAY)),
+            (None, None) => None,
+        };
+        if let Some((at, color)) = crossed {
+            let drop_x = x(at);
+            _ = writeln!(
+                svg,
+                r#"<path d="M{} {} L{} {} M{} {} L{} {}" stroke="{}" stroke-width="2"/>"#,
+                drop_x - 4.,
+                bar_y,
+                drop_x + 4.,
+                bar_y + BAR_HEIGHT,
+                drop_x + 4.,
+                bar_y,
+                drop_x - 4.,
+                bar_y + BAR_HEIGHT,
+                svg_color(color)
+            );
+        }
+
+        if let Some(at) = unit.sink {
+            let fill = match unit.failed {
+                Some(_) => svg_color(theme().error),
+                None => "white".to_string(),
+            };
+            _ = writeln!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="3" fill="{fill}"/>"#,
+                x(at),
+                bar_y + BAR_HEIGHT / 2.
+            );
+        }
+    }
+
+    // legend, a row per stage with its pending, running and blocked swatches
+    let legend_y = chart_y + chart_height + MARGIN;
+    for (i, block) in stages.iter().enumerate() {
+        let row_y = legend_y + i as f32 * LEGEND_ROW_HEIGHT;
+        let future_color = svg_color(theme().future_color(block.id()));
+
+        bar(&mut svg, MARGIN, MARGIN + 12., row_y, &future_color, 0.35);
+        bar(
+            &mut svg,
+            MARGIN + 14.,
+            MARGIN + 26.,
+            row_y,
+            &future_color,
+            1.,
+        );
+        bar(
+            &mut svg,
+            MARGIN + 28.,
+            MARGIN + 40.,
+            row_y,
+            &svg_color(block.color()),
+            1.,
+        );
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" fill="white" dominant-baseline="middle">{} - pending, running, blocked</text>"#,
+            MARGIN + 48.,
+            row_y + BAR_HEIGHT / 2.,
+            escape(&block.name())
+        );
+    }
+    _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" fill="white" dominant-baseline="middle">× filtered out   ● reached the sink</text>"#,
+        MARGIN,
+        legend_y + stages.len() as f32 * LEGEND_ROW_HEIGHT + BAR_HEIGHT / 2.
+    );
+
+    svg.push_str("</svg>\n");
+
+    fs::write(path, svg)
+}
+
+fn bar(svg: &mut String, from_x: f32, to_x: f32, y: f32, color: &str, opacity: f32) {
+    if to_x <= from_x {
+        return;
+    }
+
+    _ = writeln!(
+        svg,
+        r#"<rect x="{from_x}" y="{y}" width="{}" height="{BAR_HEIGHT}" fill="{color}" fill-opacity="{opacity}"/>"#,
+        to_x - from_x
+    );
+}
+
+pub fn svg_color(color: Color) -> String {
+    let [r, g, b, _] = color.as_rgba_u8();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::tests::run;
+
+    use super::*;
+
+    fn gantt() -> String {
+        let (blocks, events) = run();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gantt.svg");
+        write_gantt_svg(&path, &blocks, &events).unwrap();
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn draws_a_row_per_unit() {
+        let svg = gantt();
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">unit 1</text>"));
+        assert!(svg.contains(">unit 2</text>"));
+        assert_eq!(
+            svg.matches("<circle").count(),
+            1,
+            "one unit reached the sink"
+        );
+        assert_eq!(svg.matches("<path").count(), 1, "one unit was filtered out");
+        assert!(svg.contains(".map(500ms).buffer(1) - pending, running, blocked"));
+    }
+
+    #[test]
+    fn bar_widths_follow_the_timings() {
+        let svg = gantt();
+        let x = |millis| MARGIN + LABEL_WIDTH + millis as f32 / 1000. * PX_PER_SEC;
+        // unit 1 runs for 40ms and is then held back by the block for 10ms
+        let running = x(60) - x(20);
+        let blocked = x(70) - x(60);
+        assert!(svg.contains(&format!(r#"width="{running}" height="{BAR_HEIGHT}""#)));
+        assert!(svg.contains(&format!(
+            r#"width="{blocked}" height="{BAR_HEIGHT}" fill="url(#blocked)""#
+        )));
+    }
+
+    #[test]
+    fn escapes_labels() {
+        assert_eq!(escape("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+        assert_eq!(svg_color(Color::rgb(1., 0.5, 0.)), "#ff7f00");
+    }
+}
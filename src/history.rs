@@ -0,0 +1,536 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_tweening::{
+    lens::{ColorMaterialColorLens, TextColorLens, TransformPositionLens},
+    Animator, AssetAnimator, EaseFunction, Tween, Tweenable,
+};
+
+use crate::{
+    future_vis::{spawn_unit, StreamUnit, UnitLabels, UnitMeshes, UNIT_LABEL_COLOR},
+    playback::{Playback, STEP},
+    recording::RecordedEvent,
+    replay::{Replay, UnitTrack, TWEEN_DURATION},
+    stats_overlay::PipelineStats,
+    stream_vis::StreamBlock,
+    StreamEvent, StreamUpdate,
+};
+
+const STEP_BACK_KEY: KeyCode = KeyCode::Left;
+/// Stream time between snapshots, seeking replays at most that much of the history
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+const TIMELINE_HEIGHT: f32 = 8.;
+const TIMELINE_FONT_SIZE: f32 = 13.;
+
+/// The scene after the first `applied` updates of the history
+struct Snapshot {
+    at: Duration,
+    applied: usize,
+    scene: Replay,
+}
+
+/// Every update shown so far, timestamped in the stream time of the animation, with snapshots of
+/// the scene to seek back from
+#[derive(Resource)]
+pub struct History {
+    events: Vec<RecordedEvent>,
+    snapshots: Vec<Snapshot>,
+    /// The scene as of the last update, trimmed with every snapshot
+    head: Replay,
+    /// Stream time the pipelines ran for
+    live: Duration,
+    /// Stream time shown while replaying the history, `None` while showing the pipelines live
+    cursor: Option<Duration>,
+    /// Stream time to rebuild the scene at
+    seek: Option<Duration>,
+}
+
+impl History {
+    /// An empty history of the blocks, placed at their translations
+    pub fn new(blocks: Vec<(StreamBlock, Vec3)>) -> Self {
+        let head = Replay::new(blocks);
+
+        Self {
+            events: Vec::new(),
+            snapshots: vec![Snapshot {
+                at: Duration::ZERO,
+                applied: 0,
+                scene: head.clone(),
+            }],
+            head,
+            live: Duration::ZERO,
+            cursor: None,
+            seek: None,
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.cursor.is_none()
+    }
+
+    pub fn live(&self) -> Duration {
+        self.live
+    }
+
+    /// Stream time of the scene shown
+    pub fn shown(&self) -> Duration {
+        self.cursor.unwrap_or(self.live)
+    }
+
+    /// Updates up to the time shown
+    pub fn shown_events(&self) -> &[RecordedEvent] {
+        self.events_until(self.shown())
+    }
+
+    /// Record an update of the pipelines, happening now
+    pub fn record(&mut self, update: &StreamUpdate) {
+        let event = RecordedEvent {
+            at: self.live,
+            update: update.clone(),
+        };
+        self.head.apply(&event);
+        self.events.push(event);
+    }
+
+    /// Show the scene as it was at `to`, replaying the history from there
+    pub fn seek(&mut self, to: Duration) {
+        let to = to.min(self.live);
+        self.cursor = (to < self.live).then_some(to);
+        self.seek = Some(to);
+    }
+
+    fn applied_until(&self, at: Duration) -> usize {
+        self.events.partition_point(|event| event.at <= at)
+    }
+
+    fn events_between(&self, from: Duration, to: Duration) -> &[RecordedEvent] {
+        &self.events[self.applied_until(from)..self.applied_until(to)]
+    }
+
+    fn events_until(&self, at: Duration) -> &[RecordedEvent] {
+        &self.events[..self.applied_until(at)]
+    }
+
+    /// Go on from the latest snapshot taken before `at`
+    fn scene_at(&self, at: Duration) -> Replay {
+        let snapshot = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.at <= at)
+            .unwrap_or(&self.snapshots[0]);
+
+        let mut scene = snapshot.scene.clone();
+        for event in &self.events[snapshot.applied..self.applied_until(at)] {
+            scene.apply(event);
+        }
+        scene
+    }
+
+    fn snapshot_if_due(&mut self) {
+        let last = self.snapshots.last().unwrap();
+        if self.live < last.at + SNAPSHOT_INTERVAL {
+            return;
+        }
+
+        self.head = self.head.trimmed();
+        self.snapshots.push(Snapshot {
+            at: self.live,
+            applied: self.events.len(),
+            scene: self.head.clone(),
+        });
+    }
+}
+
+#[derive(Component)]
+pub struct TimelineBar;
+
+#[derive(Component)]
+pub struct TimelineFill;
+
+#[derive(Component)]
+pub struct TimelineText;
+
+/// Move through the history while replaying it until catching up with the pipelines, and take
+/// snapshots while live
+pub fn advance_history(
+    time: Res<Time<Virtual>>,
+    mut history: ResMut<History>,
+    mut events: EventWriter<StreamEvent>,
+) {
+    let Some(cursor) = history.cursor else {
+        history.snapshot_if_due();
+        history.live += time.delta();
+        return;
+    };
+
+    let to = (cursor + time.delta()).min(history.live);
+    for event in history.events_between(cursor, to) {
+        events.send(StreamEvent(event.update.clone()));
+    }
+    history.cursor = (to < history.live).then_some(to);
+}
+
+/// Clicking or dragging along the timeline seeks there and the left arrow steps back, both
+/// pausing the animation
+pub fn timeline_controls(
+    keys: Res<Input<KeyCode>>,
+    bars: Query<(&Interaction, &Node, &GlobalTransform), With<TimelineBar>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut history: ResMut<History>,
+    mut time: ResMut<Time<Virtual>>,
+    mut playback: ResMut<Playback>,
+) {
+    let mut seek = None;
+
+    if keys.just_pressed(STEP_BACK_KEY) {
+        seek = Some(history.shown().saturating_sub(STEP));
+    }
+
+    for (interaction, node, transform) in &bars {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(cursor) = windows.get_single().ok().and_then(Window::cursor_position) else {
+            continue;
+        };
+
+        let left = transform.translation().x - node.size().x / 2.;
+        let fraction = ((cursor.x - left) / node.size().x).clamp(0., 1.);
+        seek = Some(history.live().mul_f32(fraction));
+    }
+
+    let Some(to) = seek.filter(|to| *to != history.shown()) else {
+        return;
+    };
+    playback.pause(&mut time);
+    history.seek(to);
+}
+
+/// Rebuild the blocks, units and stats as they were at the time sought
+#[allow(clippy::too_many_arguments)]
+pub fn restore_history(
+    mut commands: Commands,
+    meshes: Res<UnitMeshes>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut history: ResMut<History>,
+    mut events: ResMut<Events<StreamEvent>>,
+    mut stats: ResMut<PipelineStats>,
+    mut blocks: Query<&mut StreamBlock>,
+    units: Query<Entity, With<StreamUnit>>,
+    labels: Option<Res<UnitLabels>>,
+) {
+    let Some(at) = history.seek.take() else {
+        return;
+    };
+    let scene = history.scene_at(at);
+
+    // updates not animated yet are either part of the scene rebuilt or replayed later
+    events.clear();
+
+    for mut block in &mut blocks {
+        if let Some(restored) = scene.blocks().find(|restored| restored.id() == block.id()) {
+            *block = restored.clone();
+        }
+    }
+
+    for entity in &units {
+        commands.entity(entity).despawn_recursive();
+    }
+    for unit in scene.units() {
+        spawn_restored_unit(
+            &mut commands,
+            &meshes,
+            &mut materials,
+            labels.as_deref(),
+            unit,
+            at,
+        );
+    }
+
+    let blocks = blocks.iter().collect::<Vec<_>>();
+    let mut restored = PipelineStats::default();
+    for event in history.events_until(at) {
+        restored.record(&event.update, event.at.as_secs_f32(), &blocks);
+    }
+    restored.forget_arrivals(at.as_secs_f32());
+    *stats = restored;
+}
+
+/// Spawn a unit as it was at `at`, resuming the tweens it was in the middle of
+fn spawn_restored_unit(
+    commands: &mut Commands,
+    meshes: &UnitMeshes,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    labels: Option<&UnitLabels>,
+    unit: &UnitTrack,
+    at: Duration,
+) {
+    let opacity = unit.opacity(at);
+    let mut look = unit.look(at);
+    for color in [
+        &mut look.stroke,
+        &mut look.background,
+        &mut look.progress_color,
+    ] {
+        color.set_a(color.a() * opacity);
+    }
+
+    let parts = spawn_unit(
+        commands,
+        meshes,
+        materials,
+        StreamUnit {
+            future_state: unit.future_state,
+            ..StreamUnit::new(unit.id, unit.block_id)
+        },
+        Transform::from_translation(unit.position(at).extend(10.)),
+        &look,
+        labels.map(|labels| {
+            let color = UNIT_LABEL_COLOR.with_a(opacity);
+            labels.text(unit.id, unit.block_id, unit.label.as_deref(), color)
+        }),
+    );
+
+    let motion = unit.motion(at);
+    let elapsed = at.saturating_sub(motion.at);
+    if elapsed < TWEEN_DURATION && motion.from != motion.to {
+        let tween = Tween::new(
+            EaseFunction::ExponentialOut,
+            TWEEN_DURATION,
+            TransformPositionLens {
+                start: motion.from.extend(10.),
+                end: motion.to.extend(10.),
+            },
+        );
+        commands
+            .entity(parts.unit)
+            .insert(Animator::new(resumed(tween, elapsed)));
+    }
+
+    let Some(faded) = unit.faded().filter(|faded| *faded <= at) else {
+        return;
+    };
+    let elapsed = at - faded;
+    if elapsed >= TWEEN_DURATION {
+        return;
+    }
+
+    for (entity, color) in [
+        (parts.stroke, Color::WHITE),
+        (parts.background, Color::WHITE),
+        (parts.progress, Color::GRAY),
+    ] {
+        let tween = Tween::new(
+            EaseFunction::ExponentialOut,
+            TWEEN_DURATION,
+            ColorMaterialColorLens {
+                start: color,
+                end: color.with_a(0.),
+            },
+        );
+        commands
+            .entity(entity)
+            .insert(AssetAnimator::new(resumed(tween, elapsed)));
+    }
+
+    if let Some(label) = parts.label {
+        let tween = Tween::new(
+            EaseFunction::ExponentialOut,
+            TWEEN_DURATION,
+            TextColorLens {
+                start: UNIT_LABEL_COLOR,
+                end: UNIT_LABEL_COLOR.with_a(0.),
+                section: 0,
+            },
+        );
+        commands
+            .entity(label)
+            .insert(Animator::new(resumed(tween, elapsed)));
+    }
+}
+
+/// A tween already `elapsed` into its run
+fn resumed<T>(mut tween: Tween<T>, elapsed: Duration) -> Tween<T> {
+    tween.set_elapsed(elapsed);
+    tween
+}
+
+pub fn spawn_timeline(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(5.),
+                left: Val::Px(10.),
+                right: Val::Px(10.),
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(10.),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            flex_grow: 1.,
+                            height: Val::Px(TIMELINE_HEIGHT),
+                            ..default()
+                        },
+                        background_color: Color::WHITE.with_a(0.15).into(),
+                        ..default()
+                    },
+                    Interaction::default(),
+                    TimelineBar,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(0.),
+                                height: Val::Percent(100.),
+                                ..default()
+                            },
+                            background_color: Color::WHITE.with_a(0.6).into(),
+                            ..default()
+                        },
+                        TimelineFill,
+                    ));
+                });
+
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("Virgil.ttf"),
+                        font_size: TIMELINE_FONT_SIZE,
+                        color: Color::WHITE,
+                    },
+                ),
+                TimelineText,
+            ));
+        });
+}
+
+/// Fill the timeline up to the time shown, out of the time the pipelines ran for
+pub fn update_timeline(
+    history: Res<History>,
+    mut fills: Query<&mut Style, With<TimelineFill>>,
+    mut texts: Query<&mut Text, With<TimelineText>>,
+) {
+    let shown = history.shown().as_secs_f32();
+    let live = history.live().as_secs_f32();
+
+    let width = if live > 0. {
+        Val::Percent(100. * shown / live)
+    } else {
+        Val::Percent(0.)
+    };
+    for mut style in fills.iter_mut() {
+        if style.width != width {
+            style.width = width;
+        }
+    }
+
+    let label = format!("{shown:.1}s / {live:.1}s");
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream_vis_builder::{JitteringDuration, StreamVisBuilder};
+
+    use super::*;
+
+    /// What's shown of every unit at `at`
+    fn shown(scene: &Replay, at: Duration) -> Vec<String> {
+        scene
+            .units()
+            .map(|unit| {
+                format!(
+                    "{} in {} {:?} {:?} at {} {:?} {}",
+                    unit.id,
+                    unit.block_id,
+                    unit.future_state,
+                    unit.label,
+                    unit.position(at),
+                    unit.look(at),
+                    unit.opacity(at)
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeking_from_snapshots_matches_replaying_from_the_start() {
+        let duration = JitteringDuration::from_millis(300, 2.);
+        let (blocks, events) = StreamVisBuilder::source_seeded(12, 3)
+            .map_buffered(duration, 3)
+            .filter(duration, 0.5)
+            .try_buffered(duration, 2, 0.1)
+            .run()
+            .await;
+        let blocks = blocks
+            .into_iter()
+            .enumerate()
+            .map(|(i, block)| (block, Vec3::new(i as f32 * 300., 0., 0.)))
+            .collect::<Vec<_>>();
+
+        // as `advance_history` records the updates while live
+        let mut history = History::new(blocks.clone());
+        for event in &events {
+            history.live = event.at;
+            history.snapshot_if_due();
+            history.record(&event.update);
+        }
+        assert!(history.snapshots.len() > 2);
+
+        let end = events.last().unwrap().at;
+        for millis in (0..=end.as_millis() as u64 + 500).step_by(100) {
+            let at = Duration::from_millis(millis);
+            let mut replayed = Replay::new(blocks.clone());
+            for event in events.iter().take_while(|event| event.at <= at) {
+                replayed.apply(event);
+            }
+
+            assert_eq!(
+                shown(&history.scene_at(at), at),
+                shown(&replayed, at),
+                "at {at:?}"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeking_stops_at_the_live_edge() {
+        let (blocks, events) = StreamVisBuilder::source_seeded(3, 1)
+            .map_buffered(JitteringDuration::from_millis(100, 0.), 1)
+            .run()
+            .await;
+        let mut history = History::new(
+            blocks
+                .into_iter()
+                .map(|block| (block, Vec3::ZERO))
+                .collect(),
+        );
+        for event in &events {
+            history.live = event.at;
+            history.record(&event.update);
+        }
+
+        history.seek(history.live / 2);
+        assert!(!history.is_live());
+        assert_eq!(history.shown(), history.live / 2);
+        assert!(history.shown_events().len() < events.len());
+
+        history.seek(history.live * 2);
+        assert!(history.is_live());
+        assert_eq!(history.shown_events().len(), events.len());
+    }
+}
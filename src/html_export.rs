@@ -0,0 +1,335 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bevy::prelude::*;
+use serde_json::{json, Value};
+
+use crate::{
+    animated_svg_export::{mesh_triangles, VIRGIL},
+    future_vis::{UnitShape, UNIT_STROKE_WIDTH, UNIT_WIDTH},
+    recording::RecordedEvent,
+    replay::{replay, UnitLook, TWEEN_DURATION},
+    stream_vis::{
+        crecent_mesh, dashed_line, layout_blocks, StreamBlock, FONT_SIZE, SECTION_HEIGHT,
+        SINK_ROTATION, SOURCE_RAD, SOURCE_ROTATION, TEXT_MARGIN, UNIT_SIZE, VIEW_HEIGHT,
+        VIEW_WIDTH,
+    },
+    theme::theme,
+    timeline::{run_end, unit_timelines},
+    StreamUpdate, UnitValueKind,
+};
+
+const PLAYER: &str = include_str!("player.html");
+
+/// Offline HTML page replaying a run on a canvas, with play/pause, scrubbing, speed control and
+/// a unit inspector. The pipeline is placed by `layout_blocks`, like `spawn_blocks` does
+pub fn write_html_player(
+    path: &Path,
+    blocks: &[StreamBlock],
+    events: &[RecordedEvent],
+) -> io::Result<()> {
+    let layout = layout_blocks(blocks.to_vec());
+    let end = run_end(events);
+
+    let block_name = |id: u32| {
+        blocks
+            .iter()
+            .find(|block| block.id() == id)
+            .map(|block| block.name())
+            .unwrap_or_else(|| format!("block {id}"))
+    };
+
+    let divider = dashed_line(SECTION_HEIGHT, 5., 2.);
+    let dividers = layout
+        .dividers
+        .iter()
+        .map(|transform| shape(&mesh_triangles(&divider, *transform), theme().divider))
+        .collect::<Vec<_>>();
+
+    let crescent = crecent_mesh(64, SOURCE_RAD / 2.);
+    let crescents = layout
+        .blocks
+        .iter()
+        .filter_map(|(block, transform)| {
+            let rotation = match block {
+                StreamBlock::Source(_) => SOURCE_ROTATION,
+                StreamBlock::Sink(_) => SINK_ROTATION,
+                _ => return None,
+            };
+
+            let mut transform = *transform;
+            transform.rotate_z(rotation);
+            Some(shape(&mesh_triangles(&crescent, transform), theme().source))
+        })
+        .collect::<Vec<_>>();
+
+    let block_data = layout
+        .blocks
+        .iter()
+        .map(|(block, transform)| {
+            let size = block.size().unwrap_or_default();
+            json!({
+                "id": block.id(),
+                "name": block.name(),
+                "x": transform.translation.x,
+                "y": transform.translation.y,
+                "width": size.x,
+                "height": size.y,
+                "color": css_color(block.color()),
+                "label": block
+                    .label_sections()
+                    .into_iter()
+                    .map(|(text, color)| json!([text, css_color(color)]))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let timelines = unit_timelines(events, blocks);
+    let units = replay(&layout, events)
+        .into_iter()
+        .map(|unit| {
+            let timeline = timelines.iter().find(|timeline| timeline.id == unit.id);
+
+            let unit_events = events
+                .iter()
+                .filter_map(|event| {
+                    describe(&event.update, unit.id, &block_name)
+                        .map(|text| json!([secs(event.at), text]))
+                })
+                .collect::<Vec<_>>();
+
+            json!({
+                "id": unit.id,
+                "created": secs(unit.created),
+                "filteredOut": unit.filtered_out.map(secs),
+                "dropped": unit.dropped.map(secs),
+                "sink": timeline.and_then(|timeline| timeline.sink).map(secs),
+                "motions": unit
+                    .motions
+                    .iter()
+                    .map(|motion| json!([
+                        secs(motion.at),
+                        motion.from.x,
+                        motion.from.y,
+                        motion.to.x,
+                        motion.to.y,
+                    ]))
+                    .collect::<Vec<_>>(),
+                "looks": unit
+                    .looks
+                    .iter()
+                    .map(|(at, look)| json!([secs(*at), look_data(look)]))
+                    .collect::<Vec<_>>(),
+                "stages": timeline
+                    .map(|timeline| {
+                        timeline
+                            .stages
+                            .iter()
+                            .map(|stage| json!({
+                                "block": block_name(stage.block_id),
+                                "entered": secs(stage.entered),
+                                "started": stage.started.map(secs),
+                                "finished": stage.finished.map(secs),
+                                "left": stage.left.map(secs),
+                            }))
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default(),
+                "events": unit_events,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let data = json!({
+        "duration": secs(end + TWEEN_DURATION),
+        "tween": secs(TWEEN_DURATION),
+        "view": {
+            "x": layout.end / 2. - VIEW_WIDTH / 2.,
+            "y": -VIEW_HEIGHT / 2.,
+            "width": VIEW_WIDTH,
+            "height": VIEW_HEIGHT,
+        },
+        "background": css_color(theme().background),
+        "fontSize": FONT_SIZE,
+        "textMargin": TEXT_MARGIN,
+        "unitWidth": UNIT_WIDTH,
+        "unitSize": UNIT_SIZE,
+        "unitShapes": unit_shapes(),
+        "dividers": dividers,
+        "crescents": crescents,
+        "blocks": block_data,
+        "units": units,
+        "eventTimes": events.iter().map(|event| secs(event.at)).collect::<Vec<_>>(),
+    });
+
+    // `</` can't appear inside the inline script
+    let data = data.to_string().replace("</", "<\\/");
+
+    let html = PLAYER
+        .replace("{{FONT}}", &STANDARD.encode(VIRGIL))
+        .replace("{{DATA}}", &data);
+
+    fs::write(path, html)
+}
+
+fn secs(at: Duration) -> f64 {
+    at.as_secs_f64()
+}
+
+fn css_color(color: Color) -> String {
+    let [r, g, b, _] = color.as_rgba_u8();
+    format!("rgba({r}, {g}, {b}, {})", color.a())
+}
+
+fn shape(triangles: &[Vec3], color: Color) -> Value {
+    json!({
+        "color": css_color(color),
+        "points": triangles
+            .iter()
+            .flat_map(|point| [point.x, point.y])
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn look_data(look: &UnitLook) -> Value {
+    json!({
+        "stroke": css_color(look.stroke),
+        "background": css_color(look.background),
+        "progressColor": css_color(look.progress_color),
+        "progress": look.progress,
+        "shape": shape_name(look.shape),
+    })
+}
+
+fn shape_name(shape: UnitShape) -> String {
+    format!("{shape:?}").to_lowercase()
+}
+
+/// Every unit shape's outline, clipping the unit's layers, and its stroke's triangles
+fn unit_shapes() -> Value {
+    [UnitShape::Square, UnitShape::Circle, UnitShape::Diamond]
+        .into_iter()
+        .map(|shape| {
+            let stroke = shape.stroke_mesh(UNIT_WIDTH, UNIT_STROKE_WIDTH);
+            let data = json!({
+                "outline": shape
+                    .outline(UNIT_WIDTH)
+                    .iter()
+                    .flat_map(|point| [point.x, point.y])
+                    .collect::<Vec<_>>(),
+                "stroke": mesh_triangles(&stroke, Transform::IDENTITY)
+                    .iter()
+                    .flat_map(|point| [point.x, point.y])
+                    .collect::<Vec<_>>(),
+            });
+            (shape_name(shape), data)
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// Line of the unit inspector's event list
+fn describe(
+    update: &StreamUpdate,
+    unit_id: u32,
+    block_name: &impl Fn(u32) -> String,
+) -> Option<String> {
+    match update {
+        StreamUpdate::Created(created) if created.id == unit_id => {
+            Some(format!("created in {}", block_name(created.block_id)))
+        }
+        StreamUpdate::AdvanceBlock(advance) if advance.id == unit_id => {
+            Some(format!("entered {}", block_name(advance.block_id)))
+        }
+        StreamUpdate::ChangeValue(change) if change.id == unit_id => Some(match change.value {
+            UnitValueKind::PendingFuture(_) => "future pending".to_string(),
+            UnitValueKind::RunningFuture(progress) if progress >= 1. => "future done".to_string(),
+            UnitValueKind::RunningFuture(progress) => {
+                format!("future running {:.0}%", progress * 100.)
+            }
+            UnitValueKind::Value(_) => "value changed".to_string(),
+            UnitValueKind::Shape(shape) => format!("shaped as a {}", shape_name(shape)),
+            UnitValueKind::Label(ref label) => format!("labelled {label}"),
+            UnitValueKind::Error => "future failed".to_string(),
+        }),
+        StreamUpdate::FilteredOut(filtered) if filtered.id == unit_id => {
+            Some("filtered out".to_string())
+        }
+        StreamUpdate::Dropped(dropped) if dropped.id == unit_id => {
+            Some("dropped with the stream".to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::tests::run;
+
+    use super::*;
+
+    /// The data the page's script replays
+    fn player_data() -> Value {
+        let (blocks, events) = run();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.html");
+        write_html_player(&path, &blocks, &events).unwrap();
+
+        let html = fs::read_to_string(path).unwrap();
+        assert!(!html.contains("{{"), "placeholders left in the page");
+        let data = html.split("const data = ").nth(1).unwrap();
+        let data = &data[..data.find(";\n").unwrap()];
+        serde_json::from_str(&data.replace("<\\/", "</")).unwrap()
+    }
+
+    #[test]
+    fn lists_blocks_and_units() {
+        let data = player_data();
+        assert_eq!(data["duration"], 1.07);
+        let names = data["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| block["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["source", ".map(500ms).buffer(1)", "sink"]);
+
+        let [arrived, filtered] = data["units"].as_array().unwrap().as_slice() else {
+            panic!("expected two units");
+        };
+        assert_eq!(arrived["sink"], 0.07);
+        assert_eq!(arrived["filteredOut"], Value::Null);
+        assert_eq!(filtered["filteredOut"], 0.03);
+        assert_eq!(arrived["stages"][0]["block"], ".map(500ms).buffer(1)");
+    }
+
+    #[test]
+    fn describes_the_events_of_a_unit() {
+        let data = player_data();
+        let events = data["units"][0]["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event[1].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                "created in source",
+                "entered .map(500ms).buffer(1)",
+                "future running 50%",
+                "future done",
+                "entered sink"
+            ]
+        );
+    }
+
+    #[test]
+    fn css_colors_keep_alpha() {
+        assert_eq!(
+            css_color(Color::rgba(1., 0., 0., 0.5)),
+            "rgba(255, 0, 0, 0.5)"
+        );
+    }
+}
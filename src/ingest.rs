@@ -0,0 +1,394 @@
+use std::{
+    io::{self, BufRead, BufReader},
+    net::TcpListener,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+use bevy::render::color::Color;
+use crossbeam_channel::bounded;
+
+use crate::{
+    future_vis::UnitShape,
+    recording::{EventLog, UpdateSender},
+    stream_vis::{
+        BufferBlock, BufferUnrderedBlock, FilterBlock, FilterKind, SinkBlock, SourceBlock,
+        StreamBlock,
+    },
+    stream_vis_builder::StreamPipeline,
+    DroppedEvent, FilteredOutEvent, StreamUpdate, UnitAdvanceBlockEvent, UnitCreatedEvent,
+    UnitValueKind, UnitValueUpdateEvent,
+};
+
+/// Wait for an external process to connect on `addr` (a `host:port` or `unix:` and a socket path),
+/// read its pipeline description, and forward the rest of its events on the returned channel.
+/// The wire format is documented in the `stream-vis-client` crate
+pub fn listen(addr: &str) -> io::Result<StreamPipeline> {
+    let mut reader = accept(addr)?;
+
+    let blocks = read_pipeline(&mut reader)?;
+    log::debug!("received pipeline with {} blocks", blocks.len());
+
+    let (tx, rx) = bounded::<StreamUpdate>(100);
+    let log = EventLog::new();
+    let sender = UpdateSender::new(tx, log.clone());
+
+    std::thread::spawn(move || {
+        for (i, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::error!("failed reading from client: {e}");
+                    break;
+                }
+            };
+
+            match parse_update(&line) {
+                Ok(Some(update)) => sender.send(update),
+                Ok(None) => (),
+                Err(e) => log::warn!("ignoring malformed event on line {}: {}", i + 1, e),
+            }
+        }
+
+        log::debug!("client disconnected");
+    });
+
+    Ok(StreamPipeline {
+        blocks,
+        rx,
+        log,
+        stop: None,
+    })
+}
+
+fn accept(addr: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        return accept_unix(path);
+    }
+
+    let listener = TcpListener::bind(addr)?;
+    log::info!("waiting for a client on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    log::info!("client connected from {}", peer);
+    Ok(Box::new(BufReader::new(stream)))
+}
+
+#[cfg(unix)]
+fn accept_unix(path: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    // only replace a socket left behind by a previous run, never a regular file
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path} already exists and is not a socket"),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    log::info!("waiting for a client on {}", path);
+    let (stream, _) = listener.accept()?;
+    log::info!("client connected");
+    Ok(Box::new(BufReader::new(stream)))
+}
+
+#[cfg(not(unix))]
+fn accept_unix(path: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot listen on {path}, unix sockets are not supported on this platform"),
+    ))
+}
+
+fn read_pipeline(reader: &mut Box<dyn BufRead + Send>) -> io::Result<Vec<StreamBlock>> {
+    let mut blocks = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+
+        let block = match fields.as_slice() {
+            [] => continue,
+            ["start"] => return Ok(blocks),
+            ["source", id] => StreamBlock::Source(SourceBlock { id: parse(id)? }),
+            ["map_buffered", id, millis, buffered] => StreamBlock::MapBuffer(BufferBlock {
+                id: parse(id)?,
+                duration: parse_duration(millis)?,
+                buffered: parse(buffered)?,
+                failure_ratio: None,
+                units: Default::default(),
+            }),
+            ["map_buffer_unordered", id, millis, buffered] => {
+                let buffered = parse(buffered)?;
+                StreamBlock::MapBufferUnordered(BufferUnrderedBlock::new(
+                    parse(id)?,
+                    buffered * 3,
+                    parse_duration(millis)?,
+                    buffered,
+                ))
+            }
+            ["filter", id, millis] => StreamBlock::FilterBlock(FilterBlock {
+                id: parse(id)?,
+                duration: parse_duration(millis)?,
+                filter_ratio: None,
+                predicate: None,
+                kind: FilterKind::Filter,
+                failure_ratio: None,
+            }),
+            ["sink", id] => StreamBlock::Sink(SinkBlock { id: parse(id)? }),
+            _ => {
+                return Err(invalid(format!(
+                    "unexpected pipeline description on line {}: {}",
+                    i + 1,
+                    line
+                )))
+            }
+        };
+
+        blocks.push(block);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "client disconnected before starting the pipeline",
+    ))
+}
+
+fn parse_update(line: &str) -> io::Result<Option<StreamUpdate>> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+
+    let update = match fields.as_slice() {
+        [] => return Ok(None),
+        ["created", id, block_id] => StreamUpdate::Created(UnitCreatedEvent {
+            id: parse(id)?,
+            block_id: parse(block_id)?,
+            value: UnitValueKind::Value(Color::WHITE),
+        }),
+        ["advance", id, from_block_id, block_id] => {
+            StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
+                id: parse(id)?,
+                block_id: parse(block_id)?,
+                from_block_id: parse(from_block_id)?,
+            })
+        }
+        ["pending", id, r, g, b] => StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+            id: parse(id)?,
+            value: UnitValueKind::PendingFuture(Color::rgb(parse(r)?, parse(g)?, parse(b)?)),
+        }),
+        ["running", id, progress] => StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+            id: parse(id)?,
+            value: UnitValueKind::RunningFuture(parse(progress)?),
+        }),
+        ["value", id, r, g, b] => StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+            id: parse(id)?,
+            value: UnitValueKind::Value(Color::rgb(parse(r)?, parse(g)?, parse(b)?)),
+        }),
+        ["shape", id, shape] => StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+            id: parse(id)?,
+            value: UnitValueKind::Shape(match *shape {
+                "square" => UnitShape::Square,
+                "circle" => UnitShape::Circle,
+                "diamond" => UnitShape::Diamond,
+                _ => return Err(invalid(format!("unknown shape: {}", shape))),
+            }),
+        }),
+        ["label", id, label @ ..] if !label.is_empty() => {
+            StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id: parse(id)?,
+                value: UnitValueKind::Label(label.join(" ")),
+            })
+        }
+        ["error", id] => StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+            id: parse(id)?,
+            value: UnitValueKind::Error,
+        }),
+        ["filtered", id] => StreamUpdate::FilteredOut(FilteredOutEvent { id: parse(id)? }),
+        ["dropped", id] => StreamUpdate::Dropped(DroppedEvent { id: parse(id)? }),
+        _ => return Err(invalid(format!("unknown event: {}", line))),
+    };
+
+    Ok(Some(update))
+}
+
+/// Milliseconds, or `-` when the client doesn't know how long its futures take
+fn parse_duration(field: &str) -> io::Result<Option<Duration>> {
+    match field {
+        "-" => Ok(None),
+        millis => Ok(Some(Duration::from_millis(parse(millis)?))),
+    }
+}
+
+fn parse<T: std::str::FromStr>(field: &str) -> io::Result<T> {
+    field
+        .parse()
+        .map_err(|_| invalid(format!("invalid field: {}", field)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use stream_vis_client::{Client, Shape};
+
+    use super::*;
+
+    fn pipeline(text: &str) -> io::Result<Vec<StreamBlock>> {
+        let mut reader: Box<dyn BufRead + Send> = Box::new(io::Cursor::new(text.to_string()));
+        read_pipeline(&mut reader)
+    }
+
+    fn pipeline_error(text: &str) -> io::Error {
+        match pipeline(text) {
+            Ok(_) => panic!("{text:?} should not be a pipeline"),
+            Err(e) => e,
+        }
+    }
+
+    fn update(line: &str) -> String {
+        format!("{:?}", parse_update(line).unwrap().unwrap())
+    }
+
+    #[test]
+    fn reads_the_pipeline_until_start() {
+        let blocks = pipeline(
+            "source 0\nmap_buffered 1 500 3\n\nmap_buffer_unordered 2 - 2\nfilter 3 200\n\
+             sink 4\nstart\ncreated 0 0\n",
+        )
+        .unwrap();
+
+        let [StreamBlock::Source(_), StreamBlock::MapBuffer(map), StreamBlock::MapBufferUnordered(unordered), StreamBlock::FilterBlock(filter), StreamBlock::Sink(sink)] =
+            blocks.as_slice()
+        else {
+            panic!("unexpected blocks");
+        };
+        assert_eq!(
+            (map.id, map.duration, map.buffered),
+            (1, Some(Duration::from_millis(500)), 3)
+        );
+        assert_eq!(
+            (unordered.id, unordered.duration, unordered.buffered),
+            (2, None, 2)
+        );
+        assert_eq!(
+            (filter.id, filter.duration),
+            (3, Some(Duration::from_millis(200)))
+        );
+        assert_eq!(sink.id, 4);
+    }
+
+    #[test]
+    fn rejects_broken_pipelines() {
+        let e = pipeline_error("source 0\nmap_buffered 1 fast 3\n");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let e = pipeline_error("source 0\nbuffered 1 500 3\n");
+        assert_eq!(
+            e.to_string(),
+            "unexpected pipeline description on line 2: buffered 1 500 3"
+        );
+
+        let e = pipeline_error("source 0\nsink 1\n");
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn parses_every_event() {
+        assert_eq!(
+            update("created 3 0"),
+            format!(
+                "{:?}",
+                StreamUpdate::Created(UnitCreatedEvent {
+                    id: 3,
+                    block_id: 0,
+                    value: UnitValueKind::Value(Color::WHITE),
+                })
+            )
+        );
+        assert!(update("advance 3 1 2").contains("id: 3, block_id: 2, from_block_id: 1"));
+        // a lone lane isn't offset, its ids can go past the lane stride
+        assert!(update("filtered 5000").contains("id: 5000"));
+        assert!(update("pending 3 0.5 0.25 1").contains("PendingFuture"));
+        assert!(update("running 3 0.4").contains("RunningFuture(0.4)"));
+        assert!(update("value 3 1 0 0").contains("Value"));
+        assert!(update("label 3 user 42").contains("Label(\"user 42\")"));
+        assert!(update("shape 3 diamond").contains("Shape(Diamond)"));
+        assert!(update("error 3").contains("Error"));
+        assert!(update("filtered 3").starts_with("FilteredOut"));
+        assert!(update("dropped 3").starts_with("Dropped"));
+        assert!(parse_update("   ").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_events() {
+        for line in [
+            "created 3",
+            "advance x 1 2",
+            "running 3 done",
+            "label 3",
+            "shape 3 hexagon",
+            "teleport 3 4",
+        ] {
+            let e = parse_update(line).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{line}");
+        }
+    }
+
+    #[test]
+    fn shapes_round_trip() {
+        let mut written = Vec::new();
+        let mut client = Client::new(&mut written);
+        for shape in [Shape::Square, Shape::Circle, Shape::Diamond] {
+            client.shape(3, shape).unwrap();
+        }
+
+        let shapes = String::from_utf8(written)
+            .unwrap()
+            .lines()
+            .map(|line| match parse_update(line).unwrap() {
+                Some(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                    value: UnitValueKind::Shape(shape),
+                    ..
+                })) => shape,
+                update => panic!("unexpected update {update:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shapes,
+            [UnitShape::Square, UnitShape::Circle, UnitShape::Diamond]
+        );
+    }
+
+    #[test]
+    fn labels_round_trip() {
+        let mut written = Vec::new();
+        let mut client = Client::new(&mut written);
+        client.label(3, "  two\twords   apart ").unwrap();
+        let injected = client.label(3, "x\ndropped 4").unwrap_err();
+        assert_eq!(injected.kind(), io::ErrorKind::InvalidInput);
+        assert!(client.label(3, "x\r").is_err());
+        for blank in ["", " \t "] {
+            let e = client.label(3, blank).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let written = String::from_utf8(written).unwrap();
+        let updates = written
+            .lines()
+            .map(|line| parse_update(line).unwrap())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            updates.as_slice(),
+            [Some(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id: 3,
+                value: UnitValueKind::Label(label),
+            }))] if label == "two words apart"
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    future_vis::{FutureState, StreamUnit, UNIT_WIDTH},
+    history::History,
+    lanes::{id_in_lane, lane_of},
+    stream_vis::StreamBlock,
+    theme::theme,
+    timeline::{unit_timelines, UnitTimeline},
+};
+
+const PANEL_FONT_SIZE: f32 = 13.;
+/// Slack around a unit still counting as pointing at it
+const PICK_MARGIN: f32 = 3.;
+const OUTLINE_MARGIN: f32 = 4.;
+
+/// The unit clicked, detailed in the panel, and the one under the cursor, its trail highlighted
+#[derive(Resource, Default)]
+pub struct Inspector {
+    selected: Option<u32>,
+    hovered: Option<u32>,
+}
+
+#[derive(Component)]
+pub struct InspectorPanel;
+
+#[derive(Component)]
+pub struct InspectorText;
+
+/// Hover the unit under the cursor, clicking selects it or clears the selection
+pub fn pick_units(
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    units: Query<(&StreamUnit, &GlobalTransform)>,
+    interactions: Query<&Interaction>,
+    mut inspector: ResMut<Inspector>,
+) {
+    let Ok((camera, camera_transform)) = cameras.get_single() else {
+        return;
+    };
+    let position = windows
+        .get_single()
+        .ok()
+        .and_then(Window::cursor_position)
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor));
+
+    inspector.hovered = position.and_then(|position| {
+        units
+            .iter()
+            .filter(|(_, transform)| {
+                let offset = (transform.translation().truncate() - position).abs();
+                offset.max_element() <= UNIT_WIDTH / 2. + PICK_MARGIN
+            })
+            // units piling up in the sink overlap, the latest one is picked
+            .max_by_key(|(unit, _)| unit.id)
+            .map(|(unit, _)| unit.id)
+    });
+
+    // clicks on the timeline are for seeking
+    let on_ui = interactions
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if mouse.just_pressed(MouseButton::Left) && !on_ui {
+        inspector.selected = inspector.hovered;
+    }
+}
+
+/// The stages of unit `id` up to the time shown
+fn unit_timeline<'a>(
+    history: &History,
+    blocks: impl Iterator<Item = &'a StreamBlock>,
+    unit: &StreamUnit,
+) -> Option<UnitTimeline> {
+    // the unit's lane, ids of every lane are offset
+    let blocks = blocks
+        .filter(|block| lane_of(block.id()) == lane_of(unit.cur_block))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    unit_timelines(history.shown_events(), &blocks)
+        .into_iter()
+        .find(|timeline| timeline.id == unit.id)
+}
+
+fn block_name<'a>(mut blocks: impl Iterator<Item = &'a StreamBlock>, id: u32) -> String {
+    blocks
+        .find(|block| block.id() == id)
+        .map(|block| block.name())
+        .unwrap_or_else(|| format!("block {id}"))
+}
+
+fn secs(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f32())
+}
+
+/// Where the unit is and what it's doing, then every block it entered with its timestamps
+fn describe(
+    unit: &StreamUnit,
+    timeline: &UnitTimeline,
+    blocks: &[&StreamBlock],
+    now: Duration,
+) -> String {
+    let name = |id| block_name(blocks.iter().copied(), id);
+
+    let mut lines = vec![format!("unit {}", id_in_lane(unit.id, unit.cur_block))];
+    if lane_of(unit.cur_block) > 0 {
+        lines[0] += &format!(" of lane {}", lane_of(unit.cur_block) + 1);
+    }
+
+    let current_stage = timeline
+        .stages
+        .last()
+        .filter(|stage| stage.left.is_none() && timeline.filtered_out.is_none());
+    if let Some(filtered_out) = timeline.filtered_out {
+        lines.push(format!("filtered out at {}", secs(filtered_out)));
+    } else if let Some(dropped) = timeline.dropped {
+        lines.push(format!("dropped at {}", secs(dropped)));
+    } else if let Some(sink) = timeline.sink {
+        let error = if timeline.failed.is_some() {
+            " with an error"
+        } else {
+            ""
+        };
+        lines.push(format!("reached the sink{error} at {}", secs(sink)));
+    } else if let Some(stage) = current_stage {
+        let state = match unit.future_state {
+            FutureState::Pending => "pending".to_string(),
+            FutureState::Running(progress) => format!("running, {:.0}%", progress * 100.),
+            FutureState::Done => "done".to_string(),
+            FutureState::Failed => "failed".to_string(),
+        };
+        lines.push(format!("in {}, future {state}", name(stage.block_id)));
+
+        if let Some(finished) = stage.finished {
+            lines.push(format!(
+                "blocked behind earlier units for {}",
+                secs(now.saturating_sub(finished))
+            ));
+        }
+    } else {
+        lines.push("waiting in the source".to_string());
+    }
+
+    lines.push(String::new());
+    lines.push(format!("{}  created", secs(timeline.created)));
+    for stage in &timeline.stages {
+        let mut line = format!("{}  entered {}", secs(stage.entered), name(stage.block_id));
+        if let Some(started) = stage.started {
+            line += &format!(", started {}", secs(started));
+        }
+        if let Some(finished) = stage.finished {
+            let done = if timeline.failed == Some(finished) {
+                "failed"
+            } else {
+                "done"
+            };
+            line += &format!(", {done} {}", secs(finished));
+
+            let blocked = stage.left.unwrap_or(now).saturating_sub(finished);
+            if !blocked.is_zero() {
+                line += &format!(", blocked {}", secs(blocked));
+            }
+        }
+        lines.push(line);
+    }
+    if let Some(sink) = timeline.sink {
+        lines.push(format!("{}  entered the sink", secs(sink)));
+    }
+
+    lines.join("\n")
+}
+
+pub fn spawn_inspector_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(25.),
+                    right: Val::Px(10.),
+                    padding: UiRect::all(Val::Px(5.)),
+                    ..default()
+                },
+                background_color: Color::BLACK.with_a(0.6).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            InspectorPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("Virgil.ttf"),
+                        font_size: PANEL_FONT_SIZE,
+                        color: Color::WHITE,
+                    },
+                ),
+                InspectorText,
+            ));
+        });
+}
+
+/// Detail the selected unit, the panel is hidden while none is
+pub fn update_inspector_panel(
+    inspector: Res<Inspector>,
+    history: Res<History>,
+    units: Query<&StreamUnit>,
+    blocks: Query<&StreamBlock>,
+    mut panels: Query<&mut Visibility, With<InspectorPanel>>,
+    mut texts: Query<&mut Text, With<InspectorText>>,
+) {
+    // a unit sought away from is gone until the animation gets back to it
+    let details = inspector.selected.and_then(|id| {
+        let unit = units.iter().find(|unit| unit.id == id)?;
+        let timeline = unit_timeline(&history, blocks.iter(), unit)?;
+        let blocks = blocks.iter().collect::<Vec<_>>();
+        Some(describe(unit, &timeline, &blocks, history.shown()))
+    });
+
+    let visibility = if details.is_some() {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+    for mut panel in panels.iter_mut() {
+        if *panel != visibility {
+            *panel = visibility;
+        }
+    }
+
+    let Some(details) = details else {
+        return;
+    };
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != details {
+            text.sections[0].value = details.clone();
+        }
+    }
+}
+
+/// Outline the selected and the hovered unit, and draw the hovered one's trail through every
+/// block it entered
+pub fn draw_unit_trails(
+    inspector: Res<Inspector>,
+    history: Res<History>,
+    units: Query<(&StreamUnit, &GlobalTransform)>,
+    blocks: Query<(&StreamBlock, &GlobalTransform)>,
+    mut gizmos: Gizmos,
+) {
+    let color = theme().text;
+    let position = |id| {
+        units
+            .iter()
+            .find(|(unit, _)| unit.id == id)
+            .map(|(_, transform)| transform.translation().truncate())
+    };
+    let outline = Vec2::splat(UNIT_WIDTH + OUTLINE_MARGIN);
+
+    if let Some(selected) = inspector.selected.and_then(position) {
+        gizmos.rect_2d(selected, 0., outline, color);
+    }
+
+    let Some((unit, transform)) = inspector
+        .hovered
+        .and_then(|id| units.iter().find(|(unit, _)| unit.id == id))
+    else {
+        return;
+    };
+    let Some(timeline) = unit_timeline(&history, blocks.iter().map(|(block, _)| block), unit)
+    else {
+        return;
+    };
+    let hovered = transform.translation().truncate();
+
+    let center = |block_id| {
+        blocks
+            .iter()
+            .find(|(block, _)| block.id() == block_id)
+            .map(|(block, transform)| block.center(transform.translation()))
+    };
+    let source = blocks.iter().find_map(|(block, transform)| match block {
+        StreamBlock::Source(source) if lane_of(source.id) == lane_of(unit.cur_block) => {
+            Some(block.center(transform.translation()))
+        }
+        _ => None,
+    });
+
+    let mut trail = source.into_iter().collect::<Vec<_>>();
+    trail.extend(
+        timeline
+            .stages
+            .iter()
+            .filter_map(|stage| center(stage.block_id)),
+    );
+    trail.push(hovered);
+
+    gizmos.linestrip_2d(trail, color.with_a(0.7));
+    gizmos.rect_2d(hovered, 0., outline, color);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lanes::LANE_ID_STRIDE, timeline::tests::run, UnitValueKind};
+
+    use super::*;
+
+    fn described(unit: StreamUnit, shown: usize, now: u64) -> String {
+        let (blocks, events) = run();
+        let timeline = unit_timelines(&events[..shown.min(events.len())], &blocks)
+            .into_iter()
+            .find(|timeline| timeline.id == id_in_lane(unit.id, unit.cur_block))
+            .unwrap();
+        let blocks = blocks.iter().collect::<Vec<_>>();
+        describe(&unit, &timeline, &blocks, Duration::from_millis(now))
+    }
+
+    #[test]
+    fn describes_units_that_left_the_pipeline() {
+        assert_eq!(
+            described(StreamUnit::new(1, 3), 9, 70),
+            "unit 1\n\
+             reached the sink at 0.07s\n\
+             \n\
+             0.00s  created\n\
+             0.01s  entered .map(500ms).buffer(1), started 0.02s, done 0.06s, blocked 0.01s\n\
+             0.07s  entered the sink"
+        );
+        assert_eq!(
+            described(StreamUnit::new(2, 2), 9, 70),
+            "unit 2\n\
+             filtered out at 0.03s\n\
+             \n\
+             0.00s  created\n\
+             0.01s  entered .map(500ms).buffer(1)"
+        );
+    }
+
+    #[test]
+    fn describes_the_future_of_units_in_a_stage() {
+        let running = StreamUnit {
+            future_state: FutureState::Running(0.5),
+            ..StreamUnit::new(1, 2)
+        };
+        assert_eq!(
+            described(running, 5, 20),
+            "unit 1\n\
+             in .map(500ms).buffer(1), future running, 50%\n\
+             \n\
+             0.00s  created\n\
+             0.01s  entered .map(500ms).buffer(1), started 0.02s"
+        );
+
+        let done = StreamUnit {
+            future_state: FutureState::Done,
+            ..StreamUnit::new(1, 2)
+        };
+        let description = described(done, 7, 100);
+        assert!(description.contains("future done\nblocked behind earlier units for 0.04s\n"));
+        assert!(description.ends_with("done 0.06s, blocked 0.04s"));
+    }
+
+    #[test]
+    fn names_the_lane_of_stacked_units() {
+        let unit = StreamUnit::new(LANE_ID_STRIDE + 1, LANE_ID_STRIDE + 3);
+        assert!(described(unit, 9, 70).starts_with("unit 1 of lane 2\n"));
+    }
+
+    #[test]
+    fn future_states_follow_the_values() {
+        assert_eq!(
+            FutureState::of(&UnitValueKind::PendingFuture(Color::RED)),
+            Some(FutureState::Pending)
+        );
+        assert_eq!(
+            FutureState::of(&UnitValueKind::RunningFuture(0.3)),
+            Some(FutureState::Running(0.3))
+        );
+        assert_eq!(
+            FutureState::of(&UnitValueKind::RunningFuture(1.)),
+            Some(FutureState::Done)
+        );
+        assert_eq!(FutureState::of(&UnitValueKind::Value(Color::RED)), None);
+    }
+}
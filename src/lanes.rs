@@ -0,0 +1,125 @@
+use crossbeam_channel::{unbounded, Receiver};
+
+use crate::{
+    pipeline_spec::PipelineSpec,
+    stream_vis::StreamBlock,
+    stream_vis_builder::{StopHandle, StreamPipeline},
+    StreamUpdate,
+};
+
+/// Block and unit ids of lane `i` are offset by `i * LANE_ID_STRIDE`, so a lane's pipeline can
+/// have up to this many units and blocks
+pub const LANE_ID_STRIDE: u32 = 1000;
+
+/// The lane of block `block_id`
+pub fn lane_of(block_id: u32) -> u32 {
+    block_id / LANE_ID_STRIDE
+}
+
+/// The id of unit `id` within its lane, told by the block it's in, as a lone lane's unit ids
+/// can go past the stride
+pub fn id_in_lane(id: u32, block_id: u32) -> u32 {
+    id - lane_of(block_id) * LANE_ID_STRIDE
+}
+
+/// A pipeline to show in a lane, with its caption
+pub struct LaneSpec {
+    pub pipeline: PipelineSpec,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+impl LaneSpec {
+    /// Make sure the ids of a stacked lane stay below `LANE_ID_STRIDE`, out of the next lane's
+    /// range
+    pub fn check_ids(&self) -> Result<(), String> {
+        let stride = LANE_ID_STRIDE as usize;
+        // the source and the sink are blocks too
+        let blocks = self.pipeline.stages.len() + 2;
+        if self.pipeline.source > stride || blocks > stride {
+            return Err(format!(
+                "Pipeline {} has {} units and {} blocks, a stacked lane can have at most {} of each",
+                self.title, self.pipeline.source, blocks, stride
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Several pipelines shown in the same window, stacked from the top
+pub struct Lanes {
+    /// Blocks of every lane, with offset ids
+    pub blocks: Vec<Vec<StreamBlock>>,
+    /// Updates of all the lanes, with offset ids
+    pub rx: Receiver<StreamUpdate>,
+    /// Stop every lane's runtime, dropping them stops them too
+    pub stops: Vec<StopHandle>,
+}
+
+impl Lanes {
+    pub fn merge(pipelines: Vec<StreamPipeline>) -> Self {
+        let (tx, rx) = unbounded();
+        let mut stops = Vec::new();
+
+        let blocks = pipelines
+            .into_iter()
+            .enumerate()
+            .map(|(lane, mut pipeline)| {
+                stops.extend(pipeline.stop.take());
+                let offset = lane as u32 * LANE_ID_STRIDE;
+
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for mut update in pipeline.rx.iter() {
+                        offset_update(&mut update, offset);
+                        if tx.send(update).is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                pipeline
+                    .blocks
+                    .into_iter()
+                    .map(|mut block| {
+                        block.offset_id(offset);
+                        block
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self { blocks, rx, stops }
+    }
+
+    /// Where lane `lane` is placed when lanes are `height` apart, the first one at the origin
+    pub fn lane_y(lane: usize, height: f32) -> f32 {
+        -(lane as f32) * height
+    }
+}
+
+fn offset_update(update: &mut StreamUpdate, offset: u32) {
+    match update {
+        StreamUpdate::Created(created) => {
+            created.id += offset;
+            created.block_id += offset;
+        }
+        StreamUpdate::ChangeValue(change) => change.id += offset,
+        StreamUpdate::AdvanceBlock(advance) => {
+            advance.id += offset;
+            advance.block_id += offset;
+            advance.from_block_id += offset;
+        }
+        StreamUpdate::FilteredOut(filtered) => filtered.id += offset,
+        StreamUpdate::Dropped(dropped) => dropped.id += offset,
+    }
+}
+
+/// Label of a lane, the combinators of its pipeline as they'd be chained in code
+pub fn lane_label(blocks: &[StreamBlock]) -> String {
+    blocks
+        .iter()
+        .filter(|block| !matches!(block, StreamBlock::Source(_) | StreamBlock::Sink(_)))
+        .map(|block| block.name())
+        .collect()
+}
@@ -1,39 +1,120 @@
+mod animated_svg_export;
+mod bench;
 mod future_vis;
+mod gantt_export;
+mod history;
+mod html_export;
+mod ingest;
+mod inspector;
+mod lanes;
+mod pipeline_dsl;
+mod pipeline_spec;
+mod playback;
+mod presets;
+mod recording;
+mod replay;
+mod report;
+mod scenario;
+mod stats_overlay;
 mod stream_vis;
 mod stream_vis_builder;
+mod sweep;
+mod theme;
+mod timeline;
+mod trace_export;
+mod tui;
 
 use argh::FromArgs;
 use bevy_tweening::TweeningPlugin;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, TryRecvError};
 
-use stream_vis::{spawn_blocks, BG_COLOR, SECTION_HEIGHT};
-use stream_vis_builder::{JitteringDuration, StreamVisBuilder};
+use history::{
+    advance_history, restore_history, spawn_timeline, timeline_controls, update_timeline, History,
+};
+use inspector::{
+    draw_unit_trails, pick_units, spawn_inspector_panel, update_inspector_panel, Inspector,
+};
+use pipeline_spec::Parameter;
+use playback::{
+    playback_controls, spawn_playback_status, tick_pipeline_clock, update_playback_status,
+    PipelineClock, Playback,
+};
+use presets::{COMPARED_PRESETS, DEFAULT_PRESET};
+use scenario::{ExportFormat, Scenario, ScenarioPipeline};
+use stream_vis::{layout_blocks, spawn_blocks, StreamBlock, FONT_SIZE};
+use theme::theme;
 
-use crate::stream_vis::{advance_units, create_units, handle_filtered_out, update_units};
+use crate::future_vis::{add_unit_meshes, StreamUnit, UnitLabels, UnitShape};
+use crate::lanes::{lane_label, LaneSpec, Lanes};
+use crate::recording::{EventLog, RecordedEvent};
+use crate::stats_overlay::{
+    collect_stats, spawn_stats_overlay, toggle_stats_overlay, update_stats_overlay, PipelineStats,
+    StatsOverlay,
+};
+use crate::stream_vis::{advance_units, create_units, handle_filtered_out, update_units, Divider};
+use crate::stream_vis_builder::{StopHandle, StreamPipeline};
 use bevy::{
     prelude::*,
     render::view::screenshot::ScreenshotManager,
-    sprite::MaterialMesh2dBundle,
+    sprite::{Anchor, MaterialMesh2dBundle},
+    text::Text2dBounds,
     window::{PrimaryWindow, WindowCloseRequested},
 };
 use std::{
-    env,
-    path::Path,
+    env, fs,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
 
-#[derive(Component)]
-struct MapBlock;
-
 #[derive(Resource, Deref)]
 struct StreamReceiver(Receiver<StreamUpdate>);
 
+/// Blocks of every pipeline shown, a lane each
+#[derive(Resource, Deref)]
+struct PipelineBlocks(Vec<Vec<StreamBlock>>);
+
+/// Title and description shown above each lane, empty when there are no captions
+#[derive(Resource, Deref)]
+struct LaneCaptions(Vec<(String, Option<String>)>);
+
+impl LaneCaptions {
+    fn new(config: &Config, captions: Vec<(String, Option<String>)>) -> Self {
+        // lanes need telling apart, a single pipeline only gets a caption when asked to
+        if captions.len() == 1 && !config.caption {
+            Self(Vec::new())
+        } else {
+            Self(captions)
+        }
+    }
+}
+
+/// Stop the runtimes of the pipelines shown, they're stopped once dropped too
+#[derive(Resource)]
+struct PipelineStops(Vec<StopHandle>);
+
+#[derive(Component)]
+struct LaneCaption;
+
+#[derive(Component)]
+struct Background;
+
+/// When every stream ended, to close the window once the scenario's tail has passed
+#[derive(Resource, Default)]
+struct StreamEnd(Option<Duration>);
+
 #[derive(Clone, Debug)]
 pub enum UnitValueKind {
     PendingFuture(Color),
     RunningFuture(f32),
+    /// The unit's fill, once its future is done
     Value(Color),
+    Shape(UnitShape),
+    /// Payload shown on the unit in place of its id
+    Label(String),
+    /// The unit's future failed, it goes straight to the sink
+    Error,
 }
 
 #[derive(Clone, Debug)]
@@ -53,6 +134,13 @@ pub struct UnitValueUpdateEvent {
 pub struct FilteredOutEvent {
     pub id: u32,
 }
+
+/// A unit still on its way when the stream was dropped, after an error reached the sink
+#[derive(Clone, Debug)]
+pub struct DroppedEvent {
+    pub id: u32,
+}
+
 #[derive(Clone, Debug)]
 pub struct UnitAdvanceBlockEvent {
     pub id: u32,
@@ -66,23 +154,149 @@ pub enum StreamUpdate {
     ChangeValue(UnitValueUpdateEvent),
     AdvanceBlock(UnitAdvanceBlockEvent),
     FilteredOut(FilteredOutEvent),
+    Dropped(DroppedEvent),
 }
 
 #[derive(Clone, Event, Debug)]
 pub struct StreamEvent(pub StreamUpdate);
 
 #[derive(Clone)]
-pub struct StreamedUnit {
+pub struct StreamedUnit<T> {
     pub id: u32,
     pub block_id: u32,
+    pub value: T,
 }
 
-#[derive(Debug, FromArgs, Resource)]
+#[derive(Clone, Debug, FromArgs, Resource)]
 /// stream vis config
 struct Config {
+    #[argh(subcommand)]
+    command: Option<Subcommand>,
+
     /// whether or not to jump
     #[argh(positional)]
     output_filename: Option<String>,
+
+    /// visualize a pipeline streamed by another process, listening on a host:port or unix:<socket path>
+    #[argh(option)]
+    listen: Option<String>,
+
+    /// write the run as a Chrome trace (Perfetto / chrome://tracing) json file on exit
+    #[argh(option)]
+    trace_out: Option<String>,
+
+    /// write a gantt chart svg of every unit's time in each stage on exit
+    #[argh(option)]
+    gantt_out: Option<String>,
+
+    /// write a self-contained animated svg of the run on exit
+    #[argh(option)]
+    svg_out: Option<String>,
+
+    /// write an html player of the run, with scrubbing and a unit inspector, on exit
+    #[argh(option)]
+    html_out: Option<String>,
+
+    /// write per-unit timings to <report>.csv and a summary of the run to <report>.json on exit
+    #[argh(option)]
+    report: Option<String>,
+
+    /// show live statistics next to the animation, toggled with the S key
+    #[argh(switch)]
+    stats: bool,
+
+    /// pipeline to show, see --list-presets. Repeat to stack several in one window
+    #[argh(option)]
+    preset: Vec<String>,
+
+    /// pipeline to show, written like "source(10) | buffered(500ms~3, 5) | filter(1200ms~1, 0.5)"
+    /// or a path to a .svis file holding one. Repeat to stack several, below the presets
+    #[argh(option)]
+    pipeline: Vec<String>,
+
+    /// list the presets with their descriptions
+    #[argh(switch)]
+    list_presets: bool,
+
+    /// show the preset's title and description above the pipeline
+    #[argh(switch)]
+    caption: bool,
+
+    /// show each unit's id, or the payload it's labelled with, on it
+    #[argh(switch)]
+    unit_labels: bool,
+
+    /// stack the buffer-filter and buffer-unordered-filter presets, sharing their randomness
+    #[argh(switch)]
+    compare: bool,
+
+    /// seed of the sampled future durations and filter outcomes, random by default
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// load the pipelines, seed, window size, theme, exports, tail, captions and unit labels from
+    /// a .ron scenario file, the other options take precedence over it
+    #[argh(option)]
+    scenario: Option<String>,
+
+    /// render the pipeline in the terminal instead of a window
+    #[argh(switch)]
+    tui: bool,
+
+    /// print a plain-text frame of the pipeline every N milliseconds instead of opening a window
+    #[argh(option)]
+    dump: Option<u64>,
+}
+
+#[derive(Clone, Debug, FromArgs)]
+#[argh(subcommand)]
+enum Subcommand {
+    Bench(BenchArgs),
+    Sweep(SweepArgs),
+}
+
+#[derive(Clone, Debug, FromArgs)]
+#[argh(subcommand, name = "bench")]
+/// run the pipeline many times in virtual time, without a window, and print confidence
+/// intervals of its runtime, throughput and latency
+struct BenchArgs {
+    /// number of runs, 100 by default
+    #[argh(option, default = "100")]
+    runs: usize,
+}
+
+#[derive(Clone, Debug, FromArgs)]
+#[argh(subcommand, name = "sweep")]
+/// run the pipeline in virtual time for every value of one of its parameters, then write the
+/// metrics to <out>.csv and a chart of them to <out>.svg
+struct SweepArgs {
+    /// parameter to vary: source, buffered, duration (ms), jitter, filter_ratio or failure_ratio
+    #[argh(option)]
+    parameter: Parameter,
+
+    /// 1-based stage whose parameter is varied, the first stage having it by default
+    #[argh(option)]
+    stage: Option<usize>,
+
+    /// first value
+    #[argh(option)]
+    from: f32,
+
+    /// last value
+    #[argh(option)]
+    to: f32,
+
+    /// distance between values, 1 by default
+    #[argh(option, default = "1.")]
+    step: f32,
+
+    /// number of runs per value, 50 by default
+    #[argh(option, default = "50")]
+    runs: usize,
+
+    /// output path, without extension, "sweep" by default
+    #[argh(option, default = "String::from(\"sweep\")")]
+    out: String,
 }
 
 #[derive(Resource)]
@@ -94,21 +308,153 @@ struct ScreenshotStorage {
 #[tokio::main]
 async fn main() {
     let _ = env_logger::builder().format_timestamp_millis().try_init();
-    let config: Config = argh::from_env();
+    let mut config: Config = argh::from_env();
+
+    if config.list_presets {
+        print!("{}", presets::list());
+        return;
+    }
+
+    let command_line = config.clone();
+    let (scenario, selected) = load_lanes(&mut config).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    match &config.command {
+        Some(Subcommand::Bench(bench)) => {
+            let spec = &selected[0].pipeline;
+            bench::run(|| spec.build(), bench.runs);
+            return;
+        }
+        Some(Subcommand::Sweep(sweep)) => {
+            let path = env::current_dir().unwrap().join(&sweep.out);
+            let ran = sweep::values(sweep.from, sweep.to, sweep.step).and_then(|values| {
+                sweep::run(
+                    &selected[0].pipeline,
+                    sweep.parameter,
+                    sweep.stage,
+                    &values,
+                    sweep.runs,
+                    &path,
+                )
+                .map_err(|e| e.to_string())
+            });
+            match ran {
+                Ok(_) => println!("sweep saved to {}", path.with_extension("svg").display()),
+                // no window, so no bevy log output either
+                Err(e) => {
+                    eprintln!("Cannot run sweep: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        None => (),
+    }
+
+    let seed = config.seed.unwrap_or_else(rand::random);
+    debug!("seed {seed}");
+
+    // the terminal has no animation to keep in step with, its pipeline runs in real time
+    let terminal = config.tui || config.dump.is_some();
+    let mut clock = PipelineClock::default();
+
+    let (mut pipelines, captions) = match &config.listen {
+        Some(addr) => {
+            let pipeline = ingest::listen(addr).unwrap_or_else(|e| {
+                eprintln!("Cannot receive a pipeline on {addr}: {e}");
+                std::process::exit(1);
+            });
+            let caption = (lanes::lane_label(&pipeline.blocks), None);
+            (vec![pipeline], vec![caption])
+        }
+        None => start_pipelines(&config, selected, seed, (!terminal).then_some(&mut clock)),
+    };
+    let captions = LaneCaptions::new(&config, captions);
+
+    // the terminal shows a single pipeline, the first one
+    if terminal {
+        let pipeline = pipelines.remove(0);
+        let blocks = pipeline.blocks.clone();
+        match config.dump {
+            Some(every) => tui::dump(pipeline.blocks, pipeline.rx, Duration::from_millis(every)),
+            None => tui::run(pipeline.blocks, pipeline.rx)
+                .expect("failed running the terminal renderer"),
+        }
+        write_exports(&config, &blocks, &pipeline.log.events());
+        return;
+    }
+
+    // exports cover the first pipeline, its ids aren't offset
+    let log = pipelines[0].log.clone();
+    let lanes = Lanes::merge(pipelines);
+    let hot_reload = HotReload::new(command_line, seed, &scenario);
 
     App::new()
         .add_event::<StreamEvent>()
         .add_plugins(DefaultPlugins)
         .add_plugins(TweeningPlugin)
-        .add_systems(Startup, setup)
-        .add_systems(PreUpdate, read_stream)
+        .add_systems(Startup, (add_unit_meshes, setup))
+        .add_systems(
+            First,
+            (
+                reload_on_change,
+                apply_deferred,
+                spawn_lanes.run_if(resource_changed::<PipelineBlocks>()),
+            )
+                .chain(),
+        )
+        .add_systems(PreUpdate, read_stream.after(tick_pipeline_clock))
+        .add_systems(
+            PreUpdate,
+            (
+                playback_controls,
+                timeline_controls,
+                restore_history,
+                advance_history,
+                tick_pipeline_clock,
+            )
+                .chain(),
+        )
+        .add_systems(Startup, spawn_playback_status)
+        .add_systems(Update, update_playback_status)
+        .add_systems(Startup, spawn_timeline)
+        .add_systems(Update, update_timeline)
+        .add_systems(Startup, spawn_inspector_panel)
+        .add_systems(
+            Update,
+            (pick_units, update_inspector_panel, draw_unit_trails).chain(),
+        )
+        .add_systems(Update, close_after_tail)
         .add_systems(PreUpdate, create_units.after(read_stream))
         .add_systems(FixedUpdate, advance_units.after(create_units))
         .add_systems(FixedUpdate, update_units.after(advance_units))
         .add_systems(FixedUpdate, handle_filtered_out.after(advance_units))
         .add_systems(FixedUpdate, save_frame)
         .add_systems(Update, save_gif)
+        .add_systems(Update, save_exports)
+        .add_systems(Startup, spawn_stats_overlay)
+        .add_systems(
+            Update,
+            (collect_stats, toggle_stats_overlay, update_stats_overlay).chain(),
+        )
+        .insert_resource(StatsOverlay {
+            visible: config.stats,
+        })
+        .init_resource::<PipelineStats>()
+        .init_resource::<StreamEnd>()
+        .init_resource::<Playback>()
+        .init_resource::<Inspector>()
+        .insert_resource(clock)
+        .insert_resource(scenario)
         .insert_resource(config)
+        .insert_resource(PipelineBlocks(lanes.blocks))
+        .insert_resource(captions)
+        .insert_resource(StreamReceiver(lanes.rx))
+        .insert_resource(PipelineStops(lanes.stops))
+        .insert_resource(hot_reload)
+        .insert_resource(log)
         .insert_resource(ScreenshotStorage {
             started_writing: false,
             frames: Default::default(),
@@ -116,88 +462,455 @@ async fn main() {
         .run();
 }
 
-fn setup(
+fn setup(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+/// Spawn the lanes' blocks, on startup and whenever the pipelines are reloaded, center the
+/// camera on them and start their history. Units spawned from then on are labelled if asked to
+#[allow(clippy::too_many_arguments)]
+fn spawn_lanes(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
     mut window: Query<&mut Window>,
+    mut camera: Query<&mut Transform, With<Camera>>,
+    blocks: Res<PipelineBlocks>,
+    captions: Res<LaneCaptions>,
+    scenario: Res<Scenario>,
+    config: Res<Config>,
 ) {
+    let size = scenario.window;
     let mut window = window.single_mut();
-    window.resolution.set(800., SECTION_HEIGHT + 50.);
-
-    commands.spawn(MaterialMesh2dBundle {
-        mesh: meshes
-            .add(
-                shape::Box::from_corners(
-                    Vec3::new(-1000., -1000., 0.),
-                    Vec3::new(1000., 1000., 0.),
+    window
+        .resolution
+        .set(size.width, size.height * blocks.len() as f32);
+
+    commands.spawn((
+        Background,
+        MaterialMesh2dBundle {
+            mesh: meshes
+                .add(
+                    shape::Box::from_corners(
+                        Vec3::new(-1000., -1000. * blocks.len() as f32, 0.),
+                        Vec3::new(1000., 1000., 0.),
+                    )
+                    .into(),
                 )
                 .into(),
-            )
-            .into(),
-        transform: Transform::from_translation(Vec3::new(0., 0., -200.)),
-        material: materials.add(ColorMaterial::from(BG_COLOR)),
-        ..default()
-    });
+            transform: Transform::from_translation(Vec3::new(0., 0., -200.)),
+            material: materials.add(ColorMaterial::from(theme().background)),
+            ..default()
+        },
+    ));
+
+    let mut end: f32 = 0.;
+    let mut placed = Vec::new();
+    for (lane, lane_blocks) in blocks.iter().enumerate() {
+        let origin = Vec3::new(0., Lanes::lane_y(lane, size.height), 0.);
+        placed.extend(
+            layout_blocks(lane_blocks.clone())
+                .blocks
+                .into_iter()
+                .map(|(block, transform)| (block, transform.translation + origin)),
+        );
+        end = end.max(spawn_blocks(
+            lane_blocks.clone(),
+            origin,
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &asset_server,
+        ));
+    }
 
-    // buffer 1
-    // let (blocks, rx) = StreamVisBuilder::source(3)
-    //     .map_buffered(JitteringDuration::from_millis(500, 3.), 1)
-    //     .sink();
-
-    // buffer 5
-    // let (blocks, rx) = StreamVisBuilder::source(15)
-    //     .map_buffered(JitteringDuration::from_millis(800, 4.), 5)
-    //     .sink();
-
-    // buffer unordered 5
-    // let (blocks, rx) = StreamVisBuilder::source(15)
-    //     .map_buffer_unordered(JitteringDuration::from_millis(500, 3.), 5)
-    //     .sink();
-
-    // filter
-    // let (blocks, rx) = StreamVisBuilder::source(3)
-    //     .filter(JitteringDuration::from_millis(500, 1.), 0.5)
-    //     .sink();
-
-    // buffer filter long
-    let (blocks, rx) = StreamVisBuilder::source(10)
-        .map_buffered(JitteringDuration::from_millis(500, 3.), 5)
-        .filter(JitteringDuration::from_millis(1200, 1.), 0.5)
-        .sink();
-
-    // buffer unordered filter long
-    // let (blocks, rx) = StreamVisBuilder::source(10)
-    //     .map_buffer_unordered(JitteringDuration::from_millis(500, 3.), 5)
-    //     .filter(JitteringDuration::from_millis(1200, 1.), 0.5)
-    //     .sink();
-
-    // let (blocks, rx) = StreamVisBuilder::source(10)
-    //     .map_buffered(JitteringDuration::from_millis(500, 3.), 5)
-    //     .map_buffered(JitteringDuration::from_millis(1000, 2.), 3)
-    //     .sink();
-
-    let end = spawn_blocks(
-        blocks,
-        &mut commands,
-        &mut meshes,
-        &mut materials,
-        asset_server,
+    let center = Vec3::new(
+        end / 2.,
+        Lanes::lane_y(blocks.len() - 1, size.height) / 2.,
+        0.,
     );
 
-    commands.spawn(Camera2dBundle {
-        transform: Transform::from_translation(Vec3::new(end / 2., 0., 0.)),
-        ..Default::default()
-    });
+    for (lane, (title, description)) in captions.iter().enumerate() {
+        let style = TextStyle {
+            font: asset_server.load("Virgil.ttf"),
+            font_size: FONT_SIZE,
+            color: theme().text,
+        };
+
+        let mut sections = vec![TextSection::new(title.clone(), style.clone())];
+        if let Some(description) = description {
+            sections.push(TextSection::new(
+                format!("\n{description}"),
+                TextStyle {
+                    font_size: FONT_SIZE * 0.75,
+                    color: theme().text.with_a(0.7),
+                    ..style
+                },
+            ));
+        }
+
+        commands.spawn((
+            LaneCaption,
+            Text2dBundle {
+                text_anchor: Anchor::TopLeft,
+                text: Text::from_sections(sections),
+                text_2d_bounds: Text2dBounds {
+                    size: Vec2::new(size.width - 20., f32::INFINITY),
+                },
+                transform: Transform::from_translation(Vec3::new(
+                    center.x - size.width / 2. + 10.,
+                    Lanes::lane_y(lane, size.height) + size.height / 2. - 10.,
+                    200.,
+                )),
+                ..default()
+            },
+        ));
+    }
+
+    camera.single_mut().translation = center;
+    commands.insert_resource(History::new(placed));
+
+    if config.unit_labels {
+        commands.insert_resource(UnitLabels {
+            font: asset_server.load("Virgil.ttf"),
+        });
+    } else {
+        commands.remove_resource::<UnitLabels>();
+    }
+}
+
+/// Start the lanes' pipelines, with their captions. Their time passes as `clock` ticks, or in
+/// real time without one
+fn start_pipelines(
+    config: &Config,
+    lanes: Vec<LaneSpec>,
+    seed: u64,
+    mut clock: Option<&mut PipelineClock>,
+) -> (Vec<StreamPipeline>, Vec<(String, Option<String>)>) {
+    lanes
+        .into_iter()
+        .map(|lane| {
+            let builder = lane.pipeline.build_seeded(seed);
+            let pipeline = match clock.as_deref_mut() {
+                Some(clock) => builder.sink_with_clock(clock.subscribe()),
+                None => builder.sink(),
+            };
+            let description = lane.description.filter(|_| config.caption);
+            (pipeline, (lane.title, description))
+        })
+        .unzip()
+}
+
+/// Load the scenario, fill in `config` from it and select the pipelines to show
+fn load_lanes(config: &mut Config) -> Result<(Scenario, Vec<LaneSpec>), String> {
+    let scenario = match &config.scenario {
+        Some(path) => {
+            scenario::load(Path::new(path)).map_err(|e| format!("Invalid scenario {path}: {e}"))?
+        }
+        None => Scenario::default(),
+    };
+    apply_scenario(config, &scenario);
+    let lanes = selected_pipelines(config, &scenario)?;
+
+    theme::set(scenario.theme.clone());
+    Ok((scenario, lanes))
+}
+
+/// Fill in the options left unset on the command line from the scenario
+fn apply_scenario(config: &mut Config, scenario: &Scenario) {
+    config.seed = config.seed.or(scenario.seed);
+    config.caption |= scenario.captions;
+    config.unit_labels |= scenario.unit_labels;
+
+    for export in &scenario.exports {
+        let option = match export.format {
+            ExportFormat::Gif => &mut config.output_filename,
+            ExportFormat::Trace => &mut config.trace_out,
+            ExportFormat::Gantt => &mut config.gantt_out,
+            ExportFormat::Svg => &mut config.svg_out,
+            ExportFormat::Html => &mut config.html_out,
+            ExportFormat::Report => &mut config.report,
+        };
+        option.get_or_insert_with(|| export.path.clone());
+    }
+}
+
+/// Pipelines picked by `--preset`, `--compare` and `--pipeline`, else the scenario's, the
+/// default preset when there are none
+fn selected_pipelines(config: &Config, scenario: &Scenario) -> Result<Vec<LaneSpec>, String> {
+    let from_command_line =
+        config.compare || !config.preset.is_empty() || !config.pipeline.is_empty();
+    let lanes = if !from_command_line && !scenario.pipelines.is_empty() {
+        scenario
+            .pipelines
+            .iter()
+            .map(scenario_lane)
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let preset_names = if config.compare {
+            COMPARED_PRESETS.to_vec()
+        } else if !from_command_line {
+            vec![DEFAULT_PRESET]
+        } else {
+            config.preset.iter().map(String::as_str).collect()
+        };
+
+        preset_names
+            .into_iter()
+            .map(preset_lane)
+            .chain(
+                config
+                    .pipeline
+                    .iter()
+                    .map(|pipeline| pipeline_lane(pipeline)),
+            )
+            .collect::<Result<Vec<_>, _>>()?
+    };
 
-    commands.insert_resource(StreamReceiver(rx));
+    // a lone lane isn't offset, so only stacked lanes have to stay within the stride
+    if lanes.len() > 1 {
+        for lane in &lanes {
+            lane.check_ids()?;
+        }
+    }
+    Ok(lanes)
+}
+
+fn preset_lane(name: &str) -> Result<LaneSpec, String> {
+    let preset =
+        presets::find(name).ok_or_else(|| format!("Unknown preset {name}, see --list-presets"))?;
+
+    Ok(LaneSpec {
+        pipeline: (preset.pipeline)(),
+        title: preset.title.to_string(),
+        description: Some(preset.description.to_string()),
+    })
+}
+
+/// A pipeline written in the pipeline language, or the path to a .svis file holding one
+fn pipeline_lane(pipeline: &str) -> Result<LaneSpec, String> {
+    let (origin, text) = if is_pipeline_file(pipeline) {
+        let text =
+            fs::read_to_string(pipeline).map_err(|e| format!("Cannot read {pipeline}: {e}"))?;
+        (format!("{pipeline}:"), text)
+    } else {
+        (String::new(), pipeline.to_string())
+    };
+
+    let spec = pipeline_dsl::parse(&text).map_err(|e| format!("Invalid pipeline {origin}{e}"))?;
+
+    Ok(LaneSpec {
+        title: lane_label(spec.build().blocks()),
+        pipeline: spec,
+        description: None,
+    })
+}
+
+fn is_pipeline_file(pipeline: &str) -> bool {
+    pipeline.ends_with(&format!(".{}", pipeline_dsl::FILE_EXTENSION))
+}
+
+/// A scenario's pipeline, its title and description replacing the preset's
+fn scenario_lane(pipeline: &ScenarioPipeline) -> Result<LaneSpec, String> {
+    let lane = match (&pipeline.preset, &pipeline.pipeline) {
+        (Some(name), None) => preset_lane(name)?,
+        (None, Some(text)) => pipeline_lane(text)?,
+        _ => {
+            return Err(
+                "Invalid scenario, every pipeline needs either a preset or a pipeline".to_string(),
+            )
+        }
+    };
+
+    Ok(LaneSpec {
+        title: pipeline.title.clone().unwrap_or(lane.title),
+        description: pipeline.description.clone().or(lane.description),
+        pipeline: lane.pipeline,
+    })
+}
+
+/// Scenario and pipeline files the animation restarts from when one of them changes
+#[derive(Resource)]
+struct HotReload {
+    /// The options as given on the command line, before the scenario filled them in
+    command_line: Config,
+    /// Kept across reloads unless the command line or the scenario sets one
+    seed: u64,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    timer: Timer,
+}
+
+impl HotReload {
+    fn new(command_line: Config, seed: u64, scenario: &Scenario) -> Self {
+        let mut hot_reload = Self {
+            command_line,
+            seed,
+            files: Vec::new(),
+            timer: Timer::new(RELOAD_POLL_INTERVAL, TimerMode::Repeating),
+        };
+        hot_reload.watch(scenario);
+        hot_reload
+    }
+
+    /// Watch the scenario and the pipeline files it and the command line refer to
+    fn watch(&mut self, scenario: &Scenario) {
+        // a pipeline streamed by another process has no file to reload from
+        if self.command_line.listen.is_some() {
+            self.files.clear();
+            return;
+        }
+
+        let pipeline_files = self
+            .command_line
+            .pipeline
+            .iter()
+            .chain(
+                scenario
+                    .pipelines
+                    .iter()
+                    .filter_map(|p| p.pipeline.as_ref()),
+            )
+            .filter(|pipeline| is_pipeline_file(pipeline));
+
+        self.files = self
+            .command_line
+            .scenario
+            .iter()
+            .chain(pipeline_files)
+            .map(|path| (PathBuf::from(path), modified(Path::new(path))))
+            .collect();
+    }
+
+    /// Whether a watched file changed since the last call
+    fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Everything drawn for the lanes, despawned on reload
+type LanesScene = Or<(
+    With<StreamBlock>,
+    With<StreamUnit>,
+    With<Divider>,
+    With<LaneCaption>,
+    With<Background>,
+)>;
+
+/// Restart the animation from scratch when a watched file changes, keeping the current one when
+/// the new files are invalid
+fn reload_on_change(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut hot_reload: ResMut<HotReload>,
+    mut stops: ResMut<PipelineStops>,
+    scene: Query<Entity, LanesScene>,
+    mut events: ResMut<Events<StreamEvent>>,
+    screenshot_storage: Res<ScreenshotStorage>,
+) {
+    if !hot_reload.timer.tick(time.delta()).just_finished() || !hot_reload.changed() {
+        return;
+    }
+
+    let mut config = hot_reload.command_line.clone();
+    let (scenario, selected) = match load_lanes(&mut config) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Cannot reload: {e}");
+            return;
+        }
+    };
+    hot_reload.watch(&scenario);
+    let seed = config.seed.unwrap_or(hot_reload.seed);
+
+    for stop in stops.0.drain(..) {
+        stop.stop();
+    }
+    for entity in &scene {
+        commands.entity(entity).despawn_recursive();
+    }
+    // updates of the stopped pipelines would point at despawned units
+    events.clear();
+    screenshot_storage.frames.lock().unwrap().clear();
+
+    let mut clock = PipelineClock::default();
+    let (pipelines, captions) = start_pipelines(&config, selected, seed, Some(&mut clock));
+    let log = pipelines[0].log.clone();
+    let lanes = Lanes::merge(pipelines);
+
+    commands.insert_resource(PipelineBlocks(lanes.blocks));
+    commands.insert_resource(LaneCaptions::new(&config, captions));
+    commands.insert_resource(StreamReceiver(lanes.rx));
+    commands.insert_resource(PipelineStops(lanes.stops));
+    commands.insert_resource(clock);
+    commands.insert_resource(log);
+    commands.insert_resource(PipelineStats::default());
+    commands.insert_resource(StreamEnd::default());
+    commands.insert_resource(scenario);
+    commands.insert_resource(config);
+    info!("reloaded the pipelines");
 }
 
 // This system reads from the receiver and sends events to Bevy
-fn read_stream(receiver: Res<StreamReceiver>, mut events: EventWriter<StreamEvent>) {
-    for from_stream in receiver.try_iter() {
-        events.send(StreamEvent(from_stream));
+fn read_stream(
+    receiver: Res<StreamReceiver>,
+    mut events: EventWriter<StreamEvent>,
+    mut end: ResMut<StreamEnd>,
+    mut history: ResMut<History>,
+    time: Res<Time>,
+) {
+    // updates wait in the channel while the history is replayed
+    if !history.is_live() {
+        return;
+    }
+
+    loop {
+        match receiver.try_recv() {
+            Ok(from_stream) => {
+                history.record(&from_stream);
+                events.send(StreamEvent(from_stream));
+            }
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                end.0.get_or_insert(time.elapsed());
+                break;
+            }
+        }
+    }
+}
+
+fn close_after_tail(
+    end: Res<StreamEnd>,
+    scenario: Res<Scenario>,
+    time: Res<Time>,
+    windows: Query<Entity, With<PrimaryWindow>>,
+    mut close: EventWriter<WindowCloseRequested>,
+    mut closing: Local<bool>,
+) {
+    let (Some(end), Some(tail)) = (end.0, scenario.tail) else {
+        return;
+    };
+    if *closing || time.elapsed() < end + Duration::from_secs_f32(tail) {
+        return;
+    }
+
+    *closing = true;
+    for window in &windows {
+        close.send(WindowCloseRequested { window });
     }
 }
 
@@ -233,7 +946,7 @@ fn save_gif(
         screenshot_storage.started_writing = true;
 
         let current_dir = env::current_dir().unwrap();
-        let output_file = current_dir.join(&output_filename);
+        let output_file = current_dir.join(output_filename);
         _ = std::fs::remove_file(&output_file);
 
         let screenshot_dir = tempfile::tempdir().unwrap();
@@ -248,7 +961,7 @@ fn save_gif(
         }
 
         Command::new("ffmpeg")
-            .args(&[
+            .args([
                 "-y",
                 "-i",
                 "screenshot-%09d.png",
@@ -263,7 +976,7 @@ fn save_gif(
             .unwrap();
 
         Command::new("ffmpeg")
-            .args(&[
+            .args([
                 "-i",
                 "screenshot-%09d.png",
                 "-i",
@@ -282,9 +995,64 @@ fn save_gif(
     }
 }
 
+fn save_exports(
+    mut reader: EventReader<WindowCloseRequested>,
+    config: Res<Config>,
+    blocks: Res<PipelineBlocks>,
+    log: Res<EventLog>,
+) {
+    for _ in reader.read().take(1) {
+        write_exports(&config, &blocks[0], &log.events());
+    }
+}
+
+fn write_exports(config: &Config, blocks: &[StreamBlock], events: &[RecordedEvent]) {
+    let current_dir = env::current_dir().unwrap();
+
+    if let Some(trace_out) = &config.trace_out {
+        let path = current_dir.join(trace_out);
+        match trace_export::write_chrome_trace(&path, blocks, events) {
+            Ok(_) => debug!("trace saved to {}", path.display()),
+            Err(e) => error!("Cannot save trace: {e}"),
+        }
+    }
+
+    if let Some(gantt_out) = &config.gantt_out {
+        let path = current_dir.join(gantt_out);
+        match gantt_export::write_gantt_svg(&path, blocks, events) {
+            Ok(_) => debug!("gantt chart saved to {}", path.display()),
+            Err(e) => error!("Cannot save gantt chart: {e}"),
+        }
+    }
+
+    if let Some(svg_out) = &config.svg_out {
+        let path = current_dir.join(svg_out);
+        match animated_svg_export::write_animated_svg(&path, blocks, events) {
+            Ok(_) => debug!("animated svg saved to {}", path.display()),
+            Err(e) => error!("Cannot save animated svg: {e}"),
+        }
+    }
+
+    if let Some(html_out) = &config.html_out {
+        let path = current_dir.join(html_out);
+        match html_export::write_html_player(&path, blocks, events) {
+            Ok(_) => debug!("html player saved to {}", path.display()),
+            Err(e) => error!("Cannot save html player: {e}"),
+        }
+    }
+
+    if let Some(report) = &config.report {
+        let path = current_dir.join(report);
+        match report::write_report(&path, blocks, events) {
+            Ok(_) => debug!("report saved to {}", path.display()),
+            Err(e) => error!("Cannot save report: {e}"),
+        }
+    }
+}
+
 fn save_screenshot_to_disk(img: &Image, path: &Path) {
     match img.clone().try_into_dynamic() {
-        Ok(dyn_img) => match image::ImageFormat::from_path(&path) {
+        Ok(dyn_img) => match image::ImageFormat::from_path(path) {
             Ok(format) => {
                 // discard the alpha channel which stores brightness values when HDR is enabled to make sure
                 // the screenshot looks right
@@ -299,3 +1067,97 @@ fn save_screenshot_to_disk(img: &Image, path: &Path) {
         Err(e) => error!("Cannot save screenshot, screen format cannot be understood: {e}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::scenario::ScenarioPipeline;
+
+    fn config(args: &[&str]) -> Config {
+        Config::from_args(&["rust-stream-vis"], args).unwrap()
+    }
+
+    fn touch(path: &Path, secs: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn single_pipelines_are_captioned_on_request() {
+        let caption = || vec![("buffered(1)".to_string(), None)];
+        assert!(LaneCaptions::new(&config(&[]), caption()).is_empty());
+        assert_eq!(
+            LaneCaptions::new(&config(&["--caption"]), caption()).len(),
+            1
+        );
+
+        let captions = [caption(), caption()].concat();
+        assert_eq!(LaneCaptions::new(&config(&[]), captions).len(), 2);
+    }
+
+    #[test]
+    fn watches_the_scenario_and_its_pipeline_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name| dir.path().join(name).to_str().unwrap().to_string();
+        for name in ["scenario.ron", "a.svis", "b.svis"] {
+            fs::write(path(name), "").unwrap();
+        }
+
+        let command_line = config(&[
+            "--scenario",
+            &path("scenario.ron"),
+            "--pipeline",
+            &path("b.svis"),
+            "--pipeline",
+            "source(3) | buffered(100ms, 1)",
+        ]);
+        let scenario = Scenario {
+            pipelines: vec![ScenarioPipeline {
+                preset: None,
+                pipeline: Some(path("a.svis")),
+                title: None,
+                description: None,
+            }],
+            ..default()
+        };
+        let mut hot_reload = HotReload::new(command_line, 7, &scenario);
+
+        let watched = hot_reload
+            .files
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(watched, ["scenario.ron", "b.svis", "a.svis"]);
+
+        assert!(!hot_reload.changed());
+        touch(Path::new(&path("a.svis")), 1);
+        assert!(hot_reload.changed());
+        assert!(!hot_reload.changed());
+        fs::remove_file(path("scenario.ron")).unwrap();
+        assert!(hot_reload.changed());
+    }
+
+    #[test]
+    fn only_stacked_lanes_are_bounded_by_the_stride() {
+        let large = "source(5000) | buffered(100ms, 1)";
+        let lanes = selected_pipelines(&config(&["--pipeline", large]), &Scenario::default());
+        assert_eq!(lanes.unwrap()[0].pipeline.source, 5000);
+
+        let stacked = config(&["--pipeline", large, "--pipeline", "source(3)"]);
+        let lanes = selected_pipelines(&stacked, &Scenario::default());
+        assert!(lanes.is_err_and(|e| e.contains("at most 1000 of each")));
+    }
+
+    #[test]
+    fn streamed_pipelines_have_no_files() {
+        let command_line = config(&["--listen", "127.0.0.1:0", "--pipeline", "a.svis"]);
+        let hot_reload = HotReload::new(command_line, 7, &Scenario::default());
+        assert!(hot_reload.files.is_empty());
+    }
+}
@@ -0,0 +1,573 @@
+//! A small language describing pipelines without recompiling, e.g.
+//!
+//! ```text
+//! source(10) | buffered(500ms~3, 5) | filter(1200ms~1, 0.5) | sink
+//! ```
+//!
+//! Stages are separated by `|`, the first one is always `source(units)` and a trailing `sink`
+//! is optional. Durations are written in `ms` or `s`, `~3` adds up to three times the duration
+//! of jitter. `#` starts a comment running to the end of the line.
+//!
+//! ```text
+//! source(units)                            StreamVisBuilder::source
+//! buffered(duration, buffered)             map_buffered
+//! buffer_unordered(duration, buffered)     map_buffer_unordered
+//! filter(duration, filter_ratio)           filter
+//! filter_every(duration, n)                filter_every_nth
+//! try_buffered(duration, buffered, fail)   try_buffered
+//! try_filter(duration, filter_ratio)       try_filter
+//! and_then(duration, fail)                 and_then
+//! try_for_each(duration, fail)             try_for_each, followed by the sink at most
+//! sink                                     sink
+//! ```
+
+use std::{fmt, time::Duration};
+
+use crate::{
+    pipeline_spec::{PipelineSpec, StageSpec},
+    stream_vis_builder::JitteringDuration,
+};
+
+/// Extension of files holding a pipeline
+pub const FILE_EXTENSION: &str = "svis";
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// The line the error is on, to point at the column
+    source_line: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        writeln!(f, "  {}", self.source_line)?;
+        write!(f, "  {}^", " ".repeat(self.column - 1))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    /// A number with the unit written right after it, if any
+    Number(f64, Option<String>),
+    Tilde,
+    Pipe,
+    Comma,
+    OpenParen,
+    CloseParen,
+    End,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Ident(name) => write!(f, "`{name}`"),
+            TokenKind::Number(value, unit) => {
+                write!(f, "`{value}{}`", unit.as_deref().unwrap_or_default())
+            }
+            TokenKind::Tilde => f.write_str("`~`"),
+            TokenKind::Pipe => f.write_str("`|`"),
+            TokenKind::Comma => f.write_str("`,`"),
+            TokenKind::OpenParen => f.write_str("`(`"),
+            TokenKind::CloseParen => f.write_str("`)`"),
+            TokenKind::End => f.write_str("the end of the pipeline"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    /// Byte offset in the source
+    offset: usize,
+}
+
+/// Parse a pipeline written in the pipeline language
+pub fn parse(source: &str) -> Result<PipelineSpec, ParseError> {
+    let tokens = tokenize(source)?;
+    Parser {
+        source,
+        tokens,
+        position: 0,
+    }
+    .pipeline()
+}
+
+fn error_at(source: &str, offset: usize, message: impl Into<String>) -> ParseError {
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+
+    ParseError {
+        line: source[..offset].matches('\n').count() + 1,
+        column: source[line_start..offset].chars().count() + 1,
+        message: message.into(),
+        source_line: source[line_start..line_end].to_string(),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(offset, c)) = chars.peek() {
+        let single = match c {
+            '~' => Some(TokenKind::Tilde),
+            '|' => Some(TokenKind::Pipe),
+            ',' => Some(TokenKind::Comma),
+            '(' => Some(TokenKind::OpenParen),
+            ')' => Some(TokenKind::CloseParen),
+            _ => None,
+        };
+        if let Some(kind) = single {
+            chars.next();
+            tokens.push(Token { kind, offset });
+            continue;
+        }
+
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = offset;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.') {
+                end = i + c.len_utf8();
+            }
+            let value = source[offset..end]
+                .parse::<f64>()
+                .map_err(|_| error_at(source, offset, "invalid number"))?;
+
+            let unit_start = end;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_ascii_alphabetic()) {
+                end = i + c.len_utf8();
+            }
+            let unit = (end > unit_start).then(|| source[unit_start..end].to_string());
+
+            tokens.push(Token {
+                kind: TokenKind::Number(value, unit),
+                offset,
+            });
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = offset;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+            {
+                end = i + c.len_utf8();
+            }
+            tokens.push(Token {
+                kind: TokenKind::Ident(source[offset..end].to_string()),
+                offset,
+            });
+        } else {
+            return Err(error_at(source, offset, format!("unexpected `{c}`")));
+        }
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::End,
+        offset: source.len(),
+    });
+    Ok(tokens)
+}
+
+enum Argument {
+    Number(f64),
+    Duration(JitteringDuration),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> ParseError {
+        error_at(self.source, token.offset, message)
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<Token, ParseError> {
+        let token = self.next();
+        if token.kind == kind {
+            Ok(token)
+        } else {
+            Err(self.error(&token, format!("expected {kind}, found {}", token.kind)))
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<PipelineSpec, ParseError> {
+        let (name, args) = self.stage()?;
+        if name.kind != TokenKind::Ident("source".to_string()) {
+            return Err(self.error(
+                &name,
+                format!(
+                    "a pipeline starts with `source(units)`, found {}",
+                    name.kind
+                ),
+            ));
+        }
+        let [units] = self.arguments(&name, args)?;
+        let mut spec = PipelineSpec::source(self.count(units)?);
+
+        while self.peek().kind != TokenKind::End {
+            self.expect(TokenKind::Pipe)?;
+
+            let (name, args) = self.stage()?;
+            let TokenKind::Ident(stage) = &name.kind else {
+                unreachable!("stage names are identifiers");
+            };
+            if stage != "sink" && matches!(spec.stages.last(), Some(StageSpec::TryForEach { .. })) {
+                return Err(self.error(&name, "nothing but the sink can follow `try_for_each`"));
+            }
+
+            spec = match stage.as_str() {
+                "buffered" => {
+                    let [duration, buffered] = self.arguments(&name, args)?;
+                    spec.map_buffered(self.duration(duration)?, self.count(buffered)?)
+                }
+                "buffer_unordered" => {
+                    let [duration, buffered] = self.arguments(&name, args)?;
+                    spec.map_buffer_unordered(self.duration(duration)?, self.count(buffered)?)
+                }
+                "filter" => {
+                    let [duration, filter_ratio] = self.arguments(&name, args)?;
+                    spec.filter(self.duration(duration)?, self.ratio(filter_ratio)?)
+                }
+                "filter_every" => {
+                    let [duration, n] = self.arguments(&name, args)?;
+                    spec.filter_every_nth(self.duration(duration)?, self.count(n)? as u32)
+                }
+                "try_buffered" => {
+                    let [duration, buffered, failure_ratio] = self.arguments(&name, args)?;
+                    spec.try_buffered(
+                        self.duration(duration)?,
+                        self.count(buffered)?,
+                        self.ratio(failure_ratio)?,
+                    )
+                }
+                "try_filter" => {
+                    let [duration, filter_ratio] = self.arguments(&name, args)?;
+                    spec.try_filter(self.duration(duration)?, self.ratio(filter_ratio)?)
+                }
+                "and_then" => {
+                    let [duration, failure_ratio] = self.arguments(&name, args)?;
+                    spec.and_then(self.duration(duration)?, self.ratio(failure_ratio)?)
+                }
+                "try_for_each" => {
+                    let [duration, failure_ratio] = self.arguments(&name, args)?;
+                    spec.try_for_each(self.duration(duration)?, self.ratio(failure_ratio)?)
+                }
+                "sink" => {
+                    let [] = self.arguments(&name, args)?;
+                    let token = self.next();
+                    if token.kind != TokenKind::End {
+                        return Err(self.error(&token, "nothing can follow the sink"));
+                    }
+                    break;
+                }
+                "source" => {
+                    return Err(self.error(&name, "a pipeline has a single source, at its start"))
+                }
+                _ => {
+                    return Err(self.error(
+                        &name,
+                        format!(
+                            "unknown stage `{stage}`, expected buffered, buffer_unordered, \
+                             filter, filter_every, try_buffered, try_filter, and_then, \
+                             try_for_each or sink"
+                        ),
+                    ))
+                }
+            };
+        }
+
+        Ok(spec)
+    }
+
+    /// A stage's name and its arguments, the parentheses are optional without arguments
+    fn stage(&mut self) -> Result<(Token, Vec<(Token, Argument)>), ParseError> {
+        let name = self.next();
+        if !matches!(name.kind, TokenKind::Ident(_)) {
+            return Err(self.error(&name, format!("expected a stage, found {}", name.kind)));
+        }
+
+        let mut args = Vec::new();
+        if self.peek().kind != TokenKind::OpenParen {
+            return Ok((name, args));
+        }
+        self.next();
+
+        if self.peek().kind != TokenKind::CloseParen {
+            loop {
+                args.push(self.argument()?);
+                if self.peek().kind == TokenKind::Comma {
+                    self.next();
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenKind::CloseParen)?;
+
+        Ok((name, args))
+    }
+
+    fn argument(&mut self) -> Result<(Token, Argument), ParseError> {
+        let token = self.next();
+        let TokenKind::Number(value, unit) = &token.kind else {
+            return Err(self.error(
+                &token,
+                format!("expected a number or a duration, found {}", token.kind),
+            ));
+        };
+
+        let millis = match unit.as_deref() {
+            None => return Ok((token.clone(), Argument::Number(*value))),
+            Some("ms") => *value,
+            Some("s") => *value * 1000.,
+            Some(unit) => {
+                return Err(self.error(
+                    &token,
+                    format!("unknown unit `{unit}`, expected `ms` or `s`"),
+                ))
+            }
+        };
+
+        let jitter = if self.peek().kind == TokenKind::Tilde {
+            self.next();
+            let jitter = self.next();
+            match jitter.kind {
+                TokenKind::Number(factor, None) if factor >= 0. && (factor as f32).is_finite() => {
+                    factor
+                }
+                TokenKind::Number(_, None) => {
+                    return Err(self.error(&jitter, "jitter factor too large"))
+                }
+                _ => {
+                    return Err(self.error(
+                        &jitter,
+                        format!("expected the jitter factor, found {}", jitter.kind),
+                    ))
+                }
+            }
+        } else {
+            0.
+        };
+
+        // the longest sampled duration has to fit too
+        let duration = Duration::try_from_secs_f64(millis / 1000.)
+            .ok()
+            .filter(|_| Duration::try_from_secs_f64(millis / 1000. * (1. + jitter)).is_ok())
+            .ok_or_else(|| self.error(&token, "duration too long"))?;
+
+        Ok((
+            token,
+            Argument::Duration(JitteringDuration {
+                duration,
+                jitter: jitter as f32,
+            }),
+        ))
+    }
+
+    fn arguments<const N: usize>(
+        &self,
+        name: &Token,
+        args: Vec<(Token, Argument)>,
+    ) -> Result<[(Token, Argument); N], ParseError> {
+        let count = args.len();
+        args.try_into().map_err(|_| {
+            self.error(
+                name,
+                format!(
+                    "{} takes {N} argument{}, found {count}",
+                    name.kind,
+                    if N == 1 { "" } else { "s" }
+                ),
+            )
+        })
+    }
+
+    fn duration(&self, (token, arg): (Token, Argument)) -> Result<JitteringDuration, ParseError> {
+        match arg {
+            Argument::Duration(duration) => Ok(duration),
+            Argument::Number(_) => Err(self.error(
+                &token,
+                "expected a duration, e.g. `500ms` or `500ms~3` with jitter",
+            )),
+        }
+    }
+
+    fn count(&self, (token, arg): (Token, Argument)) -> Result<usize, ParseError> {
+        match arg {
+            Argument::Number(value) if value >= 1. && value.fract() == 0. => Ok(value as usize),
+            _ => Err(self.error(&token, "expected a whole number, at least 1")),
+        }
+    }
+
+    fn ratio(&self, (token, arg): (Token, Argument)) -> Result<f32, ParseError> {
+        match arg {
+            Argument::Number(value) if (0. ..=1.).contains(&value) => Ok(value as f32),
+            _ => Err(self.error(&token, "expected a ratio between 0 and 1")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> ParseError {
+        match parse(source) {
+            Ok(_) => panic!("`{source}` should not parse"),
+            Err(e) => e,
+        }
+    }
+
+    fn position(e: &ParseError) -> (usize, usize) {
+        (e.line, e.column)
+    }
+
+    #[test]
+    fn parses_every_stage() {
+        let spec = parse(
+            "source(10) | buffered(500ms~3, 5) | buffer_unordered(1.5s, 2) \
+             | filter(200ms, 0.5) | filter_every(100ms, 3) | try_buffered(300ms, 4, 0.1) \
+             | try_filter(50ms~1, 0.7) | and_then(20ms, 0.2) | try_for_each(10ms, 0.05) | sink",
+        )
+        .unwrap();
+
+        assert_eq!(spec.source, 10);
+        let [StageSpec::MapBuffered {
+            duration,
+            buffered: 5,
+        }, StageSpec::MapBufferUnordered {
+            duration: unordered,
+            buffered: 2,
+        }, StageSpec::Filter {
+            filter_ratio: filter,
+            ..
+        }, StageSpec::FilterEveryNth { n: 3, .. }, StageSpec::TryBuffered {
+            buffered: 4,
+            failure_ratio: try_buffered,
+            ..
+        }, StageSpec::TryFilter {
+            duration: try_filter,
+            filter_ratio: try_filter_ratio,
+        }, StageSpec::AndThen {
+            failure_ratio: and_then,
+            ..
+        }, StageSpec::TryForEach {
+            duration: try_for_each,
+            failure_ratio: try_for_each_ratio,
+        }] = spec.stages.as_slice()
+        else {
+            panic!("unexpected stages");
+        };
+
+        assert_eq!(duration.duration, Duration::from_millis(500));
+        assert_eq!(duration.jitter, 3.);
+        assert_eq!(unordered.duration, Duration::from_millis(1500));
+        assert_eq!(unordered.jitter, 0.);
+        assert_eq!(*filter, 0.5);
+        assert_eq!(*try_buffered, 0.1);
+        assert_eq!(try_filter.jitter, 1.);
+        assert_eq!(*try_filter_ratio, 0.7);
+        assert_eq!(*and_then, 0.2);
+        assert_eq!(try_for_each.duration, Duration::from_millis(10));
+        assert_eq!(*try_for_each_ratio, 0.05);
+    }
+
+    #[test]
+    fn sink_and_comments_are_optional() {
+        let spec = parse("# a comment\nsource(3)   # units\n| filter(1s, 1)\n").unwrap();
+        assert_eq!(spec.source, 3);
+        assert_eq!(spec.stages.len(), 1);
+
+        assert!(parse("source(3) | sink()").unwrap().stages.is_empty());
+    }
+
+    #[test]
+    fn unknown_stage() {
+        let e = error("source(3)\n  | buferred(1s, 2)");
+        assert_eq!(position(&e), (2, 5));
+        assert!(e.message.starts_with("unknown stage `buferred`"));
+    }
+
+    #[test]
+    fn missing_close_paren() {
+        let e = error("source(3) | buffered(1s, 2 | sink");
+        assert_eq!(position(&e), (1, 28));
+        assert_eq!(e.message, "expected `)`, found `|`");
+    }
+
+    #[test]
+    fn bad_numbers() {
+        let e = error("source(3) | filter(1s, 0.5.1)");
+        assert_eq!(position(&e), (1, 24));
+        assert_eq!(e.message, "invalid number");
+
+        let e = error("source(3) | filter(1s, 2)");
+        assert_eq!(position(&e), (1, 24));
+        assert_eq!(e.message, "expected a ratio between 0 and 1");
+
+        let e = error("source(0)");
+        assert_eq!(position(&e), (1, 8));
+        assert_eq!(e.message, "expected a whole number, at least 1");
+
+        let e = error("source(3) | buffered(1min, 2)");
+        assert_eq!(position(&e), (1, 22));
+        assert_eq!(e.message, "unknown unit `min`, expected `ms` or `s`");
+    }
+
+    #[test]
+    fn out_of_range_durations() {
+        let e = error(&format!("source(3) | buffered(1{}ms, 2)", "0".repeat(400)));
+        assert_eq!(position(&e), (1, 22));
+        assert_eq!(e.message, "duration too long");
+
+        let e = error(&format!("source(3) | buffered(1s~1{}, 2)", "0".repeat(40)));
+        assert_eq!(position(&e), (1, 25));
+        assert_eq!(e.message, "jitter factor too large");
+
+        let e = error("source(3) | buffered(1s~-1, 2)");
+        assert_eq!(position(&e), (1, 25));
+    }
+
+    #[test]
+    fn misplaced_stages() {
+        let e = error("buffered(1s, 2)");
+        assert_eq!(position(&e), (1, 1));
+
+        let e = error("source(3) | sink | filter(1s, 0.5)");
+        assert_eq!(position(&e), (1, 18));
+        assert_eq!(e.message, "nothing can follow the sink");
+
+        let e = error("source(3) | buffered(1s)");
+        assert_eq!(position(&e), (1, 13));
+        assert_eq!(e.message, "`buffered` takes 2 arguments, found 1");
+
+        let e = error("source(3) | try_for_each(1s, 0.1) | filter(1s, 0.5)");
+        assert_eq!(position(&e), (1, 37));
+        assert_eq!(e.message, "nothing but the sink can follow `try_for_each`");
+        assert!(parse("source(3) | try_for_each(1s, 0.1) | sink").is_ok());
+
+        let e = error("source(3) | buffered(2, 1s)");
+        assert_eq!(position(&e), (1, 22));
+    }
+}
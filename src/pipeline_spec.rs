@@ -0,0 +1,645 @@
+use std::{collections::BTreeMap, fmt, future::Future, str::FromStr, sync::Arc, time::Duration};
+
+use futures_util::future::{BoxFuture, FutureExt};
+
+use crate::stream_vis_builder::{JitteringDuration, Progress, Render, StreamVisBuilder, ValueLook};
+
+type UserFuture = Arc<dyn Fn(u32, Progress) -> BoxFuture<'static, ()> + Send + Sync>;
+type ValueMap = Arc<dyn Fn(u32) -> u32 + Send + Sync>;
+type Predicate = Arc<dyn Fn(u32, &u32) -> bool + Send + Sync>;
+type FilterMap = Arc<dyn Fn(u32) -> Option<u32> + Send + Sync>;
+
+/// A pipeline described as data rather than as a running stream, so it can be built again for
+/// every run and have its parameters changed in between
+#[derive(Clone)]
+pub struct PipelineSpec {
+    pub source: usize,
+    pub stages: Vec<StageSpec>,
+    /// How the units' payloads, starting as their index in the source, are shown
+    pub render: Option<Render<u32>>,
+    /// Looks given to the units by the stages, by number of stages before, the source's at 0
+    pub completions: BTreeMap<usize, Render<u32>>,
+}
+
+#[derive(Clone)]
+pub enum StageSpec {
+    MapBuffered {
+        duration: JitteringDuration,
+        buffered: usize,
+    },
+    MapBufferUnordered {
+        duration: JitteringDuration,
+        buffered: usize,
+    },
+    Filter {
+        duration: JitteringDuration,
+        filter_ratio: f32,
+    },
+    MapBufferedWith {
+        buffered: usize,
+        future: UserFuture,
+    },
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    MapBufferUnorderedWith {
+        buffered: usize,
+        future: UserFuture,
+    },
+    MapValueBuffered {
+        duration: JitteringDuration,
+        buffered: usize,
+        map: ValueMap,
+    },
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    MapValueBufferUnordered {
+        duration: JitteringDuration,
+        buffered: usize,
+        map: ValueMap,
+    },
+    FilterBy {
+        duration: JitteringDuration,
+        description: String,
+        predicate: Predicate,
+    },
+    FilterEveryNth {
+        duration: JitteringDuration,
+        n: u32,
+    },
+    FilterMap {
+        duration: JitteringDuration,
+        description: String,
+        f: FilterMap,
+    },
+    TryBuffered {
+        duration: JitteringDuration,
+        buffered: usize,
+        failure_ratio: f32,
+    },
+    TryFilter {
+        duration: JitteringDuration,
+        filter_ratio: f32,
+    },
+    AndThen {
+        duration: JitteringDuration,
+        failure_ratio: f32,
+    },
+    TryForEach {
+        duration: JitteringDuration,
+        failure_ratio: f32,
+    },
+}
+
+/// A numeric parameter of a pipeline, see `PipelineSpec::with`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parameter {
+    /// Number of units created by the source
+    Source,
+    Buffered,
+    /// Base duration of the stage's futures, in milliseconds
+    Duration,
+    Jitter,
+    FilterRatio,
+    /// Chance of a unit's future failing
+    FailureRatio,
+}
+
+impl PipelineSpec {
+    pub fn source(size: usize) -> Self {
+        Self {
+            source: size,
+            stages: Vec::new(),
+            render: None,
+            completions: BTreeMap::new(),
+        }
+    }
+
+    pub fn filter(self, duration: JitteringDuration, filter_ratio: f32) -> Self {
+        self.stage(StageSpec::Filter {
+            duration,
+            filter_ratio,
+        })
+    }
+
+    pub fn map_buffered(self, duration: JitteringDuration, buffered: usize) -> Self {
+        self.stage(StageSpec::MapBuffered { duration, buffered })
+    }
+
+    pub fn map_buffer_unordered(self, duration: JitteringDuration, buffered: usize) -> Self {
+        self.stage(StageSpec::MapBufferUnordered { duration, buffered })
+    }
+
+    pub fn map_buffered_with<F, Fut>(self, buffered: usize, f: F) -> Self
+    where
+        F: Fn(u32, Progress) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.stage(StageSpec::MapBufferedWith {
+            buffered,
+            future: Arc::new(move |unit, progress| f(unit, progress).boxed()),
+        })
+    }
+
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    pub fn map_buffer_unordered_with<F, Fut>(self, buffered: usize, f: F) -> Self
+    where
+        F: Fn(u32, Progress) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.stage(StageSpec::MapBufferUnorderedWith {
+            buffered,
+            future: Arc::new(move |unit, progress| f(unit, progress).boxed()),
+        })
+    }
+
+    pub fn map_value_buffered(
+        self,
+        duration: JitteringDuration,
+        buffered: usize,
+        map: impl Fn(u32) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        self.stage(StageSpec::MapValueBuffered {
+            duration,
+            buffered,
+            map: Arc::new(map),
+        })
+    }
+
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    pub fn map_value_buffer_unordered(
+        self,
+        duration: JitteringDuration,
+        buffered: usize,
+        map: impl Fn(u32) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        self.stage(StageSpec::MapValueBufferUnordered {
+            duration,
+            buffered,
+            map: Arc::new(map),
+        })
+    }
+
+    pub fn filter_by(
+        self,
+        duration: JitteringDuration,
+        description: impl Into<String>,
+        predicate: impl Fn(u32, &u32) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.stage(StageSpec::FilterBy {
+            duration,
+            description: description.into(),
+            predicate: Arc::new(predicate),
+        })
+    }
+
+    pub fn filter_value(
+        self,
+        duration: JitteringDuration,
+        description: impl Into<String>,
+        predicate: impl Fn(&u32) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter_by(duration, description, move |_, value| predicate(value))
+    }
+
+    pub fn filter_every_nth(self, duration: JitteringDuration, n: u32) -> Self {
+        self.stage(StageSpec::FilterEveryNth { duration, n })
+    }
+
+    pub fn filter_map(
+        self,
+        duration: JitteringDuration,
+        description: impl Into<String>,
+        f: impl Fn(u32) -> Option<u32> + Send + Sync + 'static,
+    ) -> Self {
+        self.stage(StageSpec::FilterMap {
+            duration,
+            description: description.into(),
+            f: Arc::new(f),
+        })
+    }
+
+    pub fn try_buffered(
+        self,
+        duration: JitteringDuration,
+        buffered: usize,
+        failure_ratio: f32,
+    ) -> Self {
+        self.stage(StageSpec::TryBuffered {
+            duration,
+            buffered,
+            failure_ratio,
+        })
+    }
+
+    pub fn try_filter(self, duration: JitteringDuration, filter_ratio: f32) -> Self {
+        self.stage(StageSpec::TryFilter {
+            duration,
+            filter_ratio,
+        })
+    }
+
+    pub fn and_then(self, duration: JitteringDuration, failure_ratio: f32) -> Self {
+        self.stage(StageSpec::AndThen {
+            duration,
+            failure_ratio,
+        })
+    }
+
+    /// Consumes the stream, so only the sink can follow it
+    pub fn try_for_each(self, duration: JitteringDuration, failure_ratio: f32) -> Self {
+        self.stage(StageSpec::TryForEach {
+            duration,
+            failure_ratio,
+        })
+    }
+
+    pub fn render(mut self, render: impl Fn(&u32) -> ValueLook + Send + Sync + 'static) -> Self {
+        self.render = Some(Arc::new(render));
+        self
+    }
+
+    /// Look of the units the last stage is done with, see `StreamVisBuilder::on_completion`
+    pub fn on_completion(
+        mut self,
+        look: impl Fn(&u32) -> ValueLook + Send + Sync + 'static,
+    ) -> Self {
+        self.completions.insert(self.stages.len(), Arc::new(look));
+        self
+    }
+
+    fn stage(mut self, stage: StageSpec) -> Self {
+        assert!(
+            !matches!(self.stages.last(), Some(StageSpec::TryForEach { .. })),
+            "nothing but the sink can follow try_for_each"
+        );
+        self.stages.push(stage);
+        self
+    }
+
+    /// A fresh, not yet running, stream of the pipeline
+    pub fn build(&self) -> StreamVisBuilder {
+        self.build_seeded(rand::random())
+    }
+
+    /// Like `build`, drawing the pipeline's randomness from `seed`
+    pub fn build_seeded(&self, seed: u64) -> StreamVisBuilder {
+        // every stage mapping the payloads starts over with an unset render function
+        let rendered = |builder: StreamVisBuilder| match self.render.clone() {
+            Some(render) => builder.render(move |value| render(value)),
+            None => builder,
+        };
+        let completed = |builder: StreamVisBuilder, stages| match self.completions.get(&stages) {
+            Some(look) => {
+                let look = look.clone();
+                builder.on_completion(move |value| look(value))
+            }
+            None => builder,
+        };
+
+        let source = rendered(StreamVisBuilder::source_seeded(self.source, seed));
+        self.stages
+            .iter()
+            .enumerate()
+            .fold(completed(source, 0), |builder, (index, stage)| {
+                let builder = match stage {
+                    StageSpec::MapBuffered { duration, buffered } => {
+                        builder.map_buffered(*duration, *buffered)
+                    }
+                    StageSpec::MapBufferUnordered { duration, buffered } => {
+                        builder.map_buffer_unordered(*duration, *buffered)
+                    }
+                    StageSpec::Filter {
+                        duration,
+                        filter_ratio,
+                    } => builder.filter(*duration, *filter_ratio),
+                    StageSpec::MapBufferedWith { buffered, future } => {
+                        let future = future.clone();
+                        builder.map_buffered_with(*buffered, move |unit, progress| {
+                            future(unit, progress)
+                        })
+                    }
+                    StageSpec::MapBufferUnorderedWith { buffered, future } => {
+                        let future = future.clone();
+                        builder.map_buffer_unordered_with(*buffered, move |unit, progress| {
+                            future(unit, progress)
+                        })
+                    }
+                    StageSpec::MapValueBuffered {
+                        duration,
+                        buffered,
+                        map,
+                    } => {
+                        let map = map.clone();
+                        rendered(
+                            builder
+                                .map_value_buffered(*duration, *buffered, move |value| map(value)),
+                        )
+                    }
+                    StageSpec::MapValueBufferUnordered {
+                        duration,
+                        buffered,
+                        map,
+                    } => {
+                        let map = map.clone();
+                        rendered(builder.map_value_buffer_unordered(
+                            *duration,
+                            *buffered,
+                            move |value| map(value),
+                        ))
+                    }
+                    StageSpec::FilterBy {
+                        duration,
+                        description,
+                        predicate,
+                    } => {
+                        let predicate = predicate.clone();
+                        builder.filter_by(*duration, description.clone(), move |id, value| {
+                            predicate(id, value)
+                        })
+                    }
+                    StageSpec::FilterEveryNth { duration, n } => {
+                        builder.filter_every_nth(*duration, *n)
+                    }
+                    StageSpec::FilterMap {
+                        duration,
+                        description,
+                        f,
+                    } => {
+                        let f = f.clone();
+                        rendered(
+                            builder
+                                .filter_map(*duration, description.clone(), move |value| f(value)),
+                        )
+                    }
+                    StageSpec::TryBuffered {
+                        duration,
+                        buffered,
+                        failure_ratio,
+                    } => builder.try_buffered(*duration, *buffered, *failure_ratio),
+                    StageSpec::TryFilter {
+                        duration,
+                        filter_ratio,
+                    } => builder.try_filter(*duration, *filter_ratio),
+                    StageSpec::AndThen {
+                        duration,
+                        failure_ratio,
+                    } => builder.and_then(*duration, *failure_ratio),
+                    StageSpec::TryForEach {
+                        duration,
+                        failure_ratio,
+                    } => builder.try_for_each(*duration, *failure_ratio),
+                };
+                completed(builder, index + 1)
+            })
+    }
+
+    /// The same pipeline with `parameter` set to `value`, in the 1-based `stage` or in the first
+    /// stage having that parameter
+    pub fn with(
+        &self,
+        parameter: Parameter,
+        stage: Option<usize>,
+        value: f32,
+    ) -> Result<PipelineSpec, String> {
+        let mut spec = self.clone();
+        if parameter == Parameter::Source {
+            if !(value.is_finite() && value >= 0.) {
+                return Err(format!(
+                    "{parameter} must be a number of units, not {value}"
+                ));
+            }
+            spec.source = value.round() as usize;
+            return Ok(spec);
+        }
+
+        let index = match stage {
+            Some(stage) => stage
+                .checked_sub(1)
+                .filter(|index| *index < spec.stages.len())
+                .ok_or_else(|| {
+                    format!("no stage {stage}, the pipeline has {}", spec.stages.len())
+                })?,
+            None => spec
+                .stages
+                .iter_mut()
+                .position(|stage| stage.parameter(parameter).is_some())
+                .ok_or_else(|| format!("no stage has a {parameter} parameter"))?,
+        };
+
+        match spec.stages[index].parameter(parameter) {
+            Some(ParameterMut::Count(count)) => {
+                if !(value.is_finite() && value >= 1.) {
+                    return Err(format!("{parameter} must be at least 1, not {value}"));
+                }
+                *count = value.round() as usize
+            }
+            Some(ParameterMut::Millis(duration)) => {
+                *duration = Duration::try_from_secs_f32(value / 1000.)
+                    .map_err(|_| format!("{parameter} must be a number of ms, not {value}"))?
+            }
+            Some(ParameterMut::Jitter(jitter)) => {
+                if !(value.is_finite() && value >= 0.) {
+                    return Err(format!("{parameter} must be at least 0, not {value}"));
+                }
+                *jitter = value
+            }
+            Some(ParameterMut::Ratio(ratio)) => {
+                if !(0. ..=1.).contains(&value) {
+                    return Err(format!("{parameter} must be between 0 and 1, not {value}"));
+                }
+                *ratio = value
+            }
+            None => return Err(format!("stage {} has no {parameter} parameter", index + 1)),
+        }
+
+        Ok(spec)
+    }
+}
+
+enum ParameterMut<'a> {
+    Count(&'a mut usize),
+    Millis(&'a mut Duration),
+    Jitter(&'a mut f32),
+    Ratio(&'a mut f32),
+}
+
+impl StageSpec {
+    fn parameter(&mut self, parameter: Parameter) -> Option<ParameterMut<'_>> {
+        match (self, parameter) {
+            (
+                StageSpec::MapBuffered { buffered, .. }
+                | StageSpec::MapBufferUnordered { buffered, .. }
+                | StageSpec::MapBufferedWith { buffered, .. }
+                | StageSpec::MapBufferUnorderedWith { buffered, .. }
+                | StageSpec::MapValueBuffered { buffered, .. }
+                | StageSpec::MapValueBufferUnordered { buffered, .. }
+                | StageSpec::TryBuffered { buffered, .. },
+                Parameter::Buffered,
+            ) => Some(ParameterMut::Count(buffered)),
+            (
+                StageSpec::MapBuffered { duration, .. }
+                | StageSpec::MapBufferUnordered { duration, .. }
+                | StageSpec::Filter { duration, .. }
+                | StageSpec::MapValueBuffered { duration, .. }
+                | StageSpec::MapValueBufferUnordered { duration, .. }
+                | StageSpec::FilterBy { duration, .. }
+                | StageSpec::FilterEveryNth { duration, .. }
+                | StageSpec::FilterMap { duration, .. }
+                | StageSpec::TryBuffered { duration, .. }
+                | StageSpec::TryFilter { duration, .. }
+                | StageSpec::AndThen { duration, .. }
+                | StageSpec::TryForEach { duration, .. },
+                Parameter::Duration,
+            ) => Some(ParameterMut::Millis(&mut duration.duration)),
+            (
+                StageSpec::MapBuffered { duration, .. }
+                | StageSpec::MapBufferUnordered { duration, .. }
+                | StageSpec::Filter { duration, .. }
+                | StageSpec::MapValueBuffered { duration, .. }
+                | StageSpec::MapValueBufferUnordered { duration, .. }
+                | StageSpec::FilterBy { duration, .. }
+                | StageSpec::FilterEveryNth { duration, .. }
+                | StageSpec::FilterMap { duration, .. }
+                | StageSpec::TryBuffered { duration, .. }
+                | StageSpec::TryFilter { duration, .. }
+                | StageSpec::AndThen { duration, .. }
+                | StageSpec::TryForEach { duration, .. },
+                Parameter::Jitter,
+            ) => Some(ParameterMut::Jitter(&mut duration.jitter)),
+            (
+                StageSpec::Filter { filter_ratio, .. } | StageSpec::TryFilter { filter_ratio, .. },
+                Parameter::FilterRatio,
+            ) => Some(ParameterMut::Ratio(filter_ratio)),
+            (
+                StageSpec::TryBuffered { failure_ratio, .. }
+                | StageSpec::AndThen { failure_ratio, .. }
+                | StageSpec::TryForEach { failure_ratio, .. },
+                Parameter::FailureRatio,
+            ) => Some(ParameterMut::Ratio(failure_ratio)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Parameter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "source" => Ok(Parameter::Source),
+            "buffered" => Ok(Parameter::Buffered),
+            "duration" => Ok(Parameter::Duration),
+            "jitter" => Ok(Parameter::Jitter),
+            "filter_ratio" => Ok(Parameter::FilterRatio),
+            "failure_ratio" => Ok(Parameter::FailureRatio),
+            _ => Err(format!(
+                "unknown parameter {name}, expected source, buffered, duration, jitter, \
+                 filter_ratio or failure_ratio"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Parameter::Source => "source",
+            Parameter::Buffered => "buffered",
+            Parameter::Duration => "duration",
+            Parameter::Jitter => "jitter",
+            Parameter::FilterRatio => "filter_ratio",
+            Parameter::FailureRatio => "failure_ratio",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> PipelineSpec {
+        PipelineSpec::source(10)
+            .filter(JitteringDuration::from_millis(100, 1.), 0.5)
+            .map_buffered(JitteringDuration::from_millis(500, 2.), 3)
+            .map_buffered(JitteringDuration::from_millis(200, 0.), 1)
+    }
+
+    fn buffered(spec: &PipelineSpec) -> Vec<usize> {
+        spec.stages
+            .iter()
+            .filter_map(|stage| match stage {
+                StageSpec::MapBuffered { buffered, .. } => Some(*buffered),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn changes_the_first_stage_having_the_parameter() {
+        let changed = spec().with(Parameter::Buffered, None, 6.).unwrap();
+        assert_eq!(buffered(&changed), [6, 1]);
+
+        let changed = spec().with(Parameter::Buffered, Some(3), 2.4).unwrap();
+        assert_eq!(buffered(&changed), [3, 2]);
+
+        let changed = spec().with(Parameter::Source, None, 41.6).unwrap();
+        assert_eq!(changed.source, 42);
+    }
+
+    #[test]
+    fn sets_durations_and_ratios() {
+        let changed = spec().with(Parameter::Duration, Some(2), 750.).unwrap();
+        let StageSpec::MapBuffered { duration, .. } = &changed.stages[1] else {
+            panic!("stage 2 should be a map");
+        };
+        assert_eq!(duration.duration, Duration::from_millis(750));
+
+        let changed = spec().with(Parameter::FilterRatio, None, 0.25).unwrap();
+        let StageSpec::Filter { filter_ratio, .. } = &changed.stages[0] else {
+            panic!("stage 1 should be a filter");
+        };
+        assert_eq!(*filter_ratio, 0.25);
+    }
+
+    #[test]
+    fn rejects_stages_without_the_parameter() {
+        let e = spec().with(Parameter::Buffered, Some(4), 2.).err().unwrap();
+        assert_eq!(e, "no stage 4, the pipeline has 3");
+
+        let e = spec().with(Parameter::Buffered, Some(1), 2.).err().unwrap();
+        assert_eq!(e, "stage 1 has no buffered parameter");
+
+        let e = PipelineSpec::source(3)
+            .with(Parameter::FilterRatio, None, 0.5)
+            .err()
+            .unwrap();
+        assert_eq!(e, "no stage has a filter_ratio parameter");
+    }
+
+    #[test]
+    fn parameter_names_round_trip() {
+        for name in ["source", "buffered", "duration", "jitter", "filter_ratio"] {
+            assert_eq!(name.parse::<Parameter>().unwrap().to_string(), name);
+        }
+        assert!("speed".parse::<Parameter>().is_err());
+    }
+
+    #[test]
+    fn builds_the_described_stages() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let (blocks, _) = rt.block_on(spec().build().run());
+        let names = blocks.iter().map(|block| block.name()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "source",
+                ".filter(100ms)",
+                ".map(500ms).buffer(3)",
+                ".map(200ms).buffer(1)",
+                "sink"
+            ]
+        );
+    }
+}
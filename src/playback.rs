@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use crate::history::History;
+
+const PAUSE_KEY: KeyCode = KeyCode::Space;
+const STEP_KEY: KeyCode = KeyCode::Right;
+const FASTER_KEYS: [KeyCode; 3] = [KeyCode::Plus, KeyCode::Equals, KeyCode::NumpadAdd];
+const SLOWER_KEYS: [KeyCode; 2] = [KeyCode::Minus, KeyCode::NumpadSubtract];
+
+/// Stream time passing in a single step
+pub const STEP: Duration = Duration::from_millis(100);
+const MIN_SPEED: f32 = 1. / 8.;
+const MAX_SPEED: f32 = 8.;
+const STATUS_FONT_SIZE: f32 = 13.;
+
+/// Time passing in the animation, sent every frame to the runtimes of the pipelines shown, so
+/// their futures and the tweens run on the same virtual clock
+#[derive(Resource, Default)]
+pub struct PipelineClock(Vec<Sender<Duration>>);
+
+impl PipelineClock {
+    /// A clock for one more pipeline, see `StreamVisBuilder::sink_with_clock`
+    pub fn subscribe(&mut self) -> Receiver<Duration> {
+        let (tx, rx) = unbounded();
+        self.0.push(tx);
+        rx
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Playback {
+    /// Stream time left of the current step, paused once it ran out
+    step_left: Option<Duration>,
+}
+
+impl Playback {
+    /// Pause, cancelling the current step
+    pub fn pause(&mut self, time: &mut Time<Virtual>) {
+        self.step_left = None;
+        time.pause();
+    }
+}
+
+#[derive(Component)]
+pub struct PlaybackStatusText;
+
+/// The pipelines are held while the animation replays their history
+pub fn tick_pipeline_clock(
+    time: Res<Time<Virtual>>,
+    clock: Res<PipelineClock>,
+    history: Res<History>,
+) {
+    if time.delta().is_zero() || !history.is_live() {
+        return;
+    }
+
+    for tx in &clock.0 {
+        // a finished pipeline has dropped its clock
+        _ = tx.send(time.delta());
+    }
+}
+
+/// Space pauses and resumes, the right arrow steps forward, + and - change the speed
+pub fn playback_controls(
+    keys: Res<Input<KeyCode>>,
+    mut time: ResMut<Time<Virtual>>,
+    mut playback: ResMut<Playback>,
+) {
+    if let Some(step_left) = playback.step_left {
+        let step_left = step_left.saturating_sub(time.delta());
+        if step_left.is_zero() {
+            time.pause();
+            playback.step_left = None;
+        } else {
+            playback.step_left = Some(step_left);
+        }
+    }
+
+    if keys.just_pressed(PAUSE_KEY) {
+        playback.step_left = None;
+        if time.is_paused() {
+            time.unpause();
+        } else {
+            time.pause();
+        }
+    }
+
+    if keys.just_pressed(STEP_KEY) {
+        playback.step_left = Some(STEP);
+        time.unpause();
+    }
+
+    if keys.any_just_pressed(FASTER_KEYS) {
+        let speed = (time.relative_speed() * 2.).min(MAX_SPEED);
+        time.set_relative_speed(speed);
+    }
+    if keys.any_just_pressed(SLOWER_KEYS) {
+        let speed = (time.relative_speed() / 2.).max(MIN_SPEED);
+        time.set_relative_speed(speed);
+    }
+}
+
+pub fn spawn_playback_status(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("Virgil.ttf"),
+                font_size: STATUS_FONT_SIZE,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.),
+            right: Val::Px(10.),
+            ..default()
+        }),
+        PlaybackStatusText,
+    ));
+}
+
+/// Show when the animation is paused or isn't running at normal speed
+pub fn update_playback_status(
+    time: Res<Time<Virtual>>,
+    playback: Res<Playback>,
+    mut texts: Query<&mut Text, With<PlaybackStatusText>>,
+) {
+    let mut status = Vec::new();
+    if time.is_paused() && playback.step_left.is_none() {
+        status.push("paused".to_string());
+    }
+    if time.relative_speed() != 1. {
+        status.push(format!("{}x", time.relative_speed()));
+    }
+    let status = status.join("  ");
+
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Time<Virtual>>();
+        world.init_resource::<Input<KeyCode>>();
+        world.init_resource::<Playback>();
+        world.insert_resource(History::new(Vec::new()));
+        world
+    }
+
+    /// Run the controls for a frame of `delta` with `key` just pressed
+    fn frame(world: &mut World, delta: Duration, key: Option<KeyCode>) {
+        world.resource_mut::<Time<Virtual>>().advance_by(delta);
+        let mut keys = world.resource_mut::<Input<KeyCode>>();
+        keys.reset_all();
+        if let Some(key) = key {
+            keys.press(key);
+        }
+        world.run_system_once(playback_controls);
+    }
+
+    #[test]
+    fn space_pauses_and_resumes() {
+        let mut world = world();
+        frame(&mut world, Duration::ZERO, Some(PAUSE_KEY));
+        assert!(world.resource::<Time<Virtual>>().is_paused());
+        frame(&mut world, Duration::ZERO, Some(PAUSE_KEY));
+        assert!(!world.resource::<Time<Virtual>>().is_paused());
+    }
+
+    #[test]
+    fn steps_pause_once_their_time_ran_out() {
+        let mut world = world();
+        frame(&mut world, Duration::ZERO, Some(PAUSE_KEY));
+        frame(&mut world, Duration::ZERO, Some(STEP_KEY));
+        assert!(!world.resource::<Time<Virtual>>().is_paused());
+
+        frame(&mut world, Duration::from_millis(60), None);
+        assert!(!world.resource::<Time<Virtual>>().is_paused());
+        frame(&mut world, Duration::from_millis(60), None);
+        assert!(world.resource::<Time<Virtual>>().is_paused());
+        assert_eq!(world.resource::<Playback>().step_left, None);
+    }
+
+    #[test]
+    fn speed_doubles_and_halves_within_bounds() {
+        let mut world = world();
+        for _ in 0..5 {
+            frame(&mut world, Duration::ZERO, Some(KeyCode::Plus));
+        }
+        assert_eq!(
+            world.resource::<Time<Virtual>>().relative_speed(),
+            MAX_SPEED
+        );
+
+        for _ in 0..7 {
+            frame(&mut world, Duration::ZERO, Some(KeyCode::Minus));
+        }
+        assert_eq!(
+            world.resource::<Time<Virtual>>().relative_speed(),
+            MIN_SPEED
+        );
+    }
+
+    #[test]
+    fn sends_the_frame_time_to_every_pipeline() {
+        let mut world = world();
+        let mut clock = PipelineClock::default();
+        let (first, second) = (clock.subscribe(), clock.subscribe());
+        world.insert_resource(clock);
+
+        world.run_system_once(tick_pipeline_clock);
+        world
+            .resource_mut::<Time<Virtual>>()
+            .advance_by(Duration::from_millis(16));
+        world.run_system_once(tick_pipeline_clock);
+
+        let delta = Duration::from_millis(16);
+        assert_eq!(first.try_iter().collect::<Vec<_>>(), [delta]);
+        assert_eq!(second.try_iter().collect::<Vec<_>>(), [delta]);
+    }
+}
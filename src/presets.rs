@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use crate::{
+    future_vis::UnitShape,
+    pipeline_spec::PipelineSpec,
+    stream_vis_builder::{JitteringDuration, ValueLook, COLORS},
+};
+
+/// A named pipeline selectable with `--preset`, with a caption explaining what it shows
+pub struct Preset {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub pipeline: fn() -> PipelineSpec,
+}
+
+/// Preset shown when none is selected
+pub const DEFAULT_PRESET: &str = "buffer-filter";
+
+/// Presets stacked by `--compare`
+pub const COMPARED_PRESETS: [&str; 2] = ["buffer-filter", "buffer-unordered-filter"];
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "buffer-1",
+        title: "buffered(1)",
+        description: "One future at a time, every unit waits for the one ahead of it",
+        pipeline: || {
+            PipelineSpec::source(3).map_buffered(JitteringDuration::from_millis(500, 3.), 1)
+        },
+    },
+    Preset {
+        name: "buffer-5",
+        title: "buffered(5)",
+        description: "Up to five futures run at once, but units leave in order, so a slow \
+                      future holds back the finished ones behind it",
+        pipeline: || {
+            PipelineSpec::source(15).map_buffered(JitteringDuration::from_millis(800, 4.), 5)
+        },
+    },
+    Preset {
+        name: "buffer-unordered-5",
+        title: "buffer_unordered(5)",
+        description: "Up to five futures run at once, and every unit leaves as soon as its \
+                      future completes",
+        pipeline: || {
+            PipelineSpec::source(15)
+                .map_buffer_unordered(JitteringDuration::from_millis(500, 3.), 5)
+        },
+    },
+    Preset {
+        name: "filter",
+        title: "filter",
+        description: "An async predicate letting about half of the units through",
+        pipeline: || PipelineSpec::source(3).filter(JitteringDuration::from_millis(500, 1.), 0.5),
+    },
+    Preset {
+        name: "filter-every-3rd",
+        title: "filter(drop every 3rd)",
+        description: "A predicate on the unit's id rather than a coin toss, so which units \
+                      make it through is known in advance",
+        pipeline: || {
+            PipelineSpec::source(9).filter_every_nth(JitteringDuration::from_millis(500, 1.), 3)
+        },
+    },
+    Preset {
+        name: "buffer-filter",
+        title: "buffered(5), then filter",
+        description: "The filter pulls a unit at a time, while its slow future runs the \
+                      buffered futures aren't polled",
+        pipeline: || {
+            PipelineSpec::source(10)
+                .map_buffered(JitteringDuration::from_millis(500, 3.), 5)
+                .filter(JitteringDuration::from_millis(1200, 1.), 0.5)
+        },
+    },
+    Preset {
+        name: "buffer-unordered-filter",
+        title: "buffer_unordered(5), then filter",
+        description: "Like buffer-filter, but units finishing early can reach the filter \
+                      ahead of slower ones",
+        pipeline: || {
+            PipelineSpec::source(10)
+                .map_buffer_unordered(JitteringDuration::from_millis(500, 3.), 5)
+                .filter(JitteringDuration::from_millis(1200, 1.), 0.5)
+        },
+    },
+    Preset {
+        name: "two-buffers",
+        title: "buffered(5), then buffered(3)",
+        description: "The slower second stage sets the pace, the first one fills up and waits",
+        pipeline: || {
+            PipelineSpec::source(10)
+                .map_buffered(JitteringDuration::from_millis(500, 3.), 5)
+                .map_buffered(JitteringDuration::from_millis(1000, 2.), 3)
+        },
+    },
+    Preset {
+        name: "user-future",
+        title: "user supplied future",
+        description: "Futures reporting their own progress, a fetch phase then a db phase",
+        pipeline: || {
+            PipelineSpec::source(10).map_buffered_with(3, |_unit, mut progress| async move {
+                progress.phase("fetch");
+                tokio::time::sleep(Duration::from_millis(400)).await;
+                progress.phase("db");
+                tokio::time::sleep(Duration::from_millis(800)).await;
+            })
+        },
+    },
+    Preset {
+        name: "values",
+        title: "map(x * 3), then filter(even)",
+        description: "Units carry a number, tripled once the map's future completes, then \
+                      the filter keeps the even ones. Shown with --unit-labels",
+        pipeline: || {
+            PipelineSpec::source(10)
+                .map_value_buffered(JitteringDuration::from_millis(500, 2.), 3, |x| x * 3)
+                .filter_value(JitteringDuration::from_millis(800, 1.), "x % 2 == 0", |x| {
+                    x % 2 == 0
+                })
+                .render(|x| ValueLook {
+                    color: Some(COLORS[if x % 2 == 0 { 3 } else { 1 }]),
+                    label: Some(x.to_string()),
+                    ..Default::default()
+                })
+        },
+    },
+    Preset {
+        name: "filter-map",
+        title: "map(x * x), then filter_map(x / 2 if even)",
+        description: "Units turn into circles once squared by the map, the filter_map drops \
+                      the odd ones and halves the others into diamonds. Shown with --unit-labels",
+        pipeline: || {
+            PipelineSpec::source(10)
+                .map_value_buffered(JitteringDuration::from_millis(500, 2.), 3, |x| x * x)
+                .on_completion(|_| ValueLook {
+                    color: Some(COLORS[0]),
+                    shape: Some(UnitShape::Circle),
+                    ..Default::default()
+                })
+                .filter_map(
+                    JitteringDuration::from_millis(800, 1.),
+                    "x / 2 if even",
+                    |x| (x % 2 == 0).then_some(x / 2),
+                )
+                .on_completion(|_| ValueLook {
+                    color: Some(COLORS[1]),
+                    shape: Some(UnitShape::Diamond),
+                    ..Default::default()
+                })
+                .render(|x| ValueLook {
+                    label: Some(x.to_string()),
+                    ..Default::default()
+                })
+        },
+    },
+    Preset {
+        name: "try-buffered",
+        title: "try_buffered(5), then try_for_each",
+        description: "Futures fail one time in ten and turn red, the first error to reach the \
+                      sink ends the stream and the futures still running are dropped",
+        pipeline: || {
+            PipelineSpec::source(15)
+                .try_buffered(JitteringDuration::from_millis(600, 3.), 5, 0.1)
+                .try_for_each(JitteringDuration::from_millis(300, 1.), 0.05)
+        },
+    },
+    Preset {
+        name: "and-then",
+        title: "try_filter, then and_then",
+        description: "Failed units skip the stages left, and_then runs a future at a time \
+                      until one of them fails and ends the stream",
+        pipeline: || {
+            PipelineSpec::source(10)
+                .try_filter(JitteringDuration::from_millis(400, 1.), 0.7)
+                .and_then(JitteringDuration::from_millis(600, 1.), 0.2)
+        },
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// Every preset's name, title and description, for `--list-presets`
+pub fn list() -> String {
+    let width = PRESETS
+        .iter()
+        .map(|preset| preset.name.len())
+        .max()
+        .unwrap_or_default();
+
+    PRESETS
+        .iter()
+        .map(|preset| {
+            format!(
+                "{:<width$}  {}\n{:<width$}  {}\n",
+                preset.name, preset.title, "", preset.description
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::pipeline_spec::StageSpec;
+
+    #[test]
+    fn names_are_unique() {
+        let names = PRESETS
+            .iter()
+            .map(|preset| preset.name)
+            .collect::<HashSet<_>>();
+        assert_eq!(names.len(), PRESETS.len());
+    }
+
+    #[test]
+    fn default_and_compared_presets_resolve() {
+        assert_eq!(find(DEFAULT_PRESET).unwrap().name, DEFAULT_PRESET);
+        for name in COMPARED_PRESETS {
+            assert_eq!(find(name).unwrap().name, name);
+        }
+        assert!(find("buffer").is_none());
+    }
+
+    #[test]
+    fn presets_describe_their_stages() {
+        let preset = find("two-buffers").unwrap();
+        let spec = (preset.pipeline)();
+        assert_eq!(spec.source, 10);
+        assert!(matches!(
+            spec.stages[..],
+            [
+                StageSpec::MapBuffered { buffered: 5, .. },
+                StageSpec::MapBuffered { buffered: 3, .. }
+            ]
+        ));
+
+        for preset in PRESETS {
+            assert!(!(preset.pipeline)().stages.is_empty(), "{}", preset.name);
+        }
+    }
+
+    #[test]
+    fn lists_every_preset_under_its_name() {
+        let list = list();
+        let lines = list.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2 * PRESETS.len());
+        assert_eq!(lines[0], "buffer-1                 buffered(1)");
+        assert!(lines[1].starts_with("                         One future at a time"));
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::prelude::Resource;
+use crossbeam_channel::Sender;
+use tokio::time::Instant;
+
+use crate::StreamUpdate;
+
+#[derive(Clone, Debug)]
+pub struct RecordedEvent {
+    pub at: Duration,
+    pub update: StreamUpdate,
+}
+
+/// Every update of a run, timestamped relative to the start of the run.
+/// Uses tokio's clock so runs on a paused runtime are recorded in virtual time
+#[derive(Clone, Resource)]
+pub struct EventLog {
+    start: Instant,
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Default::default(),
+        }
+    }
+
+    pub fn record(&self, update: &StreamUpdate) {
+        let at = self.start.elapsed();
+        self.events.lock().unwrap().push(RecordedEvent {
+            at,
+            update: update.clone(),
+        });
+    }
+
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+/// Sends updates to the visualization while recording them in the run's `EventLog`
+#[derive(Clone)]
+pub struct UpdateSender {
+    tx: Sender<StreamUpdate>,
+    log: EventLog,
+}
+
+impl UpdateSender {
+    pub fn new(tx: Sender<StreamUpdate>, log: EventLog) -> Self {
+        Self { tx, log }
+    }
+
+    pub fn send(&self, update: StreamUpdate) {
+        self.log.record(&update);
+        // a headless run drops the receiver, the log is all that's kept
+        _ = self.tx.send(update);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::{
+    future_vis::{FutureState, UnitShape},
+    recording::RecordedEvent,
+    stream_vis::{PipelineLayout, StreamBlock, FILTERED_OUT_RISE},
+    theme::theme,
+    StreamUpdate, UnitValueKind,
+};
+
+/// Duration of the position and fade tweens started by the visualization systems
+pub const TWEEN_DURATION: Duration = Duration::from_secs(1);
+
+/// `EaseFunction::ExponentialOut` as evaluated by bevy_tweening
+pub fn exponential_out(t: f32) -> f32 {
+    if t >= 1. {
+        1.
+    } else {
+        1. - 2f32.powf(-10. * t)
+    }
+}
+
+fn tween_progress(start: Duration, at: Duration) -> f32 {
+    at.saturating_sub(start).as_secs_f32() / TWEEN_DURATION.as_secs_f32()
+}
+
+/// A position tween, eased from `from` to `to` over `TWEEN_DURATION`
+#[derive(Clone, Copy, Debug)]
+pub struct Motion {
+    pub at: Duration,
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+impl Motion {
+    fn position(&self, at: Duration) -> Vec2 {
+        self.from
+            .lerp(self.to, exponential_out(tween_progress(self.at, at)))
+    }
+}
+
+/// Colors of a unit's layers, matching the materials of `spawn_unit`'s children
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnitLook {
+    pub stroke: Color,
+    pub background: Color,
+    pub progress_color: Color,
+    pub progress: f32,
+    pub shape: UnitShape,
+}
+
+impl Default for UnitLook {
+    fn default() -> Self {
+        Self {
+            stroke: Color::BLACK,
+            background: Color::WHITE,
+            progress_color: Color::BLACK,
+            progress: 1.,
+            shape: UnitShape::Square,
+        }
+    }
+}
+
+/// A unit's position and looks over a run, as the visualization would have shown them
+#[derive(Clone, Debug)]
+pub struct UnitTrack {
+    pub id: u32,
+    /// The block the unit is in, as of the last update replayed
+    pub block_id: u32,
+    /// The state of its future there, as of the last update replayed
+    pub future_state: FutureState,
+    /// The payload it's labelled with, as of the last update replayed
+    pub label: Option<String>,
+    pub created: Duration,
+    pub filtered_out: Option<Duration>,
+    /// When it was dropped along with the stream, an error having reached the sink
+    pub dropped: Option<Duration>,
+    pub looks: Vec<(Duration, UnitLook)>,
+    pub motions: Vec<Motion>,
+}
+
+impl UnitTrack {
+    fn new(id: u32, block_id: u32, created: Duration, position: Vec2) -> Self {
+        Self {
+            id,
+            block_id,
+            future_state: FutureState::Pending,
+            label: None,
+            created,
+            filtered_out: None,
+            dropped: None,
+            looks: vec![(created, UnitLook::default())],
+            motions: vec![Motion {
+                at: created,
+                from: position,
+                to: position,
+            }],
+        }
+    }
+
+    /// The motion going on at `at`, possibly over already
+    pub fn motion(&self, at: Duration) -> &Motion {
+        self.motions
+            .iter()
+            .rev()
+            .find(|motion| motion.at <= at)
+            .unwrap_or(&self.motions[0])
+    }
+
+    pub fn position(&self, at: Duration) -> Vec2 {
+        self.motion(at).position(at)
+    }
+
+    pub fn look(&self, at: Duration) -> UnitLook {
+        self.looks
+            .iter()
+            .rev()
+            .find(|(look_at, _)| *look_at <= at)
+            .unwrap_or(&self.looks[0])
+            .1
+    }
+
+    /// When the unit started fading away, filtered out or dropped
+    pub fn faded(&self) -> Option<Duration> {
+        self.filtered_out.or(self.dropped)
+    }
+
+    /// Filtered out and dropped units fade away
+    pub fn opacity(&self, at: Duration) -> f32 {
+        match self.faded() {
+            Some(faded) if faded <= at => 1. - exponential_out(tween_progress(faded, at)),
+            _ => 1.,
+        }
+    }
+
+    /// Restarting an `ExponentialOut` tween towards the same target doesn't change its curve,
+    /// so only new targets start a new motion
+    fn move_to(&mut self, at: Duration, to: Vec2) {
+        let last = self.motions.last().unwrap();
+        if last.to == to {
+            return;
+        }
+
+        let from = self.position(at);
+        self.motions.push(Motion { at, from, to });
+    }
+
+    fn update_look(&mut self, at: Duration, update: impl FnOnce(&mut UnitLook)) {
+        let mut look = self.looks.last().unwrap().1;
+        update(&mut look);
+        self.looks.push((at, look));
+    }
+
+    /// Forget the motions and looks replaced since, keeping only the current ones
+    fn trimmed(&self) -> Self {
+        Self {
+            looks: self.looks[self.looks.len() - 1..].to_vec(),
+            motions: self.motions[self.motions.len() - 1..].to_vec(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Blocks and units of a replay, as of the last update applied
+#[derive(Clone)]
+pub struct Replay {
+    blocks: Vec<(StreamBlock, Vec3)>,
+    units: BTreeMap<u32, UnitTrack>,
+}
+
+impl Replay {
+    /// Start from empty blocks, placed at their translations
+    pub fn new(blocks: Vec<(StreamBlock, Vec3)>) -> Self {
+        Self {
+            blocks,
+            units: BTreeMap::new(),
+        }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &StreamBlock> {
+        self.blocks.iter().map(|(block, _)| block)
+    }
+
+    pub fn units(&self) -> impl Iterator<Item = &UnitTrack> {
+        self.units.values()
+    }
+
+    /// The same state with only the current motion and look of each unit, enough to go on from
+    pub fn trimmed(&self) -> Self {
+        Self {
+            blocks: self.blocks.clone(),
+            units: self
+                .units
+                .iter()
+                .map(|(id, unit)| (*id, unit.trimmed()))
+                .collect(),
+        }
+    }
+
+    /// Apply an update the way `create_units`, `advance_units`, `update_units` and
+    /// `handle_filtered_out` would have animated it
+    pub fn apply(&mut self, event: &RecordedEvent) {
+        let at = event.at;
+        let units = &mut self.units;
+
+        match &event.update {
+            StreamUpdate::Created(created) => {
+                let Some((_, translation)) = self
+                    .blocks
+                    .iter()
+                    .find(|(block, _)| block.id() == created.block_id)
+                else {
+                    return;
+                };
+
+                let mut unit =
+                    UnitTrack::new(created.id, created.block_id, at, translation.truncate());
+                if let UnitValueKind::Label(label) = &created.value {
+                    unit.label = Some(label.clone());
+                }
+                units.insert(created.id, unit);
+            }
+            StreamUpdate::AdvanceBlock(advance) => {
+                let Some(unit) = units.get_mut(&advance.id) else {
+                    return;
+                };
+                unit.block_id = advance.block_id;
+
+                for (block, translation) in self.blocks.iter_mut() {
+                    if block.id() == advance.block_id {
+                        if let Some(target) = block.entry_target(*translation) {
+                            units.get_mut(&advance.id).unwrap().move_to(at, target);
+                        }
+                        block.enter(advance.id);
+                    } else if block.id() == advance.from_block_id {
+                        block.leave(advance.id);
+                    } else {
+                        continue;
+                    }
+
+                    for (id, target) in block.unit_targets(*translation) {
+                        if let Some(unit) = units.get_mut(&id) {
+                            unit.move_to(at, target);
+                        }
+                    }
+                }
+            }
+            StreamUpdate::ChangeValue(change) => {
+                let Some(unit) = units.get_mut(&change.id) else {
+                    return;
+                };
+
+                if let Some(future_state) = FutureState::of(&change.value) {
+                    unit.future_state = future_state;
+                }
+                if let UnitValueKind::Label(label) = &change.value {
+                    unit.label = Some(label.clone());
+                    return;
+                }
+                unit.update_look(at, |look| match change.value {
+                    UnitValueKind::PendingFuture(color) => {
+                        look.stroke = color;
+                        look.background = color.with_a(0.1);
+                        look.progress_color = color;
+                        look.progress = 0.;
+                    }
+                    UnitValueKind::Value(color) => {
+                        look.background = color;
+                        look.progress_color = color;
+                    }
+                    UnitValueKind::Shape(shape) => {
+                        look.shape = shape;
+                    }
+                    UnitValueKind::Label(_) => (),
+                    UnitValueKind::Error => {
+                        let color = theme().error;
+                        look.stroke = color;
+                        look.background = color;
+                        look.progress_color = color;
+                        look.progress = 1.;
+                    }
+                    UnitValueKind::RunningFuture(progress) => {
+                        look.progress = progress;
+                        if progress == 1. {
+                            let color = &mut look.progress_color;
+                            color.set_a(1.);
+                            color.set_l(color.l() * 1.5);
+                            color.set_s(color.s() * 1.5);
+                        }
+                    }
+                });
+            }
+            StreamUpdate::FilteredOut(filtered) => {
+                let Some(unit) = units.get_mut(&filtered.id) else {
+                    return;
+                };
+
+                let position = unit.position(at);
+                unit.move_to(at, position + Vec2::new(0., FILTERED_OUT_RISE));
+                unit.filtered_out = Some(at);
+                unit.update_look(at, fade_look);
+            }
+            StreamUpdate::Dropped(dropped) => {
+                let Some(unit) = units.get_mut(&dropped.id) else {
+                    return;
+                };
+
+                unit.dropped = Some(at);
+                unit.update_look(at, fade_look);
+            }
+        }
+    }
+}
+
+/// Colors of a unit fading away, as `handle_filtered_out` tweens them
+fn fade_look(look: &mut UnitLook) {
+    look.stroke = Color::WHITE;
+    look.background = Color::WHITE;
+    look.progress_color = Color::GRAY;
+}
+
+/// Replay a run's event log from the start
+pub fn replay(layout: &PipelineLayout, events: &[RecordedEvent]) -> Vec<UnitTrack> {
+    let mut replay = Replay::new(
+        layout
+            .blocks
+            .iter()
+            .map(|(block, transform)| (block.clone(), transform.translation))
+            .collect(),
+    );
+
+    for event in events {
+        replay.apply(event);
+    }
+
+    replay.units.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{stream_vis::layout_blocks, timeline::tests::run, UnitValueUpdateEvent};
+
+    use super::*;
+
+    fn tracks() -> Vec<UnitTrack> {
+        let (blocks, events) = run();
+        replay(&layout_blocks(blocks), &events)
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn eases_out_exponentially() {
+        assert_eq!(exponential_out(0.), 0.);
+        assert!(exponential_out(0.5) > 0.95);
+        assert_eq!(exponential_out(1.), 1.);
+        assert_eq!(exponential_out(3.), 1.);
+    }
+
+    #[test]
+    fn units_settle_on_their_last_target() {
+        let [arrived, filtered] = tracks().try_into().unwrap();
+        let created = arrived.position(ms(0));
+        assert_eq!(filtered.position(ms(0)), created);
+
+        // both units head into the map together, and only the first one makes it to the sink
+        let settled = ms(70) + TWEEN_DURATION;
+        assert_ne!(arrived.position(settled), created);
+        assert_ne!(arrived.position(settled), filtered.position(settled));
+        assert_eq!(arrived.position(settled), arrived.position(settled * 2));
+    }
+
+    #[test]
+    fn filtered_out_units_rise_and_fade() {
+        // up to the first unit leaving the map, which would line the map's units up again
+        let (blocks, events) = run();
+        let [_, filtered] = replay(&layout_blocks(blocks), &events[..7])
+            .try_into()
+            .unwrap();
+        assert_eq!(filtered.filtered_out, Some(ms(30)));
+        assert_eq!(filtered.opacity(ms(20)), 1.);
+        assert!(filtered.opacity(ms(300)) < 0.5);
+        assert_eq!(filtered.opacity(ms(30) + TWEEN_DURATION), 0.);
+
+        let rise = filtered.position(ms(30) + TWEEN_DURATION) - filtered.position(ms(30));
+        assert_eq!(rise, Vec2::new(0., FILTERED_OUT_RISE));
+    }
+
+    #[test]
+    fn looks_follow_the_future() {
+        let [arrived, _] = tracks().try_into().unwrap();
+        let progress = arrived
+            .looks
+            .iter()
+            .map(|(at, look)| (at.as_millis(), look.progress))
+            .collect::<Vec<_>>();
+        assert_eq!(progress, [(0, 1.), (20, 0.5), (60, 1.)]);
+    }
+
+    #[test]
+    fn labels_leave_the_looks_alone() {
+        let (blocks, mut events) = run();
+        events.insert(
+            5,
+            RecordedEvent {
+                at: ms(20),
+                update: StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                    id: 1,
+                    value: UnitValueKind::Label("user 42".to_string()),
+                }),
+            },
+        );
+
+        let [arrived, filtered] = replay(&layout_blocks(blocks), &events).try_into().unwrap();
+        assert_eq!(arrived.label.as_deref(), Some("user 42"));
+        assert_eq!(filtered.label, None);
+        assert_eq!(arrived.looks.len(), 3);
+    }
+}
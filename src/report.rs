@@ -0,0 +1,302 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    recording::RecordedEvent,
+    stream_vis::StreamBlock,
+    timeline::{latencies, percentile, run_end, throughput, unit_timelines, UnitTimeline},
+};
+
+/// Write per-unit timings to `<path>.csv` and a summary of the run to `<path>.json`
+pub fn write_report(
+    path: &Path,
+    blocks: &[StreamBlock],
+    events: &[RecordedEvent],
+) -> io::Result<()> {
+    let units = unit_timelines(events, blocks);
+
+    fs::write(path.with_extension("csv"), units_csv(blocks, &units))?;
+
+    let file = BufWriter::new(File::create(path.with_extension("json"))?);
+    serde_json::to_writer_pretty(file, &summary(blocks, events, &units))?;
+
+    Ok(())
+}
+
+fn stages(blocks: &[StreamBlock]) -> Vec<&StreamBlock> {
+    blocks
+        .iter()
+        .filter(|block| !matches!(block, StreamBlock::Source(_) | StreamBlock::Sink(_)))
+        .collect()
+}
+
+/// A row per unit, with a group of columns per stage
+fn units_csv(blocks: &[StreamBlock], units: &[UnitTimeline]) -> String {
+    let stages = stages(blocks);
+
+    let mut csv = String::from("unit,created");
+    for stage in &stages {
+        let id = stage.id();
+        _ = write!(
+            csv,
+            ",block{id}_entered,block{id}_started,block{id}_finished,block{id}_left,block{id}_wait"
+        );
+    }
+    csv.push_str(",filtered_out,failed,dropped,sink,latency\n");
+
+    let cell = |at: Option<Duration>| {
+        at.map(|at| format!("{:.6}", at.as_secs_f64()))
+            .unwrap_or_default()
+    };
+
+    for unit in units {
+        _ = write!(csv, "{},{}", unit.id, cell(Some(unit.created)));
+
+        for stage in &stages {
+            match unit
+                .stages
+                .iter()
+                .find(|visit| visit.block_id == stage.id())
+            {
+                Some(visit) => {
+                    let wait = visit
+                        .finished
+                        .zip(visit.left)
+                        .map(|(finished, left)| left.saturating_sub(finished));
+                    _ = write!(
+                        csv,
+                        ",{},{},{},{},{}",
+                        cell(Some(visit.entered)),
+                        cell(visit.started),
+                        cell(visit.finished),
+                        cell(visit.left),
+                        cell(wait)
+                    );
+                }
+                None => csv.push_str(",,,,,"),
+            }
+        }
+
+        _ = writeln!(
+            csv,
+            ",{},{},{},{},{}",
+            cell(unit.filtered_out),
+            cell(unit.failed),
+            cell(unit.dropped),
+            cell(unit.sink),
+            cell(unit.sink.map(|sink| sink.saturating_sub(unit.created)))
+        );
+    }
+
+    csv
+}
+
+fn block_parameters(block: &StreamBlock) -> Value {
+    match block {
+        StreamBlock::Source(_) => json!({ "id": block.id(), "kind": "source" }),
+        StreamBlock::MapBuffer(buffer) => json!({
+            "id": block.id(),
+            "kind": if buffer.failure_ratio.is_some() { "try_buffered" } else { "map_buffered" },
+            "name": block.name(),
+            "duration_ms": buffer.duration.map(|duration| duration.as_millis() as u64),
+            "buffered": buffer.buffered,
+            "failure_ratio": buffer.failure_ratio,
+        }),
+        StreamBlock::MapBufferUnordered(buffer) => json!({
+            "id": block.id(),
+            "kind": "map_buffer_unordered",
+            "name": block.name(),
+            "duration_ms": buffer.duration.map(|duration| duration.as_millis() as u64),
+            "buffered": buffer.buffered,
+        }),
+        StreamBlock::FilterBlock(filter) => json!({
+            "id": block.id(),
+            "kind": filter.kind.name(),
+            "name": block.name(),
+            "duration_ms": filter.duration.map(|duration| duration.as_millis() as u64),
+            "filter_ratio": filter.filter_ratio,
+            "predicate": filter.predicate,
+            "failure_ratio": filter.failure_ratio,
+        }),
+        StreamBlock::Sink(_) => json!({ "id": block.id(), "kind": "sink" }),
+    }
+}
+
+/// min, mean, max and percentiles, in seconds
+fn distribution(mut values: Vec<f32>) -> Value {
+    if values.is_empty() {
+        return Value::Null;
+    }
+
+    values.sort_by(f32::total_cmp);
+    json!({
+        "count": values.len(),
+        "min": values[0],
+        "mean": values.iter().sum::<f32>() / values.len() as f32,
+        "p50": percentile(&values, 50.),
+        "p90": percentile(&values, 90.),
+        "p95": percentile(&values, 95.),
+        "p99": percentile(&values, 99.),
+        "max": values[values.len() - 1],
+    })
+}
+
+fn summary(blocks: &[StreamBlock], events: &[RecordedEvent], units: &[UnitTimeline]) -> Value {
+    let end = run_end(events);
+    let secs = |duration: Duration| duration.as_secs_f32();
+
+    let arrived = units.iter().filter(|unit| unit.sink.is_some()).count();
+
+    let stages = stages(blocks)
+        .into_iter()
+        .map(|block| {
+            let visits = units
+                .iter()
+                .flat_map(|unit| unit.stages.iter())
+                .filter(|visit| visit.block_id == block.id())
+                .collect::<Vec<_>>();
+
+            let left_or_end = |left: Option<Duration>| left.unwrap_or(end);
+
+            let pending = visits
+                .iter()
+                .map(|visit| {
+                    secs(
+                        visit
+                            .started
+                            .unwrap_or(left_or_end(visit.left))
+                            .saturating_sub(visit.entered),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let running = visits
+                .iter()
+                .filter_map(|visit| {
+                    let started = visit.started?;
+                    Some(secs(
+                        visit
+                            .finished
+                            .unwrap_or(left_or_end(visit.left))
+                            .saturating_sub(started),
+                    ))
+                })
+                .collect::<Vec<_>>();
+            let head_of_line_wait = visits
+                .iter()
+                .filter_map(|visit| {
+                    let finished = visit.finished?;
+                    Some(secs(left_or_end(visit.left).saturating_sub(finished)))
+                })
+                .collect::<Vec<_>>();
+
+            // a filter runs one future at a time
+            let capacity = match block {
+                StreamBlock::MapBuffer(buffer) => buffer.buffered,
+                StreamBlock::MapBufferUnordered(buffer) => buffer.buffered,
+                _ => 1,
+            };
+            let busy = running.iter().sum::<f32>();
+            let utilization = if end.is_zero() {
+                0.
+            } else {
+                busy / (secs(end) * capacity as f32)
+            };
+
+            json!({
+                "block": block_parameters(block),
+                "units": visits.len(),
+                "mean_concurrency": if end.is_zero() { 0. } else { busy / secs(end) },
+                "utilization": utilization,
+                "pending_secs": distribution(pending),
+                "running_secs": distribution(running),
+                "head_of_line_wait_secs": {
+                    "total": head_of_line_wait.iter().sum::<f32>(),
+                    "distribution": distribution(head_of_line_wait),
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "pipeline": blocks.iter().map(block_parameters).collect::<Vec<_>>(),
+        "duration_secs": secs(end),
+        "units": {
+            "created": units.len(),
+            "arrived": arrived,
+            "filtered_out": units.iter().filter(|unit| unit.filtered_out.is_some()).count(),
+            "failed": units.iter().filter(|unit| unit.failed.is_some()).count(),
+            "dropped": units.iter().filter(|unit| unit.dropped.is_some()).count(),
+        },
+        "throughput_per_sec": throughput(units),
+        "latency_secs": distribution(latencies(units)),
+        "stages": stages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::tests::run;
+
+    use super::*;
+
+    #[test]
+    fn writes_a_row_per_unit() {
+        let (blocks, events) = run();
+        let csv = units_csv(&blocks, &unit_timelines(&events, &blocks));
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "unit,created,block2_entered,block2_started,block2_finished,block2_left,\
+                 block2_wait,filtered_out,failed,dropped,sink,latency",
+                "1,0.000000,0.010000,0.020000,0.060000,0.070000,0.010000,,,,0.070000,0.070000",
+                "2,0.000000,0.010000,,,0.030000,,0.030000,,,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn summarizes_the_run() {
+        let (blocks, events) = run();
+        let summary = summary(&blocks, &events, &unit_timelines(&events, &blocks));
+
+        assert_eq!(
+            summary["units"],
+            json!({
+                "created": 2,
+                "arrived": 1,
+                "filtered_out": 1,
+                "failed": 0,
+                "dropped": 0
+            })
+        );
+        assert_eq!(summary["latency_secs"]["p50"], json!(0.07_f32));
+        assert_eq!(summary["throughput_per_sec"], json!(1. / 0.07_f32));
+        assert_eq!(summary["pipeline"][1]["duration_ms"], 500);
+
+        let stage = &summary["stages"][0];
+        assert_eq!(stage["units"], 2);
+        assert_eq!(stage["running_secs"]["count"], 1);
+        assert_eq!(stage["running_secs"]["max"], json!(0.04_f32));
+        assert_eq!(stage["head_of_line_wait_secs"]["total"], json!(0.01_f32));
+    }
+
+    #[test]
+    fn writes_both_files() {
+        let (blocks, events) = run();
+        let dir = tempfile::tempdir().unwrap();
+        write_report(&dir.path().join("run"), &blocks, &events).unwrap();
+
+        let csv = fs::read_to_string(dir.path().join("run.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        let json: Value =
+            serde_json::from_reader(File::open(dir.path().join("run.json")).unwrap()).unwrap();
+        assert_eq!(json["units"]["arrived"], 1);
+    }
+}
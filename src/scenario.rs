@@ -0,0 +1,190 @@
+use std::{fs, path::Path};
+
+use bevy::ecs::system::Resource;
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use crate::{
+    stream_vis::{VIEW_HEIGHT, VIEW_WIDTH},
+    theme::Theme,
+};
+
+/// Everything describing an animation, loaded with `--scenario` from a RON file like
+///
+/// ```text
+/// (
+///     pipelines: [(preset: "buffer-filter", title: "buffered, then filter")],
+///     seed: 7,
+///     window: (width: 800, height: 300),
+///     theme: (background: "#22272e"),
+///     exports: [(format: Gif, path: "docs/buffer-filter.gif")],
+///     tail: 2.0,
+///     captions: true,
+///     unit_labels: true,
+/// )
+/// ```
+///
+/// Every field is optional and `Some` can be left out, command line options take precedence over
+/// the file
+#[derive(Debug, Default, Deserialize, Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// Stacked from the top, the default preset when empty
+    pub pipelines: Vec<ScenarioPipeline>,
+    /// Random when unset
+    pub seed: Option<u64>,
+    /// Size of the view of a single pipeline, the window grows with every stacked one
+    pub window: WindowSize,
+    pub theme: Theme,
+    pub exports: Vec<Export>,
+    /// Seconds the window stays open once every unit reached the sink, before closing on its
+    /// own and writing the exports. Open until closed when unset
+    pub tail: Option<f32>,
+    /// Show the pipelines' titles and descriptions
+    pub captions: bool,
+    /// Show each unit's id, or its payload label, on it
+    pub unit_labels: bool,
+}
+
+/// A preset or a pipeline written in the pipeline language, with an optional caption
+/// replacing the preset's
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioPipeline {
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub pipeline: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for WindowSize {
+    fn default() -> Self {
+        Self {
+            width: VIEW_WIDTH,
+            height: VIEW_HEIGHT,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Export {
+    pub format: ExportFormat,
+    pub path: String,
+}
+
+/// The outputs matching the command line options of the same name
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum ExportFormat {
+    Gif,
+    Trace,
+    Gantt,
+    Svg,
+    Html,
+    Report,
+}
+
+pub fn load(path: &Path) -> Result<Scenario, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    ron::Options::default()
+        .with_default_extension(Extensions::IMPLICIT_SOME)
+        .from_str(&text)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bevy::render::color::Color;
+
+    use super::*;
+
+    fn parse(text: &str) -> Result<Scenario, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scenario.ron");
+        fs::write(&path, text).unwrap();
+        load(&path)
+    }
+
+    #[test]
+    fn loads_the_bundled_scenario() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenarios/compare.ron");
+        let scenario = load(&path).unwrap();
+
+        assert_eq!(scenario.pipelines.len(), 2);
+        assert_eq!(
+            scenario.pipelines[0].preset.as_deref(),
+            Some("buffer-filter")
+        );
+        assert!(scenario.pipelines[0].pipeline.is_none());
+        assert_eq!(
+            scenario.pipelines[1].title.as_deref(),
+            Some("buffer_unordered(5), then filter")
+        );
+        assert_eq!(scenario.seed, Some(42));
+        assert_eq!(
+            (scenario.window.width, scenario.window.height),
+            (800., 300.)
+        );
+        assert_eq!(scenario.theme.background, Color::hex("#22272e").unwrap());
+        assert!(matches!(
+            scenario.exports[..],
+            [
+                Export {
+                    format: ExportFormat::Gif,
+                    ..
+                },
+                Export {
+                    format: ExportFormat::Svg,
+                    ..
+                }
+            ]
+        ));
+        assert_eq!(scenario.tail, Some(2.));
+        assert!(scenario.captions);
+        assert!(scenario.unit_labels);
+    }
+
+    #[test]
+    fn every_field_is_optional() {
+        let scenario = parse("()").unwrap();
+        assert!(scenario.pipelines.is_empty());
+        assert_eq!(scenario.seed, None);
+        assert_eq!(scenario.window.width, VIEW_WIDTH);
+        assert_eq!(scenario.theme.background, Theme::default().background);
+        assert!(scenario.exports.is_empty());
+        assert_eq!(scenario.tail, None);
+        assert!(!scenario.captions);
+        assert!(!scenario.unit_labels);
+
+        let window = parse("(window: (height: 100))").unwrap().window;
+        assert_eq!((window.width, window.height), (VIEW_WIDTH, 100.));
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_colors() {
+        assert!(parse("(speed: 2.0)").unwrap_err().contains("speed"));
+        assert!(parse("(pipelines: [(name: \"filter\")])").is_err());
+        assert!(parse("(theme: (background: \"#2g\"))")
+            .unwrap_err()
+            .contains("invalid color \"#2g\""));
+        assert!(parse("(exports: [(format: Png, path: \"a.png\")])").is_err());
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        assert!(load(Path::new("missing.ron")).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+
+use crate::{
+    history::History, stream_vis::StreamBlock, timeline::percentile, StreamEvent, StreamUpdate,
+};
+
+const TOGGLE_KEY: KeyCode = KeyCode::S;
+const THROUGHPUT_WINDOW_SECS: f32 = 5.;
+const OVERLAY_FONT_SIZE: f32 = 13.;
+
+#[derive(Resource)]
+pub struct StatsOverlay {
+    pub visible: bool,
+}
+
+#[derive(Component)]
+pub struct StatsOverlayNode;
+
+#[derive(Component)]
+pub struct StatsOverlayText;
+
+/// Live numbers of the running pipeline, gathered from `StreamEvent`s
+#[derive(Resource, Default)]
+pub struct PipelineStats {
+    created: HashMap<u32, f32>,
+    unit_blocks: HashMap<u32, u32>,
+    passed: HashMap<u32, u32>,
+    dropped: HashMap<u32, u32>,
+    arrivals: VecDeque<f32>,
+    arrived: usize,
+    latencies: Vec<f32>,
+}
+
+impl PipelineStats {
+    fn in_flight(&self, block_id: u32) -> usize {
+        self.unit_blocks
+            .values()
+            .filter(|unit_block_id| **unit_block_id == block_id)
+            .count()
+    }
+
+    /// Account for an update happening `now` seconds into the run
+    pub fn record(&mut self, update: &StreamUpdate, now: f32, blocks: &[&StreamBlock]) {
+        // one sink per pipeline shown
+        let is_sink = |block_id: u32| {
+            blocks
+                .iter()
+                .any(|block| matches!(block, StreamBlock::Sink(sink) if sink.id == block_id))
+        };
+
+        match update {
+            StreamUpdate::Created(created) => {
+                self.created.insert(created.id, now);
+                self.unit_blocks.insert(created.id, created.block_id);
+            }
+            StreamUpdate::AdvanceBlock(advance) => {
+                // a filter passes its units on unchanged, their `from_block_id` is the block
+                // before it
+                if let Some(from_block_id) = self.unit_blocks.get(&advance.id).copied() {
+                    let from_filter = blocks.iter().any(|block| {
+                        matches!(block, StreamBlock::FilterBlock(filter) if filter.id == from_block_id)
+                    });
+                    if from_filter {
+                        *self.passed.entry(from_block_id).or_default() += 1;
+                    }
+                }
+
+                if is_sink(advance.block_id) {
+                    self.unit_blocks.remove(&advance.id);
+                    self.arrived += 1;
+                    self.arrivals.push_back(now);
+                    if let Some(created) = self.created.remove(&advance.id) {
+                        self.latencies.push(now - created);
+                    }
+                } else {
+                    self.unit_blocks.insert(advance.id, advance.block_id);
+                }
+            }
+            StreamUpdate::FilteredOut(filtered) => {
+                self.created.remove(&filtered.id);
+                if let Some(block_id) = self.unit_blocks.remove(&filtered.id) {
+                    *self.dropped.entry(block_id).or_default() += 1;
+                }
+            }
+            StreamUpdate::Dropped(dropped) => {
+                self.created.remove(&dropped.id);
+                self.unit_blocks.remove(&dropped.id);
+            }
+            StreamUpdate::ChangeValue(_) => (),
+        }
+    }
+
+    /// Drop arrivals too old to count towards the throughput
+    pub fn forget_arrivals(&mut self, now: f32) {
+        while self
+            .arrivals
+            .front()
+            .is_some_and(|arrival| now - arrival > THROUGHPUT_WINDOW_SECS)
+        {
+            self.arrivals.pop_front();
+        }
+    }
+}
+
+pub fn spawn_stats_overlay(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    overlay: Res<StatsOverlay>,
+) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(5.),
+                    left: Val::Px(5.),
+                    padding: UiRect::all(Val::Px(5.)),
+                    ..default()
+                },
+                background_color: Color::BLACK.with_a(0.6).into(),
+                visibility: if overlay.visible {
+                    Visibility::Visible
+                } else {
+                    Visibility::Hidden
+                },
+                ..default()
+            },
+            StatsOverlayNode,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("Virgil.ttf"),
+                        font_size: OVERLAY_FONT_SIZE,
+                        color: Color::WHITE,
+                    },
+                ),
+                StatsOverlayText,
+            ));
+        });
+}
+
+pub fn toggle_stats_overlay(
+    keys: Res<Input<KeyCode>>,
+    mut overlay: ResMut<StatsOverlay>,
+    mut nodes: Query<&mut Visibility, With<StatsOverlayNode>>,
+) {
+    if !keys.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+
+    overlay.visible = !overlay.visible;
+    for mut visibility in nodes.iter_mut() {
+        *visibility = if overlay.visible {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+pub fn collect_stats(
+    mut reader: EventReader<StreamEvent>,
+    history: Res<History>,
+    mut stats: ResMut<PipelineStats>,
+    blocks: Query<&StreamBlock>,
+) {
+    let now = history.shown().as_secs_f32();
+    let blocks = blocks.iter().collect::<Vec<_>>();
+
+    for event in reader.read() {
+        stats.record(&event.0, now, &blocks);
+    }
+
+    stats.forget_arrivals(now);
+}
+
+pub fn update_stats_overlay(
+    overlay: Res<StatsOverlay>,
+    stats: Res<PipelineStats>,
+    blocks: Query<&StreamBlock>,
+    mut text: Query<&mut Text, With<StatsOverlayText>>,
+) {
+    if !overlay.visible {
+        return;
+    }
+
+    let mut blocks = blocks.iter().collect::<Vec<_>>();
+    blocks.sort_by_key(|block| block.id());
+
+    let mut lines = Vec::new();
+    for block in blocks {
+        let in_flight = stats.in_flight(block.id());
+        let line = match block {
+            StreamBlock::Source(_) => format!("source: {in_flight} queued"),
+            StreamBlock::MapBuffer(buffer) => format!(
+                "{}: {in_flight} in flight, occupancy {}/{}",
+                block.name(),
+                buffer.units.len(),
+                buffer.buffered
+            ),
+            StreamBlock::MapBufferUnordered(buffer) => format!(
+                "{}: {in_flight} in flight, occupancy {}/{}",
+                block.name(),
+                buffer.slots.iter().filter(|slot| slot.is_some()).count(),
+                buffer.buffered
+            ),
+            StreamBlock::FilterBlock(filter) => {
+                let passed = stats.passed.get(&filter.id).copied().unwrap_or_default();
+                let dropped = stats.dropped.get(&filter.id).copied().unwrap_or_default();
+                let observed = if passed + dropped > 0 {
+                    format!("{:.0}%", 100. * passed as f32 / (passed + dropped) as f32)
+                } else {
+                    "-".to_string()
+                };
+                let configured = filter
+                    .filter_ratio
+                    .map(|ratio| format!(" (filter_ratio {:.0}%)", ratio * 100.))
+                    .unwrap_or_default();
+
+                format!(
+                    "{}: {in_flight} in flight, passed {observed}{configured}",
+                    block.name()
+                )
+            }
+            StreamBlock::Sink(_) => format!(
+                "sink: {} arrived, {:.2} units/s over the last {}s",
+                stats.arrived,
+                stats.arrivals.len() as f32 / THROUGHPUT_WINDOW_SECS,
+                THROUGHPUT_WINDOW_SECS
+            ),
+        };
+        lines.push(line);
+    }
+
+    let mut latencies = stats.latencies.clone();
+    latencies.sort_by(f32::total_cmp);
+    let latency = |percent| {
+        percentile(&latencies, percent)
+            .map(|secs| format!("{secs:.2}s"))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    lines.push(format!(
+        "latency: p50 {}  p95 {}  p99 {}",
+        latency(50.),
+        latency(95.),
+        latency(99.)
+    ));
+
+    for mut text in text.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use crate::timeline::tests::run;
+
+    use super::*;
+
+    #[test]
+    fn collects_arrivals_and_drops() {
+        let (blocks, events) = run();
+        let mut world = World::new();
+        world.insert_resource(History::new(Vec::new()));
+        world.init_resource::<PipelineStats>();
+        world.init_resource::<Events<StreamEvent>>();
+        for block in blocks {
+            world.spawn(block);
+        }
+        for event in events {
+            world.send_event(StreamEvent(event.update));
+        }
+
+        world.run_system_once(collect_stats);
+
+        let stats = world.resource::<PipelineStats>();
+        assert_eq!(stats.arrived, 1);
+        assert_eq!(stats.latencies.len(), 1);
+        assert_eq!(stats.dropped.get(&2), Some(&1));
+        assert_eq!(stats.in_flight(2), 0);
+        assert!(stats.created.is_empty());
+    }
+}
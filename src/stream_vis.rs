@@ -3,36 +3,45 @@ use std::{collections::VecDeque, time::Duration};
 use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
-    sprite::{Anchor, MaterialMesh2dBundle},
+    sprite::{Anchor, MaterialMesh2dBundle, Mesh2dHandle},
 };
 use bevy_tweening::{
-    lens::{ColorMaterialColorLens, TransformPositionLens},
+    lens::{ColorMaterialColorLens, TextColorLens, TransformPositionLens},
     Animator, AssetAnimator, EaseFunction, Tween,
 };
 
 use crate::{
-    future_vis::{spawn_unit, StreamUnit, UnitBackground, UnitFutureProgress, UnitStroke},
+    future_vis::{
+        spawn_unit, FutureState, StreamUnit, UnitBackground, UnitFutureProgress, UnitLabel,
+        UnitLabels, UnitMeshes, UnitStroke, UNIT_LABEL_COLOR,
+    },
+    replay::UnitLook,
+    theme::theme,
     StreamEvent, StreamUpdate, UnitValueKind,
 };
 
 #[derive(Component, Default, Clone)]
 pub struct BufferBlock {
     pub id: u32,
-    pub duration: Duration,
+    /// Base duration of the block's futures, unknown for futures supplied by the user
+    pub duration: Option<Duration>,
     pub buffered: usize,
+    /// Chance of a unit's future failing, for `try_buffered`
+    pub failure_ratio: Option<f32>,
     pub units: VecDeque<u32>,
 }
 
 #[derive(Component, Default, Clone)]
 pub struct BufferUnrderedBlock {
     pub id: u32,
-    pub duration: Duration,
+    /// Base duration of the block's futures, unknown for futures supplied by the user
+    pub duration: Option<Duration>,
     pub buffered: usize,
     pub slots: VecDeque<Option<u32>>,
 }
 
 impl BufferUnrderedBlock {
-    pub fn new(id: u32, size: usize, duration: Duration, buffered: usize) -> Self {
+    pub fn new(id: u32, size: usize, duration: Option<Duration>, buffered: usize) -> Self {
         Self {
             id,
             duration,
@@ -45,7 +54,41 @@ impl BufferUnrderedBlock {
 #[derive(Component, Default, Clone)]
 pub struct FilterBlock {
     pub id: u32,
-    pub duration: Duration,
+    /// Base duration of the block's futures, unknown for futures supplied by the user
+    pub duration: Option<Duration>,
+    /// Chance of a unit passing the filter, unknown for pipelines streamed by another process
+    pub filter_ratio: Option<f32>,
+    /// Short description of the predicate, for filters deciding on the units' id or payload
+    pub predicate: Option<String>,
+    pub kind: FilterKind,
+    /// Chance of a unit's future failing, for `and_then` and `try_for_each`
+    pub failure_ratio: Option<f32>,
+}
+
+/// The combinator of a filter block, all running their units' futures one at a time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FilterKind {
+    #[default]
+    Filter,
+    /// Maps the payload of the units it lets through
+    FilterMap,
+    TryFilter,
+    /// Maps the payload of every unit, letting them all through
+    AndThen,
+    /// Lets every unit through to the sink
+    TryForEach,
+}
+
+impl FilterKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Filter => "filter",
+            FilterKind::FilterMap => "filter_map",
+            FilterKind::TryFilter => "try_filter",
+            FilterKind::AndThen => "and_then",
+            FilterKind::TryForEach => "try_for_each",
+        }
+    }
 }
 
 #[derive(Component, Clone)]
@@ -58,6 +101,10 @@ pub struct SinkBlock {
     pub id: u32,
 }
 
+/// Dashed line between two blocks
+#[derive(Component)]
+pub struct Divider;
+
 #[derive(Component, Clone)]
 pub enum StreamBlock {
     Source(SourceBlock),
@@ -77,15 +124,226 @@ impl StreamBlock {
             StreamBlock::Sink(block) => block.id,
         }
     }
+
+    /// Shift the block's id, keeping pipelines shown side by side apart
+    pub fn offset_id(&mut self, offset: u32) {
+        match self {
+            StreamBlock::Source(block) => block.id += offset,
+            StreamBlock::MapBuffer(block) => block.id += offset,
+            StreamBlock::MapBufferUnordered(block) => block.id += offset,
+            StreamBlock::FilterBlock(block) => block.id += offset,
+            StreamBlock::Sink(block) => block.id += offset,
+        }
+    }
+
+    /// Sections of the block's label, highlighting its parameters
+    pub fn label_sections(&self) -> Vec<(String, Color)> {
+        let theme = theme();
+        match self {
+            StreamBlock::Source(_) | StreamBlock::Sink(_) => vec![],
+            StreamBlock::MapBuffer(block) => {
+                let (duration, suffix) = duration_label(block.duration);
+                let mut sections = vec![
+                    (".map(".to_string(), theme.text),
+                    (duration, theme.parameter),
+                    (suffix.to_string(), theme.text),
+                    ("\n.buffer(".to_string(), theme.text),
+                    (block.buffered.to_string(), theme.parameter),
+                    (")".to_string(), theme.text),
+                ];
+                if let Some(failure_ratio) = block.failure_ratio {
+                    sections.splice(
+                        2..4,
+                        [
+                            (suffix.trim_end_matches(')').to_string() + ", ", theme.text),
+                            (percent(failure_ratio), theme.parameter),
+                            (" fail)\n.try_buffered(".to_string(), theme.text),
+                        ],
+                    );
+                }
+                sections
+            }
+            StreamBlock::MapBufferUnordered(block) => {
+                let (duration, suffix) = duration_label(block.duration);
+                vec![
+                    (".map(".to_string(), theme.text),
+                    (duration, theme.parameter),
+                    (suffix.to_string(), theme.text),
+                    ("\n.buffered_unordered(".to_string(), theme.text),
+                    (block.buffered.to_string(), theme.parameter),
+                    (")".to_string(), theme.text),
+                ]
+            }
+            StreamBlock::FilterBlock(block) => {
+                let (duration, suffix) = duration_label(block.duration);
+                let mut sections = vec![
+                    (format!(".{}(", block.kind.name()), theme.text),
+                    (duration, theme.parameter),
+                    (suffix.to_string(), theme.text),
+                ];
+                if let Some(predicate) = &block.predicate {
+                    sections.pop();
+                    sections.extend([
+                        (suffix.trim_end_matches(')').to_string() + ",\n", theme.text),
+                        (predicate.clone(), theme.parameter),
+                        (")".to_string(), theme.text),
+                    ]);
+                }
+                if let Some(failure_ratio) = block.failure_ratio {
+                    sections.pop();
+                    sections.extend([
+                        (suffix.trim_end_matches(')').to_string() + ",\n", theme.text),
+                        (percent(failure_ratio), theme.parameter),
+                        (" fail)".to_string(), theme.text),
+                    ]);
+                }
+                sections
+            }
+        }
+    }
+
+    /// Single line description of the block, matching its label
+    pub fn name(&self) -> String {
+        match self {
+            StreamBlock::Source(_) => "source".to_string(),
+            StreamBlock::Sink(_) => "sink".to_string(),
+            _ => self
+                .label_sections()
+                .into_iter()
+                .map(|(text, _)| text.replace(",\n", ", ").replace('\n', ""))
+                .collect(),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            StreamBlock::Source(_) | StreamBlock::Sink(_) => theme().source,
+            StreamBlock::MapBuffer(_) => theme().buffer,
+            StreamBlock::MapBufferUnordered(_) => theme().buffer_unordered,
+            StreamBlock::FilterBlock(_) => theme().filter,
+        }
+    }
+
+    /// Width and height of the block's body, source and sink are drawn as crescents instead
+    pub fn size(&self) -> Option<Vec2> {
+        match self {
+            StreamBlock::Source(_) | StreamBlock::Sink(_) => None,
+            StreamBlock::MapBuffer(_) => Some(Vec2::new(BUFFER_WIDTH, BUFFER_HEIGHT)),
+            StreamBlock::MapBufferUnordered(_) => {
+                Some(Vec2::new(BUFFER_UNORDERED_WIDTH, BUFFER_UNORDERED_HEIGHT))
+            }
+            StreamBlock::FilterBlock(_) => Some(Vec2::new(FILTER_WIDTH, FILTER_HEIGHT)),
+        }
+    }
+
+    /// Update the block's state with a unit entering it
+    pub fn enter(&mut self, unit_id: u32) {
+        match self {
+            StreamBlock::MapBuffer(block_state) => {
+                block_state.units.push_back(unit_id);
+            }
+            StreamBlock::MapBufferUnordered(block_state) => {
+                // put in first non None slot
+                *block_state
+                    .slots
+                    .iter_mut()
+                    .find(|slot| slot.is_none())
+                    .unwrap() = Some(unit_id);
+            }
+            _ => (),
+        }
+    }
+
+    /// Update the block's state with a unit leaving it
+    pub fn leave(&mut self, unit_id: u32) {
+        match self {
+            StreamBlock::MapBuffer(block_state) => {
+                block_state.units.retain(|id| *id != unit_id);
+            }
+            StreamBlock::MapBufferUnordered(block_state) => {
+                block_state.slots.iter_mut().for_each(|slot| {
+                    if let Some(id) = slot {
+                        if *id == unit_id {
+                            *slot = None;
+                        }
+                    }
+                });
+            }
+            _ => (),
+        }
+    }
+
+    /// Middle of the block as drawn
+    pub fn center(&self, block_translation: Vec3) -> Vec2 {
+        let width = self.size().map(|size| size.x).unwrap_or_default();
+        Vec2::new(block_translation.x + width / 2., block_translation.y)
+    }
+
+    /// Where a unit entering a block without a queue of its own moves to
+    pub fn entry_target(&self, block_translation: Vec3) -> Option<Vec2> {
+        match self {
+            StreamBlock::Sink(_) => Some(block_translation.truncate()),
+            StreamBlock::FilterBlock(_) => Some(Vec2::new(
+                block_translation.x + FILTER_WIDTH / 2.,
+                block_translation.y,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Where each unit held by the block should be
+    pub fn unit_targets(&self, block_translation: Vec3) -> Vec<(u32, Vec2)> {
+        match self {
+            StreamBlock::MapBuffer(block_state) => block_state
+                .units
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    let block_br_x = block_translation.x + BUFFER_WIDTH - UNIT_SIZE;
+                    let block_br_y = block_translation.y;
+
+                    let pos_in_block = i as i64;
+
+                    let x = block_br_x - (pos_in_block as f32) * (UNIT_SIZE + 5.);
+                    let y = block_br_y;
+
+                    (*id, Vec2::new(x, y))
+                })
+                .collect(),
+            StreamBlock::MapBufferUnordered(block_state) => block_state
+                .slots
+                .iter()
+                .enumerate()
+                .filter_map(|(i, id)| {
+                    let id = (*id)?;
+
+                    let block_x = block_translation.x + BUFFER_UNORDERED_WIDTH / 2.;
+                    let block_y = block_translation.y + BUFFER_WIDTH / 2.;
+
+                    let pos_in_block = i as i64;
+
+                    let x = block_x;
+                    let y = block_y - (pos_in_block as f32) * (UNIT_SIZE + 5.);
+
+                    Some((id, Vec2::new(x, y)))
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
 }
 
 const BLOCK_PADDING: f32 = 5.;
 const SECTION_MARGIN: f32 = 80.;
 pub const BG_COLOR: Color = Color::rgb(34. / 255.0, 39. / 255.0, 46. / 255.0);
 
-const UNIT_SIZE: f32 = 15.;
+pub const UNIT_SIZE: f32 = 15.;
 pub const SECTION_HEIGHT: f32 = 250.;
 
+// window, centered on the pipeline
+pub const VIEW_WIDTH: f32 = 800.;
+pub const VIEW_HEIGHT: f32 = SECTION_HEIGHT + 50.;
+
 // buffer
 const BUFFER_WIDTH: f32 = 7. * UNIT_SIZE + BLOCK_PADDING * 2.;
 const BUFFER_HEIGHT: f32 = UNIT_SIZE + BLOCK_PADDING * 2.;
@@ -94,22 +352,44 @@ pub const BUFFER_COLOR: Color = Color::rgb(0.95, 0.71, 0.39);
 // buffered unordered
 const BUFFER_UNORDERED_WIDTH: f32 = UNIT_SIZE + BLOCK_PADDING * 2.;
 const BUFFER_UNORDERED_HEIGHT: f32 = 9. * UNIT_SIZE + BLOCK_PADDING * 2.;
-const BUFFER_UNORDERED_COLOR: Color = Color::rgb(0.95, 0.92, 0.56);
+pub const BUFFER_UNORDERED_COLOR: Color = Color::rgb(0.95, 0.92, 0.56);
 
 // filter
-const FILTER_WIDTH: f32 = UNIT_SIZE + BLOCK_PADDING * 2.;
+pub const FILTER_WIDTH: f32 = UNIT_SIZE + BLOCK_PADDING * 2.;
 const FILTER_HEIGHT: f32 = UNIT_SIZE + BLOCK_PADDING * 2.;
-const FILTER_COLOR: Color = Color::rgb(0.62, 0.73, 0.45);
+pub const FILTER_COLOR: Color = Color::rgb(0.62, 0.73, 0.45);
 
 // source/sink
-const SOURCE_RAD: f32 = 50.;
-const SOURCE_COLOR: Color = Color::rgb(0.73, 0.71, 0.78);
+pub const SOURCE_RAD: f32 = 50.;
+pub const SOURCE_COLOR: Color = Color::rgb(0.73, 0.71, 0.78);
+pub const SOURCE_ROTATION: f32 = std::f32::consts::TAU * 0.59;
+pub const SINK_ROTATION: f32 = std::f32::consts::TAU * 0.095;
+
+// divider
+pub const DIVIDER_COLOR: Color = Color::rgba(250. / 255., 240. / 255., 230. / 255., 80. / 255.);
+
+// failed units
+pub const ERROR_COLOR: Color = Color::rgb(0.86, 0.2, 0.2);
+
+// how far a filtered out unit rises before fading away
+pub const FILTERED_OUT_RISE: f32 = FILTER_WIDTH * 1.5;
 
 // text
-const FONT_SIZE: f32 = 16.;
-const TEXT_MARGIN: f32 = 120.;
+pub const FONT_SIZE: f32 = 16.;
+pub const TEXT_MARGIN: f32 = 120.;
+
+fn duration_label(duration: Option<Duration>) -> (String, &'static str) {
+    match duration {
+        Some(duration) => (duration.as_millis().to_string(), "ms)"),
+        None => ("user future".to_string(), ")"),
+    }
+}
+
+fn percent(ratio: f32) -> String {
+    format!("{:.0}%", ratio * 100.)
+}
 
-fn dashed_line(len: f32, segment_len: f32, segment_width: f32) -> Mesh {
+pub fn dashed_line(len: f32, segment_len: f32, segment_width: f32) -> Mesh {
     let segments_count = (len as usize) / (segment_len as usize);
 
     let mut positions = Vec::new();
@@ -139,10 +419,10 @@ fn dashed_line(len: f32, segment_len: f32, segment_width: f32) -> Mesh {
         uvs.push([0., 0.]);
 
         indices.extend_from_slice(&[
-            (0 + i * 2) as u32,
+            (i * 2) as u32,
             (1 + i * 2) as u32,
             (2 + i * 2) as u32,
-            (0 + i * 2) as u32,
+            (i * 2) as u32,
             (2 + i * 2) as u32,
             (3 + i * 2) as u32,
         ]);
@@ -155,7 +435,7 @@ fn dashed_line(len: f32, segment_len: f32, segment_width: f32) -> Mesh {
         .with_indices(Some(Indices::U32(indices)))
 }
 
-fn crecent_mesh(sides: usize, radius: f32) -> Mesh {
+pub fn crecent_mesh(sides: usize, radius: f32) -> Mesh {
     let mut positions = Vec::with_capacity(sides);
     let mut normals = Vec::with_capacity(sides);
     let mut uvs = Vec::with_capacity(sides);
@@ -186,6 +466,19 @@ fn crecent_mesh(sides: usize, radius: f32) -> Mesh {
         .with_indices(Some(Indices::U32(indices)))
 }
 
+fn label_text(block: &StreamBlock, font: Handle<Font>) -> Text {
+    Text::from_sections(block.label_sections().into_iter().map(|(value, color)| {
+        TextSection::new(
+            value,
+            TextStyle {
+                font_size: FONT_SIZE,
+                color,
+                font: font.clone(),
+            },
+        )
+    }))
+}
+
 fn spawn_buffered(
     buffer_block: BufferBlock,
     transform: Transform,
@@ -195,65 +488,14 @@ fn spawn_buffered(
     asset_server: &Res<AssetServer>,
 ) {
     let font_handle = asset_server.load("Virgil.ttf");
+    let block = StreamBlock::MapBuffer(buffer_block);
 
     commands
-        .spawn((
-            StreamBlock::MapBuffer(buffer_block.clone()),
-            SpatialBundle::from_transform(transform),
-        ))
+        .spawn((block.clone(), SpatialBundle::from_transform(transform)))
         .with_children(|parent| {
             parent.spawn(Text2dBundle {
                 text_anchor: Anchor::Center,
-                text: Text::from_sections([
-                    TextSection::new(
-                        ".map(",
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        buffer_block.duration.as_millis().to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::RED,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        "ms)",
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        "\n.buffer(".to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        buffer_block.buffered.to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::RED,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        ")".to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle,
-                        },
-                    ),
-                ]),
+                text: label_text(&block, font_handle),
                 transform: Transform::from_translation(Vec3::new(
                     BUFFER_WIDTH / 2.,
                     -TEXT_MARGIN,
@@ -272,7 +514,7 @@ fn spawn_buffered(
                         .into(),
                     )
                     .into(),
-                material: materials.add(ColorMaterial::from(BUFFER_COLOR)),
+                material: materials.add(ColorMaterial::from(theme().buffer)),
                 ..default()
             });
         });
@@ -286,65 +528,15 @@ fn spawn_buffer_unordered(
     materials: &mut ResMut<Assets<ColorMaterial>>,
     asset_server: &Res<AssetServer>,
 ) {
+    let block = StreamBlock::MapBufferUnordered(block);
+
     commands
-        .spawn((
-            StreamBlock::MapBufferUnordered(block.clone()),
-            SpatialBundle::from_transform(transform),
-        ))
+        .spawn((block.clone(), SpatialBundle::from_transform(transform)))
         .with_children(|parent| {
             let font_handle = asset_server.load("Virgil.ttf");
             parent.spawn(Text2dBundle {
                 text_anchor: Anchor::Center,
-                text: Text::from_sections([
-                    TextSection::new(
-                        ".map(",
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        block.duration.as_millis().to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::RED,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        "ms)",
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        "\n.buffered_unordered(".to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        block.buffered.to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::RED,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        ")".to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle,
-                        },
-                    ),
-                ]),
+                text: label_text(&block, font_handle),
                 transform: Transform::from_translation(Vec3::new(
                     BUFFER_UNORDERED_WIDTH / 2.,
                     -TEXT_MARGIN,
@@ -363,7 +555,7 @@ fn spawn_buffer_unordered(
                         .into(),
                     )
                     .into(),
-                material: materials.add(ColorMaterial::from(BUFFER_UNORDERED_COLOR)),
+                material: materials.add(ColorMaterial::from(theme().buffer_unordered)),
                 ..default()
             });
         });
@@ -377,41 +569,15 @@ fn spawn_filter(
     materials: &mut ResMut<Assets<ColorMaterial>>,
     asset_server: &Res<AssetServer>,
 ) {
+    let block = StreamBlock::FilterBlock(block);
+
     commands
-        .spawn((
-            StreamBlock::FilterBlock(block.clone()),
-            SpatialBundle::from_transform(transform),
-        ))
+        .spawn((block.clone(), SpatialBundle::from_transform(transform)))
         .with_children(|parent| {
             let font_handle = asset_server.load("Virgil.ttf");
             parent.spawn(Text2dBundle {
                 text_anchor: Anchor::Center,
-                text: Text::from_sections([
-                    TextSection::new(
-                        ".filter(",
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        block.duration.as_millis().to_string(),
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::RED,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                    TextSection::new(
-                        "ms)",
-                        TextStyle {
-                            font_size: FONT_SIZE,
-                            color: Color::WHITE,
-                            font: font_handle.clone(),
-                        },
-                    ),
-                ]),
+                text: label_text(&block, font_handle),
                 transform: Transform::from_translation(Vec3::new(
                     FILTER_WIDTH / 2.,
                     -TEXT_MARGIN,
@@ -424,13 +590,13 @@ fn spawn_filter(
                 mesh: meshes
                     .add(
                         shape::Box::from_corners(
-                            Vec3::new(0., -1. * FILTER_HEIGHT / 2., 0.),
+                            Vec3::new(0., -FILTER_HEIGHT / 2., 0.),
                             Vec3::new(FILTER_WIDTH, FILTER_HEIGHT / 2., 0.),
                         )
                         .into(),
                     )
                     .into(),
-                material: materials.add(ColorMaterial::from(FILTER_COLOR)),
+                material: materials.add(ColorMaterial::from(theme().filter)),
                 transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
                 ..default()
             });
@@ -456,11 +622,11 @@ fn spawn_source(
         ))
         .with_children(|parent| {
             let mut transform = Transform::from_translation(Vec3::new(0., 0., 200.));
-            transform.rotate_z(std::f32::consts::TAU * 0.59);
+            transform.rotate_z(SOURCE_ROTATION);
 
             parent.spawn(MaterialMesh2dBundle {
                 mesh: meshes.add(mesh).into(),
-                material: materials.add(ColorMaterial::from(SOURCE_COLOR)),
+                material: materials.add(ColorMaterial::from(theme().source)),
                 transform,
                 ..default()
             });
@@ -479,14 +645,14 @@ fn spawn_sink(
 
     let mesh = crecent_mesh(sides, radius);
 
-    let mut transform = transform.clone();
+    let mut transform = transform;
     transform.translation.z = 100.;
-    transform.rotate_z(std::f32::consts::TAU * 0.095);
+    transform.rotate_z(SINK_ROTATION);
 
     commands.spawn((
         MaterialMesh2dBundle {
             mesh: meshes.add(mesh).into(),
-            material: materials.add(ColorMaterial::from(SOURCE_COLOR)),
+            material: materials.add(ColorMaterial::from(theme().source)),
             transform,
             ..default()
         },
@@ -500,86 +666,137 @@ fn spawn_divider(
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
 ) {
-    commands.spawn(MaterialMesh2dBundle {
-        mesh: meshes.add(dashed_line(SECTION_HEIGHT, 5., 2.)).into(),
-        material: materials.add(ColorMaterial::from(Color::rgba_u8(250, 240, 230, 80))),
-        transform,
-        ..default()
-    });
+    commands.spawn((
+        Divider,
+        MaterialMesh2dBundle {
+            mesh: meshes.add(dashed_line(SECTION_HEIGHT, 5., 2.)).into(),
+            material: materials.add(ColorMaterial::from(theme().divider)),
+            transform,
+            ..default()
+        },
+    ));
+}
+
+/// Where every block of a pipeline, and every divider between them, is placed
+pub struct PipelineLayout {
+    pub blocks: Vec<(StreamBlock, Transform)>,
+    pub dividers: Vec<Transform>,
+    pub end: f32,
+}
+
+pub fn layout_blocks(blocks: Vec<StreamBlock>) -> PipelineLayout {
+    let start_pos = Vec3::new(0., 0., 0.);
+    let mut transform = Transform::from_translation(start_pos);
+
+    let mut layout = PipelineLayout {
+        blocks: Vec::new(),
+        dividers: Vec::new(),
+        end: 0.,
+    };
+
+    for block in blocks {
+        match block {
+            StreamBlock::Source(_) => {
+                layout.blocks.push((block, transform));
+
+                transform.translation += Vec3::new(SECTION_MARGIN, 0., 0.);
+                layout.dividers.push(transform);
+            }
+            StreamBlock::MapBuffer(_) => {
+                transform.translation += Vec3::new(SECTION_MARGIN / 2., 0., 0.);
+
+                layout.blocks.push((block, transform));
+                transform.translation += Vec3::new(SECTION_MARGIN / 2. + BUFFER_WIDTH, 0., 0.);
+
+                layout.dividers.push(transform);
+            }
+            StreamBlock::MapBufferUnordered(_) => {
+                transform.translation += Vec3::new(SECTION_MARGIN, 0., 0.);
+
+                layout.blocks.push((block, transform));
+
+                transform.translation += Vec3::new(SECTION_MARGIN + BUFFER_UNORDERED_WIDTH, 0., 0.);
+                layout.dividers.push(transform);
+            }
+            StreamBlock::FilterBlock(_) => {
+                transform.translation += Vec3::new(SECTION_MARGIN / 2., 0., 0.);
+
+                layout.blocks.push((block, transform));
+
+                transform.translation += Vec3::new(SECTION_MARGIN / 2. + FILTER_WIDTH, 0., 0.);
+                layout.dividers.push(transform);
+            }
+            StreamBlock::Sink(_) => {
+                transform.translation += Vec3::new(SECTION_MARGIN, 0., 0.);
+
+                layout.blocks.push((block, transform));
+            }
+        }
+    }
+
+    layout.end = transform.translation.x;
+    layout
 }
 
 pub fn spawn_blocks(
     blocks: Vec<StreamBlock>,
+    origin: Vec3,
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
-    assets_server: Res<AssetServer>,
+    assets_server: &Res<AssetServer>,
 ) -> f32 {
-    let start_pos = Vec3::new(0., 0., 0.);
-    let mut transform = Transform::from_translation(start_pos);
+    let layout = layout_blocks(blocks);
+
+    for (block, mut transform) in layout.blocks {
+        transform.translation += origin;
 
-    for block in blocks {
         match block {
             StreamBlock::Source(block) => {
                 spawn_source(block, transform, commands, meshes, materials);
-
-                transform.translation += Vec3::new(SECTION_MARGIN, 0., 0.);
-                spawn_divider(transform, commands, meshes, materials);
             }
             StreamBlock::MapBuffer(map_buffer_block) => {
-                transform.translation += Vec3::new(SECTION_MARGIN / 2., 0., 0.);
-
                 spawn_buffered(
                     map_buffer_block,
                     transform,
                     commands,
                     meshes,
                     materials,
-                    &assets_server,
+                    assets_server,
                 );
-                transform.translation += Vec3::new(SECTION_MARGIN / 2. + BUFFER_WIDTH, 0., 0.);
-
-                spawn_divider(transform, commands, meshes, materials);
             }
             StreamBlock::MapBufferUnordered(map_buffer_block) => {
-                transform.translation += Vec3::new(SECTION_MARGIN, 0., 0.);
-
                 spawn_buffer_unordered(
                     map_buffer_block,
                     transform,
                     commands,
                     meshes,
                     materials,
-                    &assets_server,
+                    assets_server,
                 );
-
-                transform.translation += Vec3::new(SECTION_MARGIN + BUFFER_UNORDERED_WIDTH, 0., 0.);
-                spawn_divider(transform, commands, meshes, materials);
             }
             StreamBlock::FilterBlock(filter) => {
-                transform.translation += Vec3::new(SECTION_MARGIN / 2., 0., 0.);
-
                 spawn_filter(
                     filter,
                     transform,
                     commands,
                     meshes,
                     materials,
-                    &assets_server,
+                    assets_server,
                 );
-
-                transform.translation += Vec3::new(SECTION_MARGIN / 2. + FILTER_WIDTH, 0., 0.);
-                spawn_divider(transform, commands, meshes, materials);
             }
             StreamBlock::Sink(block) => {
-                transform.translation += Vec3::new(SECTION_MARGIN, 0., 0.);
-
                 spawn_sink(block, transform, commands, meshes, materials);
             }
         }
     }
 
-    transform.translation.x
+    for mut transform in layout.dividers {
+        transform.translation += origin;
+        spawn_divider(transform, commands, meshes, materials);
+    }
+
+    layout.end
 }
 
 pub fn handle_filtered_out(
@@ -589,23 +806,26 @@ pub fn handle_filtered_out(
     unit_strokes: Query<Entity, With<UnitStroke>>,
     unit_background: Query<Entity, With<UnitBackground>>,
     unit_future_progress: Query<Entity, With<UnitFutureProgress>>,
+    unit_labels: Query<Entity, With<UnitLabel>>,
 ) {
-    if reader.len() == 0 {
+    if reader.is_empty() {
         return;
     }
 
     let events = reader.read().collect::<Vec<_>>();
 
-    let filtered_out_events = events.iter().filter_map(|event| match event.0 {
-        StreamUpdate::FilteredOut(ref event) => Some(event),
+    // filtered out units rise as they fade away, dropped ones fade where they are
+    let faded_events = events.iter().filter_map(|event| match event.0 {
+        StreamUpdate::FilteredOut(ref event) => Some((event.id, FILTERED_OUT_RISE)),
+        StreamUpdate::Dropped(ref event) => Some((event.id, 0.)),
         _ => None,
     });
 
-    for event in filtered_out_events {
-        log::debug!("handling filtered out event {}", event.id);
+    for (id, rise) in faded_events {
+        log::debug!("handling filtered out or dropped event {}", id);
         let (entity, _, unit_transform, children) = units
             .iter_mut()
-            .find(|(_, unit, _, _)| unit.id == event.id)
+            .find(|(_, unit, _, _)| unit.id == id)
             .unwrap();
 
         let pos_tween = Tween::new(
@@ -619,7 +839,7 @@ pub fn handle_filtered_out(
                 ),
                 end: glam::Vec3::new(
                     unit_transform.translation.x,
-                    unit_transform.translation.y + FILTER_WIDTH * 1.5,
+                    unit_transform.translation.y + rise,
                     10.,
                 ),
             },
@@ -670,6 +890,20 @@ pub fn handle_filtered_out(
                     .entity(entity)
                     .insert(AssetAnimator::new(color_tween));
             }
+
+            if let Ok(entity) = unit_labels.get(*child) {
+                let color_tween = Tween::new(
+                    EaseFunction::ExponentialOut,
+                    Duration::from_secs(1),
+                    TextColorLens {
+                        start: UNIT_LABEL_COLOR,
+                        end: UNIT_LABEL_COLOR.with_a(0.),
+                        section: 0,
+                    },
+                );
+
+                commands.entity(entity).insert(Animator::new(color_tween));
+            }
         }
 
         commands.entity(entity).insert(Animator::new(pos_tween));
@@ -690,7 +924,7 @@ pub fn advance_units(
         Without<StreamBlock>,
     >,
 ) {
-    if reader.len() == 0 {
+    if reader.is_empty() {
         return;
     }
 
@@ -705,10 +939,10 @@ pub fn advance_units(
 
     let mut blocks = blocks.iter_mut().collect::<Vec<_>>();
 
-    blocks.sort_by_key(|(block, _)| -1 * block.id() as i64);
+    blocks.sort_by_key(|(block, _)| -(block.id() as i64));
 
     for (block, block_transform) in blocks.iter_mut() {
-        let block_id = block.id().clone();
+        let block_id = block.id();
 
         let cur_advance_block_events = advance_block_events
             .iter()
@@ -728,154 +962,59 @@ pub fn advance_units(
                 event.block_id
             );
 
-            let (_, mut unit, _) = units
+            let (entity, mut unit, unit_transform) = units
                 .iter_mut()
                 .find(|(_, unit, _)| unit.id == event.id)
                 .unwrap();
 
-            unit.cur_block = event.block_id.clone();
-
-            match block.as_mut() {
-                StreamBlock::Sink(_) => {
-                    let (entity, _, unit_transform) = units
-                        .iter_mut()
-                        .find(|(_, unit, _)| unit.id == event.id)
-                        .unwrap();
-
-                    let tween = Tween::new(
-                        EaseFunction::ExponentialOut,
-                        Duration::from_secs(1),
-                        TransformPositionLens {
-                            start: Vec3::new(
-                                unit_transform.translation.x,
-                                unit_transform.translation.y,
-                                10.,
-                            ),
-                            end: Vec3::new(
-                                block_transform.translation.x,
-                                block_transform.translation.y,
-                                10.,
-                            ),
-                        },
-                    );
-                    commands.entity(entity).insert(Animator::new(tween));
-                }
+            unit.cur_block = event.block_id;
 
-                StreamBlock::FilterBlock(_) => {
-                    let (entity, _, unit_transform) = units
-                        .iter_mut()
-                        .find(|(_, unit, _)| unit.id == event.id)
-                        .unwrap();
-
-                    let tween = Tween::new(
-                        EaseFunction::ExponentialOut,
-                        Duration::from_secs(1),
-                        TransformPositionLens {
-                            start: Vec3::new(
-                                unit_transform.translation.x,
-                                unit_transform.translation.y,
-                                10.,
-                            ),
-                            end: Vec3::new(
-                                block_transform.translation.x + FILTER_WIDTH / 2.,
-                                block_transform.translation.y,
-                                10.,
-                            ),
-                        },
-                    );
-                    commands.entity(entity).insert(Animator::new(tween));
-                }
+            // a failed unit is drawn over the units already in the sink, marking the error that
+            // ended the stream
+            let failed = unit.future_state == FutureState::Failed
+                || events.iter().any(|update| {
+                    matches!(update.0, StreamUpdate::ChangeValue(ref change)
+                        if change.id == event.id && matches!(change.value, UnitValueKind::Error))
+                });
+            let z = if failed { 11. } else { 10. };
 
-                StreamBlock::MapBuffer(ref mut block_state) => {
-                    block_state.units.push_back(unit.id);
-                }
-                StreamBlock::MapBufferUnordered(ref mut block_state) => {
-                    // put in first non None slot
-                    *block_state
-                        .slots
-                        .iter_mut()
-                        .find(|slot| slot.is_none())
-                        .unwrap() = Some(unit.id);
-                }
-                _ => (),
+            if let Some(target) = block.entry_target(block_transform.translation) {
+                let tween = Tween::new(
+                    EaseFunction::ExponentialOut,
+                    Duration::from_secs(1),
+                    TransformPositionLens {
+                        start: Vec3::new(
+                            unit_transform.translation.x,
+                            unit_transform.translation.y,
+                            10.,
+                        ),
+                        end: target.extend(z),
+                    },
+                );
+                commands.entity(entity).insert(Animator::new(tween));
             }
+
+            block.enter(unit.id);
         }
 
         for event in unit_leave_block_events.iter() {
-            match block.as_mut() {
-                StreamBlock::MapBuffer(ref mut block_state) => {
-                    block_state.units.retain(|id| *id != event.id);
-                }
-                StreamBlock::MapBufferUnordered(ref mut block_state) => {
-                    block_state.slots.iter_mut().for_each(|slot| {
-                        if let Some(id) = slot {
-                            if *id == event.id {
-                                *slot = None;
-                            }
-                        }
-                    });
-                }
-                _ => (),
-            }
+            block.leave(event.id);
         }
 
         // adjust positions after updates
-        match block.as_mut() {
-            StreamBlock::MapBuffer(ref mut block_state) => {
-                for (i, id) in block_state.units.iter().enumerate() {
-                    let (entity, _, transform) = units
-                        .iter_mut()
-                        .find(|(_, unit, _)| unit.id == *id)
-                        .unwrap();
-
-                    let block_br_x = block_transform.translation.x + BUFFER_WIDTH - UNIT_SIZE;
-                    let block_br_y = block_transform.translation.y;
-
-                    let pos_in_block = i as i64;
-
-                    let x = block_br_x - (pos_in_block as f32) * (UNIT_SIZE + 5.);
-                    let y = block_br_y;
-
-                    let tween = Tween::new(
-                        EaseFunction::ExponentialOut,
-                        Duration::from_secs(1),
-                        TransformPositionLens {
-                            start: transform.translation,
-                            end: Vec3::new(x, y, transform.translation.z),
-                        },
-                    );
-                    commands.entity(entity).insert(Animator::new(tween));
-                }
-            }
-            StreamBlock::MapBufferUnordered(ref mut block_state) => {
-                for (i, id) in block_state.slots.iter().enumerate() {
-                    if let Some(id) = id {
-                        let (entity, _, transform) = units
-                            .iter_mut()
-                            .find(|(_, unit, _)| unit.id == *id)
-                            .unwrap();
-
-                        let block_x = block_transform.translation.x + BUFFER_UNORDERED_WIDTH / 2.;
-                        let block_y = block_transform.translation.y + BUFFER_WIDTH / 2.;
-
-                        let pos_in_block = i as i64;
-
-                        let x = block_x;
-                        let y = block_y - (pos_in_block as f32) * (UNIT_SIZE + 5.);
-
-                        let tween = Tween::new(
-                            EaseFunction::ExponentialOut,
-                            Duration::from_secs(1),
-                            TransformPositionLens {
-                                start: transform.translation,
-                                end: Vec3::new(x, y, transform.translation.z),
-                            },
-                        );
-                        commands.entity(entity).insert(Animator::new(tween));
-                    }
-                }
-            }
-            _ => (),
+        for (id, target) in block.unit_targets(block_transform.translation) {
+            let (entity, _, transform) =
+                units.iter_mut().find(|(_, unit, _)| unit.id == id).unwrap();
+
+            let tween = Tween::new(
+                EaseFunction::ExponentialOut,
+                Duration::from_secs(1),
+                TransformPositionLens {
+                    start: transform.translation,
+                    end: target.extend(transform.translation.z),
+                },
+            );
+            commands.entity(entity).insert(Animator::new(tween));
         }
     }
 }
@@ -883,9 +1022,10 @@ pub fn advance_units(
 pub fn create_units(
     mut commands: Commands,
     mut reader: EventReader<StreamEvent>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    meshes: Res<UnitMeshes>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut blocks: Query<(&mut StreamBlock, &Transform)>,
+    labels: Option<Res<UnitLabels>>,
 ) {
     let events = reader.read().collect::<Vec<_>>();
 
@@ -907,20 +1047,30 @@ pub fn create_units(
         let x = block_transform.translation.x;
         let y = block_transform.translation.y;
 
+        let label = match &event.value {
+            UnitValueKind::Label(label) => Some(label.as_str()),
+            _ => None,
+        };
+
         spawn_unit(
             &mut commands,
-            &mut meshes,
+            &meshes,
             &mut materials,
-            event.id,
-            block.id().clone(),
+            StreamUnit::new(event.id, block.id()),
             Transform::from_translation(Vec3::new(x, y, 10.)),
+            &UnitLook::default(),
+            labels
+                .as_ref()
+                .map(|labels| labels.text(event.id, block.id(), label, UNIT_LABEL_COLOR)),
         );
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn update_units(
     mut reader: EventReader<StreamEvent>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    meshes: Res<UnitMeshes>,
     mut units: Query<(&mut StreamUnit, &Children)>,
     mut unit_strokes: Query<(&mut Visibility, &Handle<ColorMaterial>), With<UnitStroke>>,
     unit_background: Query<&Handle<ColorMaterial>, With<UnitBackground>>,
@@ -928,6 +1078,15 @@ pub fn update_units(
         (&mut Transform, &Handle<ColorMaterial>),
         With<UnitFutureProgress>,
     >,
+    mut unit_labels: Query<&mut Text, With<UnitLabel>>,
+    mut unit_meshes: Query<
+        (&mut Mesh2dHandle, Has<UnitStroke>),
+        Or<(
+            With<UnitStroke>,
+            With<UnitBackground>,
+            With<UnitFutureProgress>,
+        )>,
+    >,
 ) {
     let events = reader.read().collect::<Vec<_>>();
 
@@ -943,11 +1102,15 @@ pub fn update_units(
             event.value
         );
 
-        let (_, children) = units
+        let (mut unit, children) = units
             .iter_mut()
             .find(|(unit, _)| unit.id == event.id)
             .unwrap();
 
+        if let Some(future_state) = FutureState::of(&event.value) {
+            unit.future_state = future_state;
+        }
+
         match event.value {
             UnitValueKind::PendingFuture(color) => {
                 for child in children {
@@ -975,6 +1138,49 @@ pub fn update_units(
                     if let Ok(background) = unit_background.get(*child) {
                         materials.get_mut(background).unwrap().color = value;
                     }
+
+                    if let Ok((_, progress_matrial)) = unit_future_progress.get_mut(*child) {
+                        materials.get_mut(progress_matrial).unwrap().color = value;
+                    }
+                }
+            }
+            UnitValueKind::Shape(shape) => {
+                let shape = meshes.of(shape);
+                for child in children {
+                    if let Ok((mut mesh, is_stroke)) = unit_meshes.get_mut(*child) {
+                        mesh.0 = if is_stroke {
+                            shape.stroke.clone()
+                        } else {
+                            shape.body.clone()
+                        };
+                    }
+                }
+            }
+            UnitValueKind::Error => {
+                let color = theme().error;
+                for child in children {
+                    if let Ok((_, stroke_material)) = unit_strokes.get(*child) {
+                        materials.get_mut(stroke_material).unwrap().color = color;
+                    }
+
+                    if let Ok(background) = unit_background.get(*child) {
+                        materials.get_mut(background).unwrap().color = color;
+                    }
+
+                    if let Ok((mut progress_transform, progress_matrial)) =
+                        unit_future_progress.get_mut(*child)
+                    {
+                        materials.get_mut(progress_matrial).unwrap().color = color;
+                        progress_transform.scale.y = 1.;
+                        progress_transform.translation.y = 0.;
+                    }
+                }
+            }
+            UnitValueKind::Label(ref label) => {
+                for child in children {
+                    if let Ok(mut text) = unit_labels.get_mut(*child) {
+                        text.sections[0].value = label.clone();
+                    }
                 }
             }
             UnitValueKind::RunningFuture(progress) => {
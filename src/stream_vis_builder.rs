@@ -1,21 +1,33 @@
-use std::time::Duration;
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use bevy::render::color::Color;
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crossbeam_channel::{bounded, Receiver};
 use futures_util::{
-    future::BoxFuture,
-    stream::{self, BoxStream, StreamExt},
+    future::{self, BoxFuture, FutureExt},
+    stream::{self, BoxStream, StreamExt, TryStreamExt},
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{runtime, sync::oneshot};
 
 use crate::{
+    future_vis::UnitShape,
+    recording::{EventLog, RecordedEvent, UpdateSender},
     stream_vis::{
-        BufferBlock, BufferUnrderedBlock, FilterBlock, SinkBlock, SourceBlock, StreamBlock,
+        BufferBlock, BufferUnrderedBlock, FilterBlock, FilterKind, SinkBlock, SourceBlock,
+        StreamBlock,
     },
-    FilteredOutEvent, StreamUpdate, StreamedUnit, UnitAdvanceBlockEvent, UnitCreatedEvent,
-    UnitValueKind, UnitValueUpdateEvent,
+    theme::theme,
+    DroppedEvent, FilteredOutEvent, StreamUpdate, StreamedUnit, UnitAdvanceBlockEvent,
+    UnitCreatedEvent, UnitValueKind, UnitValueUpdateEvent,
 };
 
-const COLORS: [Color; 4] = [
+pub const COLORS: [Color; 4] = [
     Color::rgb(0.50, 0.27, 0.45),
     Color::rgb(0.66, 0.39, 0.39),
     Color::rgb(0.61, 0.27, 0.27),
@@ -36,37 +48,294 @@ impl JitteringDuration {
         }
     }
 
-    pub fn get(&self) -> Duration {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
         let jitter = self.duration.mul_f32(self.jitter);
-        let delta = jitter.mul_f32(rand::random::<f32>());
+        let delta = jitter.mul_f32(rng.gen::<f32>());
         self.duration + delta
     }
 }
 
-pub struct StreamVisBuilder {
-    stream: BoxStream<'static, StreamedUnit>,
+/// Randomness of a pipeline, drawn separately for every unit in every block. Pipelines built
+/// with the same seed sample the same durations and filter outcomes for the same unit, no
+/// matter in which order their combinators poll the futures
+#[derive(Clone, Copy)]
+pub struct Sampler {
+    seed: u64,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    pub fn rng(&self, block_id: u32, unit_id: u32) -> StdRng {
+        let key = (block_id as u64) << 32 | unit_id as u64;
+        StdRng::seed_from_u64(self.seed ^ key.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+}
+
+/// Handle given to user supplied futures, used to report their progress to the visualization
+pub struct Progress {
+    unit_id: u32,
+    block_id: u32,
+    tx: UpdateSender,
+    phases: i32,
+}
+
+impl Progress {
+    /// Set the progress of the running future, between 0 and 1.
+    /// Completion is reported once the future resolves, so the value is kept below 1
+    pub fn set(&self, progress: f32) {
+        log::trace!(
+            "progress for unit({}) buffer({}) set to {}",
+            self.unit_id,
+            self.block_id,
+            progress
+        );
+
+        self.tx
+            .send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id: self.unit_id,
+                value: UnitValueKind::RunningFuture(progress.clamp(0., 0.99)),
+            }));
+    }
+
+    /// Mark the start of a named phase of the future. The total number of phases is unknown,
+    /// so every new phase moves the progress half way towards completion
+    pub fn phase(&mut self, name: &str) {
+        log::debug!(
+            "unit({}) buffer({}) entered phase {}",
+            self.unit_id,
+            self.block_id,
+            name
+        );
+
+        self.phases += 1;
+        self.set(1. - 0.5_f32.powi(self.phases));
+    }
+}
+
+/// A running pipeline: its blocks, the updates it sends, and the log recording them
+pub struct StreamPipeline {
+    pub blocks: Vec<StreamBlock>,
+    pub rx: Receiver<StreamUpdate>,
+    pub log: EventLog,
+    /// Stops the runtime streaming the units, unset for pipelines streamed by another process
+    pub stop: Option<StopHandle>,
+}
+
+/// Stops the runtime started by `StreamVisBuilder::sink` when stopped or dropped, cancelling
+/// the pipeline's futures
+pub struct StopHandle(oneshot::Sender<()>);
+
+impl StopHandle {
+    pub fn stop(self) {
+        _ = self.0.send(());
+    }
+}
+
+/// A unit whose future failed, on its way to the sink in place of its payload
+#[derive(Clone, Copy, Debug)]
+pub struct Failure {
+    pub id: u32,
+    /// The block its future failed in
+    pub block_id: u32,
+}
+
+/// A unit going down the stream, an error once one of its futures failed
+type Unit<T> = Result<StreamedUnit<T>, Failure>;
+
+/// How a unit's payload is shown, see `StreamVisBuilder::render` and `on_completion`
+#[derive(Clone, Debug, Default)]
+pub struct ValueLook {
+    /// Fill of the unit once its future is done
+    pub color: Option<Color>,
+    pub shape: Option<UnitShape>,
+    /// Shown on the unit in place of its id, with `--unit-labels`
+    pub label: Option<String>,
+}
+
+impl ValueLook {
+    /// `self`, with what it leaves unset taken from `other`
+    pub fn or(self, other: ValueLook) -> ValueLook {
+        ValueLook {
+            color: self.color.or(other.color),
+            shape: self.shape.or(other.shape),
+            label: self.label.or(other.label),
+        }
+    }
+
+    /// The updates showing the look on unit `id`
+    fn updates(self, id: u32) -> impl Iterator<Item = StreamUpdate> {
+        let color = self.color.map(UnitValueKind::Value);
+        let shape = self.shape.map(UnitValueKind::Shape);
+        let label = self.label.map(UnitValueKind::Label);
+
+        [color, shape, label]
+            .into_iter()
+            .flatten()
+            .map(move |value| StreamUpdate::ChangeValue(UnitValueUpdateEvent { id, value }))
+    }
+}
+
+pub type Render<T> = Arc<dyn Fn(&T) -> ValueLook + Send + Sync>;
+
+/// A render function, shared by the stages using it so it can still be set once they're added
+struct Renderer<T>(Arc<Mutex<Option<Render<T>>>>);
+
+impl<T> Clone for Renderer<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Renderer<T> {
+    fn new(render: Option<Render<T>>) -> Self {
+        Self(Arc::new(Mutex::new(render)))
+    }
+
+    fn set(&self, render: Render<T>) {
+        *self.0.lock().unwrap() = Some(render);
+    }
+
+    fn look(&self, value: &T) -> ValueLook {
+        let render = self.0.lock().unwrap().clone();
+        render.map(|render| render(value)).unwrap_or_default()
+    }
+}
+
+/// What the units of a block look like once its future is done with them: the look set for the
+/// block, else the render function of their payload type
+struct Looks<T> {
+    render: Renderer<T>,
+    completion: Renderer<T>,
+}
+
+impl<T> Clone for Looks<T> {
+    fn clone(&self) -> Self {
+        Self {
+            render: self.render.clone(),
+            completion: self.completion.clone(),
+        }
+    }
+}
+
+impl<T> Looks<T> {
+    /// Looks of a block changing the payload type, nothing is set for the new type yet
+    fn new() -> Self {
+        Self {
+            render: Renderer::new(None),
+            completion: Renderer::new(None),
+        }
+    }
+
+    /// Looks of the next block, rendering the same payload type
+    fn next(&self) -> Self {
+        Self {
+            render: self.render.clone(),
+            completion: Renderer::new(None),
+        }
+    }
+
+    fn look(&self, value: &T) -> ValueLook {
+        self.completion.look(value).or(self.render.look(value))
+    }
+
+    /// Show `value` on unit `id`
+    fn send(&self, tx: &UpdateSender, id: u32, value: &T) {
+        for update in self.look(value).updates(id) {
+            tx.send(update);
+        }
+    }
+}
+
+/// A pipeline being built, streaming units carrying a payload of type `T`
+pub struct StreamVisBuilder<T = u32> {
+    stream: BoxStream<'static, Unit<T>>,
     blocks: Vec<StreamBlock>,
-    tx: Sender<StreamUpdate>,
+    tx: UpdateSender,
     rx: Receiver<StreamUpdate>,
+    log: EventLog,
+    sampler: Sampler,
+    /// Looks of the last block added
+    looks: Looks<T>,
 }
 
-impl StreamVisBuilder {
+impl StreamVisBuilder<u32> {
+    /// A source of `size` units, each carrying its index as payload
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
     pub fn source(size: usize) -> Self {
+        Self::source_seeded(size, rand::random())
+    }
+
+    /// Like `source`, with the durations and filter outcomes of its stages drawn from `seed`
+    pub fn source_seeded(size: usize, seed: u64) -> Self {
+        Self::from_values(0..size as u32, seed, Looks::new())
+    }
+}
+
+impl<T: Debug + Clone + Send + 'static> StreamVisBuilder<T> {
+    /// A source of a unit for every value, labelled with the value until `render` says otherwise
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    pub fn source_values<I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+    {
+        Self::source_values_seeded(values, rand::random())
+    }
+
+    /// Like `source_values`, with the durations and filter outcomes of its stages drawn from
+    /// `seed`
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    pub fn source_values_seeded<I>(values: I, seed: u64) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+    {
+        let looks = Looks::new();
+        looks.render.set(Arc::new(|value: &T| ValueLook {
+            label: Some(format!("{value:?}")),
+            ..Default::default()
+        }));
+
+        Self::from_values(values, seed, looks)
+    }
+
+    fn from_values<I>(values: I, seed: u64, looks: Looks<T>) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+    {
         let (tx, rx) = bounded::<StreamUpdate>(100);
+        let log = EventLog::new();
+        let tx = UpdateSender::new(tx, log.clone());
 
         let tick_tx = tx.clone();
-        let tick_stream = stream::iter(0..size).map(move |id| {
+        let tick_looks = looks.clone();
+        let tick_stream = stream::iter(values.into_iter().enumerate()).map(move |(id, value)| {
             let id = id as u32;
             log::debug!("new stream unit: {}", id);
+            let mut look = tick_looks.look(&value);
             let update = StreamUpdate::Created(UnitCreatedEvent {
                 id,
                 block_id: 0,
-                value: UnitValueKind::Value(Color::WHITE),
+                value: match look.label.take() {
+                    Some(label) => UnitValueKind::Label(label),
+                    None => UnitValueKind::Value(Color::WHITE),
+                },
             });
 
-            tick_tx.send(update.clone()).unwrap();
+            tick_tx.send(update.clone());
+            for update in look.updates(id) {
+                tick_tx.send(update);
+            }
 
-            StreamedUnit { id, block_id: 0 }
+            Ok(StreamedUnit {
+                id,
+                block_id: 0,
+                value,
+            })
         });
 
         StreamVisBuilder {
@@ -74,181 +343,698 @@ impl StreamVisBuilder {
             blocks: vec![StreamBlock::Source(SourceBlock { id: 0 })],
             tx,
             rx,
+            log,
+            sampler: Sampler::new(seed),
+            looks,
         }
     }
 
-    pub fn filter(self, async_duration: JitteringDuration, filter_ratio: f32) -> Self {
-        let id = self.blocks.len() as u32 + 1;
+    /// Show the units' payload values with `render`, from their creation or from the last
+    /// stage changing their type
+    pub fn render(self, render: impl Fn(&T) -> ValueLook + Send + Sync + 'static) -> Self {
+        self.looks.render.set(Arc::new(render));
+        self
+    }
 
-        let color = COLORS[(id as usize) % COLORS.len()];
+    /// Give the units the look of `look`, before their payload's render function, once the last
+    /// block added is done with them: created by the source, through the map or the filter
+    pub fn on_completion(self, look: impl Fn(&T) -> ValueLook + Send + Sync + 'static) -> Self {
+        self.looks.completion.set(Arc::new(look));
+        self
+    }
 
-        let stream = self
-            .stream
-            .filter_map(updating_filter(
-                id,
-                self.tx.clone(),
-                async_duration,
-                filter_ratio,
-                color,
-            ))
-            .boxed();
+    /// Add a block after the ones so far, `stream` making the stream of units out of it from
+    /// the stream into it
+    fn stage<U>(
+        self,
+        block: StreamBlock,
+        looks: Looks<U>,
+        stream: impl FnOnce(BoxStream<'static, Unit<T>>) -> BoxStream<'static, Unit<U>>,
+    ) -> StreamVisBuilder<U> {
+        assert!(
+            !matches!(
+                self.blocks.last(),
+                Some(StreamBlock::FilterBlock(FilterBlock {
+                    kind: FilterKind::TryForEach,
+                    ..
+                }))
+            ),
+            "nothing but the sink can follow try_for_each"
+        );
+        let stream = stream(self.stream);
+        let mut blocks = self.blocks;
+        blocks.push(block);
 
         StreamVisBuilder {
             stream,
+            blocks,
             tx: self.tx,
             rx: self.rx,
-            blocks: self
-                .blocks
-                .into_iter()
-                .chain(vec![StreamBlock::FilterBlock(FilterBlock {
-                    id: id,
-                    duration: async_duration.duration.clone(),
-                })])
-                .collect(),
+            log: self.log,
+            sampler: self.sampler,
+            looks,
         }
     }
 
+    /// A filter letting through about `filter_ratio` of the units, at random
+    pub fn filter(self, async_duration: JitteringDuration, filter_ratio: f32) -> Self {
+        let looks = self.looks.next();
+        self.filter_deciding(
+            async_duration,
+            FilterBlock {
+                filter_ratio: Some(filter_ratio),
+                ..Default::default()
+            },
+            looks,
+            Arc::new(move |_, value, rng| (rng.gen::<f32>() < filter_ratio).then_some(value)),
+        )
+    }
+
+    /// Like `filter`, as `TryStreamExt::try_filter`. Its predicate can't fail, failed units
+    /// go through it untouched
+    pub fn try_filter(self, async_duration: JitteringDuration, filter_ratio: f32) -> Self {
+        let looks = self.looks.next();
+        self.filter_deciding(
+            async_duration,
+            FilterBlock {
+                filter_ratio: Some(filter_ratio),
+                kind: FilterKind::TryFilter,
+                ..Default::default()
+            },
+            looks,
+            Arc::new(move |_, value, rng| (rng.gen::<f32>() < filter_ratio).then_some(value)),
+        )
+    }
+
+    /// A filter letting through the units whose id and payload match `predicate`, labelled with
+    /// its short `description`
+    pub fn filter_by(
+        self,
+        async_duration: JitteringDuration,
+        description: impl Into<String>,
+        predicate: impl Fn(u32, &T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let looks = self.looks.next();
+        self.filter_deciding(
+            async_duration,
+            FilterBlock {
+                predicate: Some(description.into()),
+                ..Default::default()
+            },
+            looks,
+            Arc::new(move |id, value, _| predicate(id, &value).then_some(value)),
+        )
+    }
+
+    /// Like `filter_by`, the predicate looking at the payload only
+    #[allow(dead_code)] // builder API, unused by the bundled pipelines
+    pub fn filter_value(
+        self,
+        async_duration: JitteringDuration,
+        description: impl Into<String>,
+        predicate: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter_by(async_duration, description, move |_, value| {
+            predicate(value)
+        })
+    }
+
+    /// A filter dropping every `n`th unit created by the source
+    pub fn filter_every_nth(self, async_duration: JitteringDuration, n: u32) -> Self {
+        let n = n.max(1);
+        self.filter_by(
+            async_duration,
+            format!("drop every {}", ordinal(n)),
+            move |id, _| (id + 1) % n != 0,
+        )
+    }
+
+    /// A filter mapping the payload of the units it lets through, dropping those `f` maps to
+    /// `None`. Labelled with its short `description`
+    pub fn filter_map<U: Debug + Clone + Send + 'static>(
+        self,
+        async_duration: JitteringDuration,
+        description: impl Into<String>,
+        f: impl Fn(T) -> Option<U> + Send + Sync + 'static,
+    ) -> StreamVisBuilder<U> {
+        self.filter_deciding(
+            async_duration,
+            FilterBlock {
+                predicate: Some(description.into()),
+                kind: FilterKind::FilterMap,
+                ..Default::default()
+            },
+            Looks::new(),
+            Arc::new(move |_, value, _| f(value)),
+        )
+    }
+
+    /// As `TryStreamExt::and_then`, a future run for one unit at a time, failing with a chance
+    /// of `failure_ratio`
+    pub fn and_then(self, async_duration: JitteringDuration, failure_ratio: f32) -> Self {
+        let looks = self.looks.next();
+        self.filter_deciding(
+            async_duration,
+            FilterBlock {
+                kind: FilterKind::AndThen,
+                failure_ratio: Some(failure_ratio),
+                ..Default::default()
+            },
+            looks,
+            Arc::new(|_, value, _| Some(value)),
+        )
+    }
+
+    /// As `TryStreamExt::try_for_each`, consuming the units one at a time before the sink, each
+    /// future failing with a chance of `failure_ratio`. Only the sink can follow it
+    pub fn try_for_each(self, async_duration: JitteringDuration, failure_ratio: f32) -> Self {
+        let looks = self.looks.next();
+        self.filter_deciding(
+            async_duration,
+            FilterBlock {
+                kind: FilterKind::TryForEach,
+                failure_ratio: Some(failure_ratio),
+                ..Default::default()
+            },
+            looks,
+            Arc::new(|_, value, _| Some(value)),
+        )
+    }
+
+    /// Add `block`, a filter block whose id and duration are yet to be set
+    fn filter_deciding<U: Send + 'static>(
+        self,
+        async_duration: JitteringDuration,
+        block: FilterBlock,
+        looks: Looks<U>,
+        decide: Decide<T, U>,
+    ) -> StreamVisBuilder<U> {
+        let id = self.blocks.len() as u32 + 1;
+
+        let color = theme().future_color(id);
+        let failure_ratio = block.failure_ratio;
+
+        let tx = self.tx.clone();
+        let sampler = self.sampler;
+        let filter_looks = looks.clone();
+        self.stage(
+            StreamBlock::FilterBlock(FilterBlock {
+                id,
+                duration: Some(async_duration.duration),
+                ..block
+            }),
+            looks,
+            |stream| {
+                stream
+                    .filter_map(updating_filter(
+                        id,
+                        tx,
+                        sampler,
+                        async_duration,
+                        failure_ratio,
+                        decide,
+                        filter_looks,
+                        color,
+                    ))
+                    .boxed()
+            },
+        )
+    }
+
     pub fn map_buffered(self, async_duration: JitteringDuration, buffered: usize) -> Self {
+        let looks = self.looks.next();
+        self.map_buffered_looking(async_duration, buffered, |value| value, looks)
+    }
+
+    pub fn map_buffer_unordered(self, async_duration: JitteringDuration, buffered: usize) -> Self {
+        let looks = self.looks.next();
+        self.map_buffer_unordered_looking(async_duration, buffered, |value| value, looks)
+    }
+
+    /// Like `map_buffered`, the future resolving to its unit's payload mapped by `f`
+    pub fn map_value_buffered<U: Debug + Clone + Send + 'static>(
+        self,
+        async_duration: JitteringDuration,
+        buffered: usize,
+        f: impl Fn(T) -> U + Send + Sync + 'static,
+    ) -> StreamVisBuilder<U> {
+        self.map_buffered_looking(async_duration, buffered, f, Looks::new())
+    }
+
+    fn map_buffered_looking<U: Debug + Clone + Send + 'static>(
+        self,
+        async_duration: JitteringDuration,
+        buffered: usize,
+        f: impl Fn(T) -> U + Send + Sync + 'static,
+        looks: Looks<U>,
+    ) -> StreamVisBuilder<U> {
         let map_id = self.blocks.len() as u32 + 1;
-        let color = COLORS[(map_id as usize) % COLORS.len()];
+        let map = mapping(self.tx.clone(), looks.clone(), f);
 
-        let stream = self
-            .stream
-            .map(update_stream_state(
-                self.tx.clone(),
-                async_duration,
-                map_id,
-                color,
-            ))
-            .buffered(buffered)
-            .boxed();
+        let tx = self.tx.clone();
+        let sampler = self.sampler;
+        self.stage(
+            StreamBlock::MapBuffer(BufferBlock {
+                id: map_id,
+                duration: Some(async_duration.duration),
+                buffered,
+                failure_ratio: None,
+                units: Default::default(),
+            }),
+            looks,
+            |stream| {
+                stream
+                    .map(passing_failures(update_stream_state(
+                        tx,
+                        sampler,
+                        async_duration,
+                        None,
+                        map_id,
+                        theme().future_color(map_id),
+                        map,
+                    )))
+                    .buffered(buffered)
+                    .boxed()
+            },
+        )
+    }
 
-        StreamVisBuilder {
-            stream,
-            tx: self.tx,
-            rx: self.rx,
-            blocks: self
-                .blocks
-                .into_iter()
-                .chain(vec![StreamBlock::MapBuffer(BufferBlock {
-                    id: map_id,
-                    duration: async_duration.duration.clone(),
-                    buffered: buffered,
-                    units: Default::default(),
-                })])
-                .collect(),
-        }
+    /// Like `map_buffered`, as `TryStreamExt::try_buffered`: every future fails with a chance of
+    /// `failure_ratio`, and failed units coming from upstream skip the queue
+    pub fn try_buffered(
+        self,
+        async_duration: JitteringDuration,
+        buffered: usize,
+        failure_ratio: f32,
+    ) -> Self {
+        let map_id = self.blocks.len() as u32 + 1;
+        let looks = self.looks.next();
+        let state = update_stream_state(
+            self.tx.clone(),
+            self.sampler,
+            async_duration,
+            Some(failure_ratio),
+            map_id,
+            theme().future_color(map_id),
+            mapping(self.tx.clone(), looks.clone(), |value| value),
+        );
+
+        self.stage(
+            StreamBlock::MapBuffer(BufferBlock {
+                id: map_id,
+                duration: Some(async_duration.duration),
+                buffered,
+                failure_ratio: Some(failure_ratio),
+                units: Default::default(),
+            }),
+            looks,
+            |stream| {
+                stream
+                    .map(move |unit| unit.map(&state))
+                    .try_buffered(buffered)
+                    .boxed()
+            },
+        )
     }
 
-    pub fn map_buffer_unordered(self, async_duration: JitteringDuration, buffered: usize) -> Self {
+    /// Like `map_buffer_unordered`, the future resolving to its unit's payload mapped by `f`
+    pub fn map_value_buffer_unordered<U: Debug + Clone + Send + 'static>(
+        self,
+        async_duration: JitteringDuration,
+        buffered: usize,
+        f: impl Fn(T) -> U + Send + Sync + 'static,
+    ) -> StreamVisBuilder<U> {
+        self.map_buffer_unordered_looking(async_duration, buffered, f, Looks::new())
+    }
+
+    fn map_buffer_unordered_looking<U: Debug + Clone + Send + 'static>(
+        self,
+        async_duration: JitteringDuration,
+        buffered: usize,
+        f: impl Fn(T) -> U + Send + Sync + 'static,
+        looks: Looks<U>,
+    ) -> StreamVisBuilder<U> {
         let map_id = self.blocks.len() as u32 + 1;
-        let color = COLORS[(map_id as usize) % COLORS.len()];
+        let map = mapping(self.tx.clone(), looks.clone(), f);
 
-        let stream = self
-            .stream
-            .map(update_stream_state(
-                self.tx.clone(),
-                async_duration,
+        let tx = self.tx.clone();
+        let sampler = self.sampler;
+        self.stage(
+            StreamBlock::MapBufferUnordered(BufferUnrderedBlock::new(
                 map_id,
-                color,
-            ))
-            .buffer_unordered(buffered)
-            .boxed();
+                buffered * 3, // TODO: fix this
+                Some(async_duration.duration),
+                buffered,
+            )),
+            looks,
+            |stream| {
+                stream
+                    .map(passing_failures(update_stream_state(
+                        tx,
+                        sampler,
+                        async_duration,
+                        None,
+                        map_id,
+                        theme().future_color(map_id),
+                        map,
+                    )))
+                    .buffer_unordered(buffered)
+                    .boxed()
+            },
+        )
+    }
 
-        StreamVisBuilder {
-            stream,
-            tx: self.tx,
-            rx: self.rx,
-            blocks: self
-                .blocks
-                .into_iter()
-                .chain(vec![StreamBlock::MapBufferUnordered(
-                    BufferUnrderedBlock::new(
+    /// Like `map_buffered`, but the future is supplied by the user and drives its own progress
+    pub fn map_buffered_with<F, Fut>(self, buffered: usize, f: F) -> Self
+    where
+        F: Fn(u32, Progress) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let map_id = self.blocks.len() as u32 + 1;
+        let color = theme().future_color(map_id);
+
+        let tx = self.tx.clone();
+        let looks = self.looks.next();
+        let map = mapping(self.tx.clone(), looks.clone(), |value| value);
+        self.stage(
+            StreamBlock::MapBuffer(BufferBlock {
+                id: map_id,
+                duration: None,
+                buffered,
+                failure_ratio: None,
+                units: Default::default(),
+            }),
+            looks,
+            |stream| {
+                stream
+                    .map(passing_failures(update_stream_state_with(
+                        tx.clone(),
                         map_id,
-                        buffered * 3, // TODO: fix this
-                        async_duration.duration.clone(),
-                        buffered,
-                    ),
-                )])
-                .collect(),
-        }
+                        color,
+                        f,
+                        map,
+                    )))
+                    .buffered(buffered)
+                    .boxed()
+            },
+        )
     }
 
-    pub fn sink(self) -> (Vec<StreamBlock>, Receiver<StreamUpdate>) {
-        let sink_id = (self.blocks.len() + 1) as u32;
+    /// Like `map_buffer_unordered`, but the future is supplied by the user and drives its own progress
+    pub fn map_buffer_unordered_with<F, Fut>(self, buffered: usize, f: F) -> Self
+    where
+        F: Fn(u32, Progress) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let map_id = self.blocks.len() as u32 + 1;
+        let color = theme().future_color(map_id);
+
+        let tx = self.tx.clone();
+        let looks = self.looks.next();
+        let map = mapping(self.tx.clone(), looks.clone(), |value| value);
+        self.stage(
+            StreamBlock::MapBufferUnordered(BufferUnrderedBlock::new(
+                map_id,
+                buffered * 3, // TODO: fix this
+                None,
+                buffered,
+            )),
+            looks,
+            |stream| {
+                stream
+                    .map(passing_failures(update_stream_state_with(
+                        tx.clone(),
+                        map_id,
+                        color,
+                        f,
+                        map,
+                    )))
+                    .buffer_unordered(buffered)
+                    .boxed()
+            },
+        )
+    }
+
+    /// Blocks added so far, without the sink
+    pub fn blocks(&self) -> &[StreamBlock] {
+        &self.blocks
+    }
+
+    pub fn sink(self) -> StreamPipeline {
+        self.spawn_runtime(None)
+    }
+
+    /// Like `sink`, on a runtime whose clock stands still but for the durations received from
+    /// `clock`, so the futures pause, step and speed up along with the animation
+    pub fn sink_with_clock(self, clock: Receiver<Duration>) -> StreamPipeline {
+        self.spawn_runtime(Some(clock))
+    }
+
+    fn spawn_runtime(self, clock: Option<Receiver<Duration>>) -> StreamPipeline {
+        let (mut pipeline, stream_to_sink) = self.into_pipeline();
+        let (stop_tx, stop_rx) = oneshot::channel();
 
         std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let mut stream = self.stream;
-            rt.block_on(async move {
-                while let Some(unit) = stream.next().await {
-                    log::debug!("sink received unit({})", unit.id);
-                    self.tx
-                        .send(StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
+            let rt = match clock {
+                // user supplied futures can do real IO, only their timers follow the clock
+                Some(_) => runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .enable_io()
+                    .start_paused(true)
+                    .build(),
+                None => runtime::Runtime::new(),
+            }
+            .unwrap();
+
+            rt.block_on(async {
+                if let Some(clock) = clock {
+                    drive_clock(clock);
+                }
+
+                tokio::select! {
+                    _ = stream_to_sink => (),
+                    _ = stop_rx => log::debug!("pipeline stopped"),
+                }
+            });
+            // the clock's blocking task ends on the next tick
+            rt.shutdown_background();
+        });
+
+        pipeline.stop = Some(StopHandle(stop_tx));
+        pipeline
+    }
+
+    /// Stream every unit into the sink on the current runtime, with nobody watching.
+    /// On a paused runtime the futures' sleeps take no wall time
+    pub async fn run(self) -> (Vec<StreamBlock>, Vec<RecordedEvent>) {
+        let (pipeline, stream_to_sink) = self.into_pipeline();
+        drop(pipeline.rx);
+
+        stream_to_sink.await;
+        (pipeline.blocks, pipeline.log.events())
+    }
+
+    /// The pipeline with its sink, and the future driving its units into the sink
+    fn into_pipeline(self) -> (StreamPipeline, BoxFuture<'static, ()>) {
+        let sink_id = (self.blocks.len() + 1) as u32;
+
+        let mut stream = self.stream;
+        let tx = self.tx;
+        let log = self.log.clone();
+        let stream_to_sink = Box::pin(async move {
+            let failure = loop {
+                match stream.next().await {
+                    Some(Ok(unit)) => {
+                        log::debug!("sink received unit({})", unit.id);
+                        tx.send(StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
                             id: unit.id,
                             block_id: sink_id,
-                            from_block_id: unit.block_id.clone(),
-                        }))
-                        .unwrap();
+                            from_block_id: unit.block_id,
+                        }));
+                    }
+                    Some(Err(failure)) => break failure,
+                    None => return,
                 }
-            })
+            };
+
+            // like `try_for_each` and `try_collect`, the first error ends the stream, dropping
+            // the futures still running and the units waiting on them
+            log::debug!("sink received failed unit({})", failure.id);
+            tx.send(StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
+                id: failure.id,
+                block_id: sink_id,
+                from_block_id: failure.block_id,
+            }));
+            drop(stream);
+
+            for id in in_flight(&log.events(), sink_id) {
+                tx.send(StreamUpdate::Dropped(DroppedEvent { id }));
+            }
         });
 
         let mut blocks = self.blocks;
         blocks.push(StreamBlock::Sink(SinkBlock { id: sink_id }));
 
-        (blocks, self.rx)
+        (
+            StreamPipeline {
+                blocks,
+                rx: self.rx,
+                log: self.log,
+                stop: None,
+            },
+            stream_to_sink,
+        )
     }
 }
 
-fn updating_filter(
+/// Advance the current, paused, runtime's clock by every duration received from `clock`
+fn drive_clock(clock: Receiver<Duration>) {
+    tokio::spawn(async move {
+        loop {
+            // tokio doesn't auto-advance a paused clock while a blocking task is in flight, so
+            // between ticks the runtime parks on the IO driver for real and user futures reading
+            // sockets make progress with the clock standing still
+            let clock = clock.clone();
+            let Ok(Ok(delta)) = tokio::task::spawn_blocking(move || clock.recv()).await else {
+                break;
+            };
+            // with the other futures waiting, sleeping auto-advances the clock from timer to
+            // timer, so they all wake at their exact deadlines. Pending IO doesn't hold the clock
+            // back, the IO driver is polled without waiting before every jump, so IO takes no
+            // virtual time
+            tokio::time::sleep(delta).await;
+        }
+    });
+}
+
+/// Units created but neither filtered out nor in the sink yet
+fn in_flight(events: &[RecordedEvent], sink_id: u32) -> BTreeSet<u32> {
+    let mut units = BTreeSet::new();
+    for event in events {
+        match &event.update {
+            StreamUpdate::Created(created) => {
+                units.insert(created.id);
+            }
+            StreamUpdate::AdvanceBlock(advance) if advance.block_id == sink_id => {
+                units.remove(&advance.id);
+            }
+            StreamUpdate::FilteredOut(FilteredOutEvent { id })
+            | StreamUpdate::Dropped(DroppedEvent { id }) => {
+                units.remove(id);
+            }
+            _ => (),
+        }
+    }
+    units
+}
+
+/// The payload a filter lets through, if any, given the unit's id, its payload and the randomness
+/// drawn for it
+type Decide<T, U> = Arc<dyn Fn(u32, T, &mut StdRng) -> Option<U> + Send + Sync>;
+
+/// `n` as in "every 3rd"
+fn ordinal(n: u32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    match n {
+        1 => "unit".to_string(),
+        2 => "other unit".to_string(),
+        _ => format!("{n}{suffix}"),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn updating_filter<T: Send + 'static, U: Send + 'static>(
     phase: u32,
-    tx: Sender<StreamUpdate>,
+    tx: UpdateSender,
+    sampler: Sampler,
     duration: JitteringDuration,
-    filter_ratio: f32,
+    failure_ratio: Option<f32>,
+    decide: Decide<T, U>,
+    looks: Looks<U>,
     color: Color,
-) -> impl FnMut(StreamedUnit) -> BoxFuture<'static, Option<StreamedUnit>> {
+) -> impl FnMut(Unit<T>) -> BoxFuture<'static, Option<Unit<U>>> {
     move |unit| {
+        let unit = match unit {
+            Ok(unit) => unit,
+            Err(failure) => return future::ready(Some(Err(failure))).boxed(),
+        };
         let tx = tx.clone();
+        let decide = decide.clone();
+        let looks = looks.clone();
 
         tx.send(StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
             id: unit.id,
-            block_id: phase.clone(),
-            from_block_id: unit.block_id.clone(),
-        }))
-        .unwrap();
+            block_id: phase,
+            from_block_id: unit.block_id,
+        }));
 
         tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
             id: unit.id,
             value: UnitValueKind::PendingFuture(color),
-        }))
-        .unwrap();
+        }));
 
         log::debug!("creating filter future for unit({})", unit.id);
-        Box::pin(async move {
+        async move {
             log::debug!("calling filter future for unit({})", unit.id);
-            let unit_id = unit.id.clone();
-            updating_future(unit.clone(), phase, tx.clone(), duration).await;
+            let unit_id = unit.id;
+            let mut rng = sampler.rng(phase, unit_id);
+            let duration = duration.sample(&mut rng);
+            let unit = updating_future(unit, phase, tx.clone(), duration).await;
 
-            let is_in = rand::random::<f32>() < filter_ratio;
-
-            if !is_in {
-                tx.send(StreamUpdate::FilteredOut(FilteredOutEvent { id: unit_id }))
-                    .unwrap();
+            if failure_ratio.is_some_and(|ratio| rng.gen::<f32>() < ratio) {
+                return Some(Err(failure(&tx, unit_id, phase)));
             }
 
-            is_in.then_some(unit)
-        })
+            let Some(value) = decide(unit_id, unit.value, &mut rng) else {
+                tx.send(StreamUpdate::FilteredOut(FilteredOutEvent { id: unit_id }));
+                return None;
+            };
+
+            looks.send(&tx, unit_id, &value);
+            Some(Ok(StreamedUnit {
+                id: unit_id,
+                block_id: unit.block_id,
+                value,
+            }))
+        }
+        .boxed()
     }
 }
 
-async fn updating_future(
-    unit: StreamedUnit,
+/// Show the future of unit `id` as failed in `block_id`, turning the unit into an error
+fn failure(tx: &UpdateSender, id: u32, block_id: u32) -> Failure {
+    log::debug!("future failed for unit({}) buffer({})", id, block_id);
+    tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+        id,
+        value: UnitValueKind::Error,
+    }));
+
+    Failure { id, block_id }
+}
+
+/// Run the futures of `f` for the units going down the stream, failed units passing through
+/// untouched, though in order
+fn passing_failures<T, U: Send + 'static>(
+    f: impl Fn(StreamedUnit<T>) -> BoxFuture<'static, Unit<U>>,
+) -> impl Fn(Unit<T>) -> BoxFuture<'static, Unit<U>> {
+    move |unit| match unit {
+        Ok(unit) => f(unit),
+        Err(failure) => future::ready(Err(failure)).boxed(),
+    }
+}
+
+async fn updating_future<T>(
+    unit: StreamedUnit<T>,
     block_id: u32,
-    tx: Sender<StreamUpdate>,
-    duration: JitteringDuration,
-) -> StreamedUnit {
-    let duration = duration.get();
+    tx: UpdateSender,
+    duration: Duration,
+) -> StreamedUnit<T> {
     log::debug!(
         "starting future for unit({}) buffer({}) duration({})",
         unit.id,
@@ -260,8 +1046,7 @@ async fn updating_future(
     tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
         id: unit.id,
         value: UnitValueKind::RunningFuture(0.),
-    }))
-    .unwrap();
+    }));
 
     for i in 1..interval + 1 {
         log::trace!(
@@ -276,8 +1061,7 @@ async fn updating_future(
         tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
             id: unit.id,
             value: UnitValueKind::RunningFuture(i as f32 / interval as f32),
-        }))
-        .unwrap();
+        }));
         log::trace!(
             "done update future for unit({}) buffer({}) {}/{}",
             unit.id,
@@ -288,48 +1072,713 @@ async fn updating_future(
     }
 
     log::debug!("future done for unit({}) buffer({})", unit.id, block_id);
-    StreamedUnit {
-        id: unit.id,
-        block_id,
+    StreamedUnit { block_id, ..unit }
+}
+
+/// Map the payload of a unit whose future is done with `f`, and show the new value
+fn mapping<T, U: Send + 'static>(
+    tx: UpdateSender,
+    looks: Looks<U>,
+    f: impl Fn(T) -> U + Send + Sync + 'static,
+) -> impl Fn(StreamedUnit<T>) -> StreamedUnit<U> + Clone + Send + Sync + 'static {
+    let f = Arc::new(f);
+    move |unit| {
+        let value = f(unit.value);
+        looks.send(&tx, unit.id, &value);
+
+        StreamedUnit {
+            id: unit.id,
+            block_id: unit.block_id,
+            value,
+        }
     }
 }
 
-fn update_stream_state(
-    tx: Sender<StreamUpdate>,
+fn update_stream_state<T: Send + 'static, U>(
+    tx: UpdateSender,
+    sampler: Sampler,
     duration: JitteringDuration,
+    failure_ratio: Option<f32>,
     phase2: u32,
     color: Color,
-) -> impl Fn(StreamedUnit) -> BoxFuture<'static, StreamedUnit> {
+    map: impl Fn(StreamedUnit<T>) -> StreamedUnit<U> + Clone + Send + 'static,
+) -> impl Fn(StreamedUnit<T>) -> BoxFuture<'static, Unit<U>> {
     move |unit| {
         tx.send(StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
             id: unit.id,
-            block_id: phase2.clone(),
-            from_block_id: unit.block_id.clone(),
-        }))
-        .unwrap();
+            block_id: phase2,
+            from_block_id: unit.block_id,
+        }));
 
         tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
             id: unit.id,
             value: UnitValueKind::PendingFuture(color),
-        }))
-        .unwrap();
+        }));
 
         let tx = tx.clone();
-        let block_id = phase2.clone();
+        let block_id = phase2;
 
         log::debug!(
             "creating map future for unit({}), map_buffered({})",
             unit.id,
             block_id,
         );
-        Box::pin(updating_future(
-            StreamedUnit {
-                id: unit.id,
-                block_id: block_id.clone(),
-            },
+        let mut rng = sampler.rng(block_id, unit.id);
+        let duration = duration.sample(&mut rng);
+        let failed = failure_ratio.is_some_and(|ratio| rng.gen::<f32>() < ratio);
+
+        let map = map.clone();
+        Box::pin(
+            updating_future(unit, block_id, tx.clone(), duration).map(move |unit| {
+                if failed {
+                    Err(failure(&tx, unit.id, block_id))
+                } else {
+                    Ok(map(unit))
+                }
+            }),
+        )
+    }
+}
+
+fn update_stream_state_with<T: Send + 'static, F, Fut>(
+    tx: UpdateSender,
+    block_id: u32,
+    color: Color,
+    f: F,
+    map: impl Fn(StreamedUnit<T>) -> StreamedUnit<T> + Clone + Send + 'static,
+) -> impl Fn(StreamedUnit<T>) -> BoxFuture<'static, Unit<T>>
+where
+    F: Fn(u32, Progress) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    move |unit| {
+        tx.send(StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
+            id: unit.id,
             block_id,
-            tx,
-            duration,
-        ))
+            from_block_id: unit.block_id,
+        }));
+
+        tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+            id: unit.id,
+            value: UnitValueKind::PendingFuture(color),
+        }));
+
+        log::debug!(
+            "creating user map future for unit({}), map_buffered({})",
+            unit.id,
+            block_id,
+        );
+
+        let tx = tx.clone();
+        let future = f(
+            unit.id,
+            Progress {
+                unit_id: unit.id,
+                block_id,
+                tx: tx.clone(),
+                phases: 0,
+            },
+        );
+
+        let map = map.clone();
+        Box::pin(async move {
+            tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id: unit.id,
+                value: UnitValueKind::RunningFuture(0.),
+            }));
+
+            future.await;
+
+            tx.send(StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id: unit.id,
+                value: UnitValueKind::RunningFuture(1.),
+            }));
+
+            log::debug!(
+                "user future done for unit({}) buffer({})",
+                unit.id,
+                block_id
+            );
+            Ok(map(StreamedUnit { block_id, ..unit }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crossbeam_channel::unbounded;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn run<T: Debug + Clone + Send + 'static>(
+        builder: StreamVisBuilder<T>,
+    ) -> Vec<RecordedEvent> {
+        builder.run().await.1
+    }
+
+    /// The last label shown on every unit
+    fn labels(events: &[RecordedEvent]) -> BTreeMap<u32, String> {
+        events
+            .iter()
+            .filter_map(|event| match &event.update {
+                StreamUpdate::Created(UnitCreatedEvent {
+                    id,
+                    value: UnitValueKind::Label(label),
+                    ..
+                })
+                | StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                    id,
+                    value: UnitValueKind::Label(label),
+                }) => Some((*id, label.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn filtered_out(events: &[RecordedEvent]) -> Vec<u32> {
+        events
+            .iter()
+            .filter_map(|event| match event.update {
+                StreamUpdate::FilteredOut(FilteredOutEvent { id }) => Some(id),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn values_are_labelled_until_mapped() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let created = run(StreamVisBuilder::source_values_seeded(["ab", "c"], 1)).await;
+        assert_eq!(labels(&created)[&0], "\"ab\"");
+
+        // the mapped payload type has no render function of its own
+        let mapped = run(StreamVisBuilder::source_values_seeded(["ab", "c"], 1)
+            .map_value_buffered(duration, 2, |value| value.len()))
+        .await;
+        assert_eq!(labels(&mapped)[&0], "\"ab\"");
+
+        let rendered = run(StreamVisBuilder::source_values_seeded(["ab", "c"], 1)
+            .map_value_buffered(duration, 2, |value| value.len())
+            .render(|len| ValueLook {
+                color: None,
+                shape: None,
+                label: Some(format!("{len} chars")),
+            }))
+        .await;
+        assert_eq!(labels(&rendered)[&0], "2 chars");
+        assert_eq!(labels(&rendered)[&1], "1 chars");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn render_applies_from_the_source_on() {
+        let events = run(StreamVisBuilder::source_seeded(2, 1)
+            .map_buffered(JitteringDuration::from_millis(100, 0.), 1)
+            .render(|value| ValueLook {
+                color: Some(Color::RED),
+                shape: None,
+                label: Some(format!("#{value}")),
+            }))
+        .await;
+
+        assert_eq!(labels(&events)[&1], "#1");
+        assert!(events.iter().any(|event| matches!(
+            event.update,
+            StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id: 1,
+                value: UnitValueKind::Value(Color::RED),
+            })
+        )));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn filters_on_the_payload() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let events = run(StreamVisBuilder::source_seeded(6, 1)
+            .map_value_buffered(duration, 2, |value| value * 10)
+            .filter_value(duration, "multiple of 20", |value| value % 20 == 0))
+        .await;
+        assert_eq!(filtered_out(&events), [1, 3, 5]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn filters_on_the_id_and_payload() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let builder = StreamVisBuilder::source_seeded(5, 1)
+            .map_value_buffered(duration, 2, |value| value * 2)
+            .filter_by(duration, "early and a multiple of 4", |id, value| {
+                id < 3 && value % 4 == 0
+            });
+        assert_eq!(
+            builder.blocks()[2].name(),
+            ".filter(100ms, early and a multiple of 4)"
+        );
+        assert_eq!(filtered_out(&run(builder).await), [1, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn filter_map_passes_the_mapped_payload_on() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let builder = StreamVisBuilder::source_seeded(5, 1)
+            .filter_map(duration, "halve the even ones", |value| {
+                (value % 2 == 0).then(|| format!("{}", value / 2))
+            })
+            .render(|half| ValueLook {
+                label: Some(format!("half {half}")),
+                ..Default::default()
+            });
+        assert_eq!(
+            builder.blocks()[1].name(),
+            ".filter_map(100ms, halve the even ones)"
+        );
+
+        let events = run(builder).await;
+        assert_eq!(filtered_out(&events), [1, 3]);
+        let labels = labels(&events);
+        assert_eq!(labels[&2], "half 1");
+        assert_eq!(labels[&4], "half 2");
+        assert!(!labels.contains_key(&1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn completion_looks_come_before_the_render_function() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let events = run(StreamVisBuilder::source_seeded(2, 1)
+            .map_buffered(duration, 1)
+            .on_completion(|value| ValueLook {
+                shape: (*value == 1).then_some(UnitShape::Circle),
+                label: Some("mapped".to_string()),
+                ..Default::default()
+            })
+            .render(|value| ValueLook {
+                label: Some(format!("#{value}")),
+                ..Default::default()
+            }))
+        .await;
+
+        let shapes = events
+            .iter()
+            .filter_map(|event| match event.update {
+                StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                    id,
+                    value: UnitValueKind::Shape(shape),
+                }) => Some((id, shape)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(shapes, [(1, UnitShape::Circle)]);
+        assert_eq!(labels(&events)[&0], "mapped");
+    }
+
+    #[test]
+    fn looks_fill_in_what_they_leave_unset() {
+        let look = ValueLook {
+            color: Some(Color::RED),
+            ..Default::default()
+        }
+        .or(ValueLook {
+            color: Some(Color::BLUE),
+            shape: Some(UnitShape::Diamond),
+            label: None,
+        });
+        assert_eq!(look.color, Some(Color::RED));
+        assert_eq!(look.shape, Some(UnitShape::Diamond));
+        assert_eq!(look.label, None);
+        assert_eq!(look.updates(3).count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_every_nth_unit() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let builder = StreamVisBuilder::source_seeded(7, 1).filter_every_nth(duration, 3);
+        assert_eq!(builder.blocks()[1].name(), ".filter(100ms, drop every 3rd)");
+        assert_eq!(filtered_out(&run(builder).await), [2, 5]);
+
+        let every = StreamVisBuilder::source_seeded(3, 1).filter_every_nth(duration, 0);
+        assert_eq!(filtered_out(&run(every).await), [0, 1, 2]);
+    }
+
+    #[test]
+    fn names_every_nth_unit() {
+        let names = [1, 2, 3, 4, 11, 12, 21, 22, 23, 113].map(ordinal);
+        assert_eq!(
+            names,
+            [
+                "unit",
+                "other unit",
+                "3rd",
+                "4th",
+                "11th",
+                "12th",
+                "21st",
+                "22nd",
+                "23rd",
+                "113th"
+            ]
+        );
+    }
+
+    #[test]
+    fn samplers_draw_per_block_and_unit() {
+        let draws = |seed, block_id, unit_id| {
+            let mut rng = Sampler::new(seed).rng(block_id, unit_id);
+            [rng.gen::<u64>(), rng.gen::<u64>()]
+        };
+
+        assert_eq!(draws(7, 1, 2), draws(7, 1, 2));
+        assert_ne!(draws(7, 1, 2), draws(8, 1, 2));
+        assert_ne!(draws(7, 1, 2), draws(7, 2, 1));
+        assert_ne!(draws(7, 1, 2), draws(7, 1, 3));
+    }
+
+    #[test]
+    fn jitter_only_lengthens_durations() {
+        let duration = JitteringDuration::from_millis(100, 3.);
+        let mut rng = Sampler::new(1).rng(1, 1);
+        for _ in 0..100 {
+            let sampled = duration.sample(&mut rng);
+            assert!(sampled >= Duration::from_millis(100));
+            assert!(sampled <= Duration::from_millis(400));
+        }
+
+        let steady = JitteringDuration::from_millis(100, 0.);
+        assert_eq!(steady.sample(&mut rng), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_seed_replays_the_same_run() {
+        let duration = JitteringDuration::from_millis(100, 3.);
+        let pipeline = || {
+            StreamVisBuilder::source_seeded(20, 7)
+                .map_buffer_unordered(duration, 4)
+                .filter(duration, 0.5)
+        };
+
+        let timed = |events: Vec<RecordedEvent>| {
+            events
+                .into_iter()
+                .map(|event| (event.at, format!("{:?}", event.update)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(timed(run(pipeline()).await), timed(run(pipeline()).await));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lanes_sharing_a_seed_filter_the_same_units() {
+        let duration = JitteringDuration::from_millis(100, 3.);
+        let ordered = run(StreamVisBuilder::source_seeded(30, 7)
+            .map_buffered(duration, 4)
+            .filter(duration, 0.5))
+        .await;
+        let unordered = run(StreamVisBuilder::source_seeded(30, 7)
+            .map_buffer_unordered(duration, 2)
+            .filter(duration, 0.5))
+        .await;
+        let reseeded = run(StreamVisBuilder::source_seeded(30, 8)
+            .map_buffered(duration, 4)
+            .filter(duration, 0.5))
+        .await;
+
+        let filtered = filtered_out(&ordered);
+        assert!(!filtered.is_empty() && filtered.len() < 30);
+        assert_eq!(filtered, filtered_out(&unordered));
+        assert_ne!(filtered, filtered_out(&reseeded));
+    }
+
+    fn failed(events: &[RecordedEvent]) -> Vec<u32> {
+        events
+            .iter()
+            .filter_map(|event| match event.update {
+                StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                    id,
+                    value: UnitValueKind::Error,
+                }) => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn into_sink(events: &[RecordedEvent], sink_id: u32) -> Vec<&UnitAdvanceBlockEvent> {
+        events
+            .iter()
+            .filter_map(|event| match &event.update {
+                StreamUpdate::AdvanceBlock(advance) if advance.block_id == sink_id => Some(advance),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_first_error_ends_the_stream() {
+        let duration = JitteringDuration::from_millis(100, 2.);
+        let events = run(StreamVisBuilder::source_seeded(20, 5)
+            .try_buffered(duration, 3, 0.3)
+            .and_then(duration, 0.3))
+        .await;
+        // after the source at 0, stage ids start from 2
+        let sink_id = 4;
+
+        // the failed unit is the last one to reach the sink, the others still on their way are
+        // dropped with the stream
+        let failed = failed(&events);
+        let arrived = into_sink(&events, sink_id);
+        let last = arrived.last().unwrap();
+        assert!(failed.contains(&last.id));
+        assert!(arrived[..arrived.len() - 1]
+            .iter()
+            .all(|advance| !failed.contains(&advance.id)));
+
+        let dropped = events
+            .iter()
+            .filter_map(|event| match event.update {
+                StreamUpdate::Dropped(DroppedEvent { id }) => Some(id),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        let dropping = events
+            .iter()
+            .position(|event| matches!(event.update, StreamUpdate::Dropped(_)))
+            .unwrap();
+        assert!(!dropped.is_empty());
+        assert_eq!(dropped, in_flight(&events[..dropping], sink_id));
+        assert!(matches!(
+            events.last().unwrap().update,
+            StreamUpdate::Dropped(_)
+        ));
+        // units the source hadn't yielded yet were never created
+        let created = events
+            .iter()
+            .filter(|event| matches!(event.update, StreamUpdate::Created(_)))
+            .count();
+        assert!(created < 20);
+        assert_eq!(arrived.len() + dropped.len(), created);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_units_skip_the_stages_left() {
+        let duration = JitteringDuration::from_millis(100, 0.);
+        let events = run(StreamVisBuilder::source_seeded(10, 2)
+            .and_then(duration, 1.)
+            .try_filter(duration, 0.)
+            .try_buffered(duration, 2, 0.))
+        .await;
+
+        // filtering everything out, the failed unit still goes straight from the `and_then`
+        let arrived = into_sink(&events, 5);
+        assert_eq!(arrived.len(), 1);
+        assert_eq!((arrived[0].id, arrived[0].from_block_id), (0, 2));
+        assert!(filtered_out(&events).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn without_failures_every_unit_arrives() {
+        let duration = JitteringDuration::from_millis(100, 1.);
+        let events = run(StreamVisBuilder::source_seeded(10, 2)
+            .try_buffered(duration, 3, 0.)
+            .try_for_each(duration, 0.))
+        .await;
+
+        assert!(failed(&events).is_empty());
+        let arrived = into_sink(&events, 4)
+            .iter()
+            .map(|advance| advance.id)
+            .collect::<Vec<_>>();
+        assert_eq!(arrived, (0..10).collect::<Vec<_>>());
+    }
+
+    fn progress() -> (Progress, Receiver<StreamUpdate>) {
+        let (tx, rx) = unbounded();
+        let progress = Progress {
+            unit_id: 4,
+            block_id: 1,
+            tx: UpdateSender::new(tx, EventLog::new()),
+            phases: 0,
+        };
+        (progress, rx)
+    }
+
+    fn running(rx: &Receiver<StreamUpdate>) -> Vec<f32> {
+        rx.try_iter()
+            .map(|update| match update {
+                StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                    id: 4,
+                    value: UnitValueKind::RunningFuture(progress),
+                }) => progress,
+                update => panic!("unexpected update {update:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn progress_stays_below_completion() {
+        let (progress, rx) = progress();
+        progress.set(-1.);
+        progress.set(0.3);
+        progress.set(1.);
+        progress.set(7.);
+        assert_eq!(running(&rx), [0., 0.3, 0.99, 0.99]);
+    }
+
+    #[test]
+    fn phases_halve_the_remaining_progress() {
+        let (mut progress, rx) = progress();
+        for phase in ["connect", "send", "receive"] {
+            progress.phase(phase);
+        }
+        assert_eq!(running(&rx), [0.5, 0.75, 0.875]);
+    }
+
+    #[test]
+    fn stopping_cancels_the_running_futures() {
+        let pipeline = StreamVisBuilder::source_seeded(3, 7)
+            .map_buffered(JitteringDuration::from_millis(60_000, 0.), 1)
+            .sink();
+        let stop = pipeline.stop.unwrap();
+
+        let started = std::time::Instant::now();
+        stop.stop();
+        loop {
+            match pipeline.rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(_) => continue,
+                Err(e) => break assert!(e.is_disconnected()),
+            }
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn futures_follow_the_clock() {
+        let (clock_tx, clock) = unbounded();
+        let pipeline = StreamVisBuilder::source_seeded(2, 7)
+            .map_buffered(JitteringDuration::from_millis(100, 0.), 1)
+            .sink_with_clock(clock);
+        let arrived = |updates: &[StreamUpdate]| {
+            updates
+                .iter()
+                .filter(|update| {
+                    matches!(update, StreamUpdate::AdvanceBlock(advance) if advance.block_id == 3)
+                })
+                .count()
+        };
+
+        std::thread::sleep(Duration::from_millis(300));
+        let stood_still = pipeline.rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(arrived(&stood_still), 0);
+
+        for _ in 0..10 {
+            clock_tx.send(Duration::from_millis(50)).unwrap();
+        }
+        let mut updates = Vec::new();
+        while let Ok(update) = pipeline.rx.recv_timeout(Duration::from_secs(5)) {
+            updates.push(update);
+        }
+        assert_eq!(arrived(&updates), 2);
+    }
+
+    #[test]
+    fn runs_in_virtual_time() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let (blocks, events) = rt.block_on(
+            StreamVisBuilder::source(5)
+                .map_buffered(JitteringDuration::from_millis(1000, 0.), 2)
+                .run(),
+        );
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(blocks.len(), 3);
+        let arrived = events
+            .iter()
+            .filter(|event| {
+                matches!(&event.update, StreamUpdate::AdvanceBlock(advance) if advance.block_id == 3)
+            })
+            .count();
+        assert_eq!(arrived, 5);
+        // three rounds of two futures of a second each
+        let end = events.last().unwrap().at;
+        assert!(
+            end > Duration::from_millis(2900) && end < Duration::from_millis(3100),
+            "{end:?}"
+        );
+    }
+
+    /// Updates of `pipeline` until it's done, ticking `clock` by 10ms whenever it goes quiet,
+    /// at most `max_ticks` times
+    fn updates(
+        pipeline: StreamPipeline,
+        clock: crossbeam_channel::Sender<Duration>,
+        max_ticks: usize,
+    ) -> Vec<StreamUpdate> {
+        let mut updates = Vec::new();
+        let mut ticks = 0;
+        loop {
+            match pipeline.rx.recv_timeout(Duration::from_millis(50)) {
+                Ok(update) => updates.push(update),
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return updates,
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    assert!(ticks < max_ticks, "pipeline stalled after {ticks} ticks");
+                    clock.send(Duration::from_millis(10)).unwrap();
+                    ticks += 1;
+                }
+            }
+        }
+    }
+
+    fn reached_sink(updates: &[StreamUpdate], sink_id: u32) -> Vec<u32> {
+        updates
+            .iter()
+            .filter_map(|update| match update {
+                StreamUpdate::AdvanceBlock(advance) if advance.block_id == sink_id => {
+                    Some(advance.id)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    #[should_panic(expected = "nothing but the sink can follow try_for_each")]
+    fn try_for_each_ends_the_pipeline() {
+        let duration = JitteringDuration::from_millis(10, 0.);
+        _ = StreamVisBuilder::source_seeded(3, 0)
+            .try_for_each(duration, 0.1)
+            .filter(duration, 0.5);
+    }
+
+    #[test]
+    fn user_futures_can_do_io_on_the_virtual_clock() {
+        let (tick, clock) = unbounded();
+        let pipeline = StreamVisBuilder::source_seeded(3, 0)
+            .map_buffered_with(2, |_, progress| async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                // the peer answers in real time, off the runtime
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(20));
+                    let mut peer = std::net::TcpStream::connect(addr).unwrap();
+                    std::io::Write::write_all(&mut peer, b"done").unwrap();
+                });
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut read = String::new();
+                socket.read_to_string(&mut read).await.unwrap();
+                assert_eq!(read, "done");
+
+                progress.set(0.5);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            })
+            .sink_with_clock(clock);
+
+        // two rounds of 50ms sleeps, the reads take no ticks of their own
+        let updates = updates(pipeline, tick, 20);
+        assert_eq!(reached_sink(&updates, 3), [0, 1, 2]);
     }
 }
@@ -0,0 +1,407 @@
+use std::{fmt::Write as _, fs, io, path::Path};
+
+use bevy::render::color::Color;
+
+use crate::{
+    bench::{simulate, Estimate, RunMetrics},
+    gantt_export::{escape, svg_color},
+    pipeline_spec::{Parameter, PipelineSpec},
+    theme::theme,
+};
+
+const PANEL_WIDTH: f32 = 560.;
+const PANEL_HEIGHT: f32 = 180.;
+const PANEL_GAP: f32 = 50.;
+const AXIS_WIDTH: f32 = 60.;
+const TITLE_HEIGHT: f32 = 40.;
+const MARGIN: f32 = 20.;
+
+/// Metrics of every run at a single value of the swept parameter
+struct SweepPoint {
+    value: f32,
+    total: Estimate,
+    throughput: Estimate,
+    mean_latency: Estimate,
+    p95_latency: Estimate,
+}
+
+impl SweepPoint {
+    fn new(value: f32, metrics: &[RunMetrics]) -> Self {
+        Self {
+            value,
+            total: Estimate::of(metrics, |run| run.total_secs),
+            throughput: Estimate::of(metrics, |run| run.throughput),
+            mean_latency: Estimate::of(metrics, |run| run.mean_latency),
+            p95_latency: Estimate::of(metrics, |run| run.p95_latency),
+        }
+    }
+}
+
+/// Values from `from` to `to`, inclusive, `step` apart
+pub fn values(from: f32, to: f32, step: f32) -> Result<Vec<f32>, String> {
+    if !from.is_finite() || !to.is_finite() {
+        return Err(format!("--from {from} and --to {to} must be finite"));
+    }
+    if !(step > 0. && step.is_finite()) {
+        return Err(format!("--step {step} must be above 0"));
+    }
+    if from > to {
+        return Err(format!("--from {from} is above --to {to}"));
+    }
+
+    Ok((0..)
+        .map(|i| from + i as f32 * step)
+        .take_while(|value| *value <= to + step * 1e-3)
+        .collect())
+}
+
+/// Simulate `spec` `runs` times for every value of `parameter`, then write the metrics to
+/// `<path>.csv` and chart them against the parameter in `<path>.svg`
+pub fn run(
+    spec: &PipelineSpec,
+    parameter: Parameter,
+    stage: Option<usize>,
+    values: &[f32],
+    runs: usize,
+    path: &Path,
+) -> io::Result<()> {
+    let specs = values
+        .iter()
+        .map(|value| spec.with(parameter, stage, *value))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut description = String::new();
+    let mut points = Vec::new();
+    for (value, spec) in values.iter().zip(&specs) {
+        let metrics;
+        (description, metrics) = simulate(|| spec.build(), runs);
+
+        let point = SweepPoint::new(*value, &metrics);
+        println!(
+            "{parameter} = {value}: total {:.3}s, throughput {:.3}/s, latency {:.3}s (p95 {:.3}s)",
+            point.total.mean,
+            point.throughput.mean,
+            point.mean_latency.mean,
+            point.p95_latency.mean
+        );
+        points.push(point);
+    }
+
+    fs::write(
+        path.with_extension("csv"),
+        points_csv(parameter, runs, &points),
+    )?;
+
+    let title = format!("{description}, {runs} runs per value");
+    fs::write(
+        path.with_extension("svg"),
+        chart_svg(&title, parameter, &points),
+    )
+}
+
+fn points_csv(parameter: Parameter, runs: usize, points: &[SweepPoint]) -> String {
+    let mut csv = format!("{parameter},runs");
+    for metric in [
+        "total_secs",
+        "throughput_per_sec",
+        "latency_secs",
+        "latency_p95_secs",
+    ] {
+        _ = write!(csv, ",{metric}_mean,{metric}_ci_low,{metric}_ci_high");
+    }
+    csv.push('\n');
+
+    for point in points {
+        _ = write!(csv, "{},{runs}", point.value);
+        for estimate in [
+            point.total,
+            point.throughput,
+            point.mean_latency,
+            point.p95_latency,
+        ] {
+            _ = write!(
+                csv,
+                ",{:.6},{:.6},{:.6}",
+                estimate.mean,
+                estimate.low(),
+                estimate.high()
+            );
+        }
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Round tick spacing giving about 5 ticks up to `max`
+fn tick_step(max: f32) -> f32 {
+    let rough = max / 5.;
+    let magnitude = 10_f32.powf(rough.log10().floor());
+    [1., 2., 5., 10.]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= rough)
+        .unwrap_or(magnitude * 10.)
+}
+
+/// A metric's name and its series, each named with how to read it off a point
+type Panel = (
+    &'static str,
+    Vec<(&'static str, fn(&SweepPoint) -> Estimate)>,
+);
+
+/// A line per series with its confidence interval as a band, stacked panels per metric
+fn chart_svg(title: &str, parameter: Parameter, points: &[SweepPoint]) -> String {
+    let panels: [Panel; 3] = [
+        ("total runtime (s)", vec![("total", |point| point.total)]),
+        (
+            "throughput (units/s)",
+            vec![("throughput", |point| point.throughput)],
+        ),
+        (
+            "latency (s)",
+            vec![
+                ("mean", |point| point.mean_latency),
+                ("p95", |point| point.p95_latency),
+            ],
+        ),
+    ];
+
+    let width = MARGIN * 2. + AXIS_WIDTH + PANEL_WIDTH;
+    let height = TITLE_HEIGHT + panels.len() as f32 * (PANEL_HEIGHT + PANEL_GAP) + MARGIN;
+
+    let mut svg = String::new();
+    _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="11">"#
+    );
+    _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        svg_color(theme().background)
+    );
+    _ = writeln!(
+        svg,
+        r#"<text x="{MARGIN}" y="{}" fill="white" font-size="13">{}</text>"#,
+        MARGIN + 5.,
+        escape(title)
+    );
+
+    let min_value = points
+        .iter()
+        .map(|point| point.value)
+        .fold(f32::INFINITY, f32::min);
+    let max_value = points
+        .iter()
+        .map(|point| point.value)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let value_range = (max_value - min_value).max(f32::EPSILON);
+
+    let chart_x = MARGIN + AXIS_WIDTH;
+    let x = |value: f32| chart_x + (value - min_value) / value_range * PANEL_WIDTH;
+
+    for (i, (name, series)) in panels.iter().enumerate() {
+        let top = TITLE_HEIGHT + i as f32 * (PANEL_HEIGHT + PANEL_GAP) + 15.;
+        let bottom = top + PANEL_HEIGHT;
+
+        let max = points
+            .iter()
+            .flat_map(|point| series.iter().map(|(_, estimate)| estimate(point).high()))
+            .fold(0_f32, f32::max);
+        let step = tick_step(max.max(f32::EPSILON));
+        let y_max = (max / step).ceil().max(1.) * step;
+        let y = |value: f32| bottom - value.max(0.) / y_max * PANEL_HEIGHT;
+
+        _ = writeln!(
+            svg,
+            r#"<text x="{chart_x}" y="{}" fill="white">{}</text>"#,
+            top - 6.,
+            escape(name)
+        );
+
+        let mut tick = 0.;
+        while tick <= y_max + step * 1e-3 {
+            _ = writeln!(
+                svg,
+                r#"<line x1="{chart_x}" y1="{0}" x2="{1}" y2="{0}" stroke="white" stroke-opacity="0.15"/><text x="{2}" y="{0}" fill="white" text-anchor="end" dominant-baseline="middle">{3}</text>"#,
+                y(tick),
+                chart_x + PANEL_WIDTH,
+                chart_x - 6.,
+                format_tick(tick, step)
+            );
+            tick += step;
+        }
+
+        for point in points {
+            _ = writeln!(
+                svg,
+                r#"<line x1="{0}" y1="{bottom}" x2="{0}" y2="{1}" stroke="white" stroke-opacity="0.3"/><text x="{0}" y="{2}" fill="white" text-anchor="middle">{3}</text>"#,
+                x(point.value),
+                bottom + 4.,
+                bottom + 16.,
+                point.value
+            );
+        }
+
+        for (j, (label, estimate)) in series.iter().enumerate() {
+            let color = svg_color(series_color(j));
+
+            let upper = points
+                .iter()
+                .map(|point| format!("{},{}", x(point.value), y(estimate(point).high())));
+            let lower = points
+                .iter()
+                .rev()
+                .map(|point| format!("{},{}", x(point.value), y(estimate(point).low())));
+            _ = writeln!(
+                svg,
+                r#"<polygon points="{}" fill="{color}" fill-opacity="0.3"/>"#,
+                upper.chain(lower).collect::<Vec<_>>().join(" ")
+            );
+
+            let line = points
+                .iter()
+                .map(|point| format!("{},{}", x(point.value), y(estimate(point).mean)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            _ = writeln!(
+                svg,
+                r#"<polyline points="{line}" fill="none" stroke="{color}" stroke-width="2"/>"#
+            );
+            for point in points {
+                _ = writeln!(
+                    svg,
+                    r#"<circle cx="{}" cy="{}" r="2.5" fill="{color}"/>"#,
+                    x(point.value),
+                    y(estimate(point).mean)
+                );
+            }
+
+            if series.len() > 1 {
+                let legend_x = chart_x + PANEL_WIDTH - 60. * (series.len() - j) as f32;
+                _ = writeln!(
+                    svg,
+                    r#"<rect x="{legend_x}" y="{}" width="10" height="10" fill="{color}"/><text x="{}" y="{}" fill="white" dominant-baseline="middle">{label}</text>"#,
+                    top - 15.,
+                    legend_x + 14.,
+                    top - 10.
+                );
+            }
+        }
+
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" fill="white" text-anchor="middle">{parameter}</text>"#,
+            chart_x + PANEL_WIDTH / 2.,
+            bottom + 30.
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn series_color(i: usize) -> Color {
+    let theme = theme();
+    [
+        theme.future_color(3),
+        theme.future_color(1),
+        theme.future_color(0),
+    ][i % 3]
+}
+
+fn format_tick(tick: f32, step: f32) -> String {
+    if step >= 1. {
+        format!("{tick:.0}")
+    } else {
+        let decimals = (-step.log10()).ceil() as usize;
+        format!("{tick:.decimals$}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(mean: f32) -> Estimate {
+        Estimate {
+            mean,
+            margin: 0.5,
+            std_dev: 1.,
+            min: mean - 1.,
+            max: mean + 1.,
+        }
+    }
+
+    fn point(value: f32) -> SweepPoint {
+        SweepPoint {
+            value,
+            total: estimate(10.),
+            throughput: estimate(2.),
+            mean_latency: estimate(3.),
+            p95_latency: estimate(4.),
+        }
+    }
+
+    #[test]
+    fn values_include_both_ends() {
+        assert_eq!(values(1., 3., 1.).unwrap(), [1., 2., 3.]);
+        assert_eq!(values(0., 1., 0.25).unwrap(), [0., 0.25, 0.5, 0.75, 1.]);
+        // rounding errors don't drop the last value
+        assert_eq!(values(0.1, 0.3, 0.1).unwrap().len(), 3);
+        assert_eq!(values(0., 1., 0.4).unwrap(), [0., 0.4, 0.8]);
+        assert_eq!(values(2., 2., 1.).unwrap(), [2.]);
+    }
+
+    #[test]
+    fn rejects_ranges_it_cannot_step_through() {
+        assert_eq!(values(0., 1., 0.).unwrap_err(), "--step 0 must be above 0");
+        assert!(values(0., 1., -1.).is_err());
+        assert!(values(0., 1., f32::NAN).is_err());
+        assert!(values(0., f32::INFINITY, 1.).is_err());
+        assert!(values(f32::NAN, 1., 1.).is_err());
+        assert_eq!(values(3., 1., 1.).unwrap_err(), "--from 3 is above --to 1");
+    }
+
+    #[test]
+    fn ticks_are_round() {
+        assert_eq!(tick_step(10.), 2.);
+        assert_eq!(tick_step(7.), 2.);
+        assert_eq!(tick_step(100.), 20.);
+        assert!((tick_step(0.03) - 0.01).abs() < 1e-6);
+
+        assert_eq!(format_tick(20., 5.), "20");
+        assert_eq!(format_tick(0.4, 0.2), "0.4");
+        assert_eq!(format_tick(0.05, 0.01), "0.05");
+    }
+
+    #[test]
+    fn writes_a_row_per_value() {
+        let csv = points_csv(Parameter::Buffered, 20, &[point(1.), point(2.)]);
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "buffered,runs,total_secs_mean,total_secs_ci_low,total_secs_ci_high,\
+             throughput_per_sec_mean,throughput_per_sec_ci_low,throughput_per_sec_ci_high,\
+             latency_secs_mean,latency_secs_ci_low,latency_secs_ci_high,\
+             latency_p95_secs_mean,latency_p95_secs_ci_low,latency_p95_secs_ci_high"
+        );
+        assert_eq!(
+            lines[2],
+            "2,20,10.000000,9.500000,10.500000,2.000000,1.500000,2.500000,\
+             3.000000,2.500000,3.500000,4.000000,3.500000,4.500000"
+        );
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn charts_every_series() {
+        let svg = chart_svg("a & b", Parameter::Duration, &[point(100.), point(200.)]);
+        assert!(svg.contains("a &amp; b"));
+        assert_eq!(svg.matches("<polyline").count(), 4);
+        assert_eq!(svg.matches("<polygon").count(), 4);
+        // a circle per point and series
+        assert_eq!(svg.matches("<circle").count(), 8);
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
+use bevy::render::color::Color;
+use serde::{de::Error, Deserialize, Deserializer};
+
+use crate::{
+    stream_vis::{
+        BG_COLOR, BUFFER_COLOR, BUFFER_UNORDERED_COLOR, DIVIDER_COLOR, ERROR_COLOR, FILTER_COLOR,
+        SOURCE_COLOR,
+    },
+    stream_vis_builder::COLORS,
+};
+
+/// Colours of the animation and of the exports, written as hex strings like "#22272e"
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    #[serde(deserialize_with = "hex_color")]
+    pub background: Color,
+    #[serde(deserialize_with = "hex_color")]
+    pub source: Color,
+    #[serde(deserialize_with = "hex_color")]
+    pub buffer: Color,
+    #[serde(deserialize_with = "hex_color")]
+    pub buffer_unordered: Color,
+    #[serde(deserialize_with = "hex_color")]
+    pub filter: Color,
+    #[serde(deserialize_with = "hex_color")]
+    pub divider: Color,
+    #[serde(deserialize_with = "hex_color")]
+    pub text: Color,
+    /// Parameters in the block labels
+    #[serde(deserialize_with = "hex_color")]
+    pub parameter: Color,
+    /// Failed units
+    #[serde(deserialize_with = "hex_color")]
+    pub error: Color,
+    /// Futures of each map or filter stage, cycled through by block id
+    #[serde(deserialize_with = "hex_colors")]
+    pub futures: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: BG_COLOR,
+            source: SOURCE_COLOR,
+            buffer: BUFFER_COLOR,
+            buffer_unordered: BUFFER_UNORDERED_COLOR,
+            filter: FILTER_COLOR,
+            divider: DIVIDER_COLOR,
+            text: Color::WHITE,
+            parameter: Color::RED,
+            error: ERROR_COLOR,
+            futures: COLORS.to_vec(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn future_color(&self, block_id: u32) -> Color {
+        self.futures[block_id as usize % self.futures.len()]
+    }
+}
+
+static THEME: OnceLock<RwLock<Arc<Theme>>> = OnceLock::new();
+
+fn current() -> &'static RwLock<Arc<Theme>> {
+    THEME.get_or_init(Default::default)
+}
+
+/// The theme in use, the default one unless `set` was called
+pub fn theme() -> Arc<Theme> {
+    current().read().unwrap().clone()
+}
+
+/// Use `theme` for whatever is drawn or built from now on
+pub fn set(theme: Theme) {
+    *current().write().unwrap() = Arc::new(theme);
+}
+
+fn parse_hex<E: Error>(hex: &str) -> Result<Color, E> {
+    Color::hex(hex).map_err(|e| E::custom(format!("invalid color {hex:?}: {e}")))
+}
+
+fn hex_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    parse_hex(&String::deserialize(deserializer)?)
+}
+
+fn hex_colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    let colors = Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|hex| parse_hex(hex))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if colors.is_empty() {
+        return Err(D::Error::custom("futures needs at least one color"));
+    }
+    Ok(colors)
+}
@@ -0,0 +1,298 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{recording::RecordedEvent, stream_vis::StreamBlock, StreamUpdate, UnitValueKind};
+
+/// A unit's stay in a single block between the source and the sink
+#[derive(Clone, Debug)]
+pub struct StageVisit {
+    pub block_id: u32,
+    pub entered: Duration,
+    pub started: Option<Duration>,
+    pub finished: Option<Duration>,
+    pub left: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct UnitTimeline {
+    pub id: u32,
+    pub created: Duration,
+    pub stages: Vec<StageVisit>,
+    pub filtered_out: Option<Duration>,
+    /// When its future failed
+    pub failed: Option<Duration>,
+    /// When it was dropped along with the stream, an error having reached the sink
+    pub dropped: Option<Duration>,
+    pub sink: Option<Duration>,
+}
+
+impl UnitTimeline {
+    /// When the unit left the source
+    pub fn started(&self) -> Option<Duration> {
+        self.stages.first().map(|stage| stage.entered).or(self.sink)
+    }
+}
+
+/// Time of the last event of the run
+pub fn run_end(events: &[RecordedEvent]) -> Duration {
+    events.last().map(|event| event.at).unwrap_or_default()
+}
+
+/// Units reaching the sink per second, from the first unit created to the last arrival
+pub fn throughput(units: &[UnitTimeline]) -> f32 {
+    let arrivals = units.iter().filter_map(|unit| unit.sink);
+    let first_created = units.iter().map(|unit| unit.created).min();
+
+    match (first_created, arrivals.clone().max()) {
+        (Some(first), Some(last)) if last > first => {
+            arrivals.count() as f32 / (last - first).as_secs_f32()
+        }
+        _ => 0.,
+    }
+}
+
+/// Seconds from creation to the sink of every unit that made it there
+pub fn latencies(units: &[UnitTimeline]) -> Vec<f32> {
+    units
+        .iter()
+        .filter_map(|unit| {
+            unit.sink
+                .map(|sink| sink.saturating_sub(unit.created).as_secs_f32())
+        })
+        .collect()
+}
+
+/// Nearest-rank percentile of already sorted values
+pub fn percentile(sorted: &[f32], percent: f32) -> Option<f32> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = ((percent / 100. * sorted.len() as f32).ceil() as usize).max(1);
+    Some(sorted[rank.min(sorted.len()) - 1])
+}
+
+/// Reconstruct every unit's path through the pipeline from a run's event log
+pub fn unit_timelines(events: &[RecordedEvent], blocks: &[StreamBlock]) -> Vec<UnitTimeline> {
+    let sink_id = blocks.iter().find_map(|block| match block {
+        StreamBlock::Sink(sink) => Some(sink.id),
+        _ => None,
+    });
+
+    let mut units = BTreeMap::<u32, UnitTimeline>::new();
+
+    for event in events {
+        match &event.update {
+            StreamUpdate::Created(created) => {
+                units.insert(
+                    created.id,
+                    UnitTimeline {
+                        id: created.id,
+                        created: event.at,
+                        stages: Vec::new(),
+                        filtered_out: None,
+                        failed: None,
+                        dropped: None,
+                        sink: None,
+                    },
+                );
+            }
+            StreamUpdate::AdvanceBlock(advance) => {
+                let Some(unit) = units.get_mut(&advance.id) else {
+                    continue;
+                };
+
+                if let Some(stage) = unit.stages.last_mut() {
+                    stage.left.get_or_insert(event.at);
+                }
+
+                if Some(advance.block_id) == sink_id {
+                    unit.sink = Some(event.at);
+                } else {
+                    unit.stages.push(StageVisit {
+                        block_id: advance.block_id,
+                        entered: event.at,
+                        started: None,
+                        finished: None,
+                        left: None,
+                    });
+                }
+            }
+            StreamUpdate::ChangeValue(change) => {
+                let Some(unit) = units.get_mut(&change.id) else {
+                    continue;
+                };
+
+                let finished = match change.value {
+                    UnitValueKind::RunningFuture(progress) => progress >= 1.,
+                    UnitValueKind::Error => {
+                        unit.failed = Some(event.at);
+                        true
+                    }
+                    _ => continue,
+                };
+
+                let Some(stage) = unit.stages.last_mut() else {
+                    continue;
+                };
+
+                stage.started.get_or_insert(event.at);
+                if finished {
+                    stage.finished.get_or_insert(event.at);
+                }
+            }
+            StreamUpdate::FilteredOut(filtered) => {
+                let Some(unit) = units.get_mut(&filtered.id) else {
+                    continue;
+                };
+
+                unit.filtered_out = Some(event.at);
+                if let Some(stage) = unit.stages.last_mut() {
+                    stage.left.get_or_insert(event.at);
+                }
+            }
+            StreamUpdate::Dropped(dropped) => {
+                let Some(unit) = units.get_mut(&dropped.id) else {
+                    continue;
+                };
+
+                unit.dropped = Some(event.at);
+                if let Some(stage) = unit.stages.last_mut() {
+                    stage.left.get_or_insert(event.at);
+                }
+            }
+        }
+    }
+
+    units.into_values().collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{
+        stream_vis::{BufferBlock, SinkBlock, SourceBlock},
+        FilteredOutEvent, UnitAdvanceBlockEvent, UnitCreatedEvent, UnitValueUpdateEvent,
+    };
+    use bevy::render::color::Color;
+
+    use super::*;
+
+    fn at(millis: u64, update: StreamUpdate) -> RecordedEvent {
+        RecordedEvent {
+            at: Duration::from_millis(millis),
+            update,
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Unit 1 goes through the map to the sink, unit 2 is filtered out in the map
+    pub(crate) fn run() -> (Vec<StreamBlock>, Vec<RecordedEvent>) {
+        let blocks = vec![
+            StreamBlock::Source(SourceBlock { id: 0 }),
+            StreamBlock::MapBuffer(BufferBlock {
+                id: 2,
+                duration: Some(ms(500)),
+                buffered: 1,
+                failure_ratio: None,
+                units: Default::default(),
+            }),
+            StreamBlock::Sink(SinkBlock { id: 3 }),
+        ];
+
+        let advance = |id, from_block_id, block_id| {
+            StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
+                id,
+                block_id,
+                from_block_id,
+            })
+        };
+        let running = |id, progress| {
+            StreamUpdate::ChangeValue(UnitValueUpdateEvent {
+                id,
+                value: UnitValueKind::RunningFuture(progress),
+            })
+        };
+        let created = |id| {
+            StreamUpdate::Created(UnitCreatedEvent {
+                id,
+                block_id: 0,
+                value: UnitValueKind::Value(Color::WHITE),
+            })
+        };
+
+        let events = vec![
+            at(0, created(1)),
+            at(0, created(2)),
+            at(10, advance(1, 0, 2)),
+            at(10, advance(2, 0, 2)),
+            at(20, running(1, 0.5)),
+            at(30, StreamUpdate::FilteredOut(FilteredOutEvent { id: 2 })),
+            at(60, running(1, 1.)),
+            at(70, advance(1, 2, 3)),
+        ];
+
+        (blocks, events)
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = [1., 2., 3., 4., 5., 6., 7., 8., 9., 10.];
+        assert_eq!(percentile(&sorted, 50.), Some(5.));
+        assert_eq!(percentile(&sorted, 95.), Some(10.));
+        assert_eq!(percentile(&sorted, 0.), Some(1.));
+        assert_eq!(percentile(&[], 50.), None);
+    }
+
+    #[test]
+    fn follows_units_through_their_stages() {
+        let (blocks, events) = run();
+        let units = unit_timelines(&events, &blocks);
+        assert_eq!(run_end(&events), ms(70));
+
+        let [arrived, filtered] = units.as_slice() else {
+            panic!("expected two units, got {units:?}");
+        };
+
+        assert_eq!(arrived.started(), Some(ms(10)));
+        assert_eq!(arrived.sink, Some(ms(70)));
+        let [stage] = arrived.stages.as_slice() else {
+            panic!("expected a single stage, got {:?}", arrived.stages);
+        };
+        assert_eq!(
+            (
+                stage.block_id,
+                stage.entered,
+                stage.started,
+                stage.finished,
+                stage.left
+            ),
+            (2, ms(10), Some(ms(20)), Some(ms(60)), Some(ms(70)))
+        );
+
+        assert_eq!(filtered.filtered_out, Some(ms(30)));
+        assert_eq!(filtered.sink, None);
+        assert_eq!(filtered.stages[0].left, Some(ms(30)));
+        assert_eq!(filtered.stages[0].started, None);
+    }
+
+    #[test]
+    fn measures_throughput_and_latencies() {
+        let (blocks, events) = run();
+        let units = unit_timelines(&events, &blocks);
+        assert_eq!(latencies(&units), [0.07]);
+        assert_eq!(throughput(&units), 1. / 0.07);
+        assert_eq!(throughput(&units[1..]), 0.);
+    }
+
+    #[test]
+    fn ignores_events_of_unknown_units() {
+        let (blocks, mut events) = run();
+        events.insert(
+            0,
+            at(5, StreamUpdate::FilteredOut(FilteredOutEvent { id: 9 })),
+        );
+        assert_eq!(unit_timelines(&events, &blocks).len(), 2);
+    }
+}
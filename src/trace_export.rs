@@ -0,0 +1,227 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    recording::RecordedEvent,
+    stream_vis::StreamBlock,
+    timeline::{run_end, unit_timelines},
+};
+
+/// Write a run in the Chrome Trace Event format, readable by Perfetto and chrome://tracing.
+/// Every block is a process track, with a thread per unit that passed through it
+pub fn write_chrome_trace(
+    path: &Path,
+    blocks: &[StreamBlock],
+    events: &[RecordedEvent],
+) -> io::Result<()> {
+    let end = run_end(events);
+    let mut trace = Vec::new();
+
+    for block in blocks {
+        trace.push(json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": block.id(),
+            "args": { "name": block.name() },
+        }));
+        trace.push(json!({
+            "name": "process_sort_index",
+            "ph": "M",
+            "pid": block.id(),
+            "args": { "sort_index": block.id() },
+        }));
+    }
+
+    let source_id = blocks.first().map(|block| block.id()).unwrap_or_default();
+    let sink_id = blocks.last().map(|block| block.id()).unwrap_or_default();
+
+    for unit in unit_timelines(events, blocks) {
+        let mut unit_blocks = vec![source_id];
+        unit_blocks.extend(unit.stages.iter().map(|stage| stage.block_id));
+        if unit.sink.is_some() {
+            unit_blocks.push(sink_id);
+        }
+
+        for block_id in unit_blocks {
+            trace.push(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": block_id,
+                "tid": unit.id,
+                "args": { "name": format!("unit {}", unit.id) },
+            }));
+            trace.push(json!({
+                "name": "thread_sort_index",
+                "ph": "M",
+                "pid": block_id,
+                "tid": unit.id,
+                "args": { "sort_index": unit.id },
+            }));
+        }
+
+        trace.extend(slice(
+            source_id,
+            unit.id,
+            "queued",
+            unit.created,
+            unit.started().unwrap_or(end),
+        ));
+
+        for stage in &unit.stages {
+            let left = stage.left.unwrap_or(end);
+
+            trace.extend(slice(
+                stage.block_id,
+                unit.id,
+                "pending",
+                stage.entered,
+                stage.started.unwrap_or(left),
+            ));
+
+            if let Some(started) = stage.started {
+                trace.extend(slice(
+                    stage.block_id,
+                    unit.id,
+                    "running",
+                    started,
+                    stage.finished.unwrap_or(left),
+                ));
+            }
+
+            if let Some(finished) = stage.finished {
+                trace.extend(slice(stage.block_id, unit.id, "waiting", finished, left));
+            }
+        }
+
+        if let (Some(at), Some(stage)) = (unit.filtered_out, unit.stages.last()) {
+            trace.push(instant(stage.block_id, unit.id, "filtered out", at));
+        }
+
+        if let (Some(at), Some(stage)) = (unit.failed, unit.stages.last()) {
+            trace.push(instant(stage.block_id, unit.id, "failed", at));
+        }
+
+        if let (Some(at), Some(stage)) = (unit.dropped, unit.stages.last()) {
+            trace.push(instant(stage.block_id, unit.id, "dropped", at));
+        }
+
+        if let Some(at) = unit.sink {
+            trace.push(instant(sink_id, unit.id, "arrived", at));
+        }
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(
+        file,
+        &json!({
+            "traceEvents": trace,
+            "displayTimeUnit": "ms",
+        }),
+    )?;
+
+    Ok(())
+}
+
+fn slice(pid: u32, tid: u32, name: &str, start: Duration, end: Duration) -> Option<Value> {
+    if end <= start {
+        return None;
+    }
+
+    Some(json!({
+        "name": name,
+        "cat": "unit",
+        "ph": "X",
+        "pid": pid,
+        "tid": tid,
+        "ts": start.as_micros() as u64,
+        "dur": (end - start).as_micros() as u64,
+    }))
+}
+
+fn instant(pid: u32, tid: u32, name: &str, at: Duration) -> Value {
+    json!({
+        "name": name,
+        "cat": "unit",
+        "ph": "i",
+        "s": "t",
+        "pid": pid,
+        "tid": tid,
+        "ts": at.as_micros() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::tests::run;
+
+    use super::*;
+
+    fn trace() -> Vec<Value> {
+        let (blocks, events) = run();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.json");
+        write_chrome_trace(&path, &blocks, &events).unwrap();
+
+        let trace: Value = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        trace["traceEvents"].as_array().unwrap().clone()
+    }
+
+    /// (pid, tid, name, ts, dur) of the slices and instants of the trace, in microseconds
+    fn spans(trace: &[Value]) -> Vec<(u64, u64, &str, u64, u64)> {
+        trace
+            .iter()
+            .filter(|event| event["ph"] != "M")
+            .map(|event| {
+                (
+                    event["pid"].as_u64().unwrap(),
+                    event["tid"].as_u64().unwrap(),
+                    event["name"].as_str().unwrap(),
+                    event["ts"].as_u64().unwrap(),
+                    event["dur"].as_u64().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn names_a_process_per_block() {
+        let trace = trace();
+        let names = trace
+            .iter()
+            .filter(|event| event["name"] == "process_name")
+            .map(|event| {
+                (
+                    event["pid"].as_u64().unwrap(),
+                    event["args"]["name"].as_str().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [(0, "source"), (2, ".map(500ms).buffer(1)"), (3, "sink")]
+        );
+    }
+
+    #[test]
+    fn slices_every_stage_of_a_unit() {
+        assert_eq!(
+            spans(&trace()),
+            [
+                (0, 1, "queued", 0, 10_000),
+                (2, 1, "pending", 10_000, 10_000),
+                (2, 1, "running", 20_000, 40_000),
+                (2, 1, "waiting", 60_000, 10_000),
+                (3, 1, "arrived", 70_000, 0),
+                (0, 2, "queued", 0, 10_000),
+                (2, 2, "pending", 10_000, 20_000),
+                (2, 2, "filtered out", 30_000, 0),
+            ]
+        );
+    }
+}
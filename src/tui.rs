@@ -0,0 +1,481 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, stdout},
+    time::{Duration, Instant},
+};
+
+use bevy::render::color::Color as BevyColor;
+use crossbeam_channel::{Receiver, RecvTimeoutError, TryRecvError};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Paragraph},
+};
+
+use crate::{stream_vis::StreamBlock, StreamUpdate, UnitValueKind};
+
+const PROGRESS_WIDTH: usize = 8;
+const DUMP_WIDTH: u16 = 120;
+
+#[derive(Clone, Copy, Debug)]
+enum UnitState {
+    Queued,
+    Pending,
+    Running(f32),
+    Failed,
+}
+
+/// What the terminal shows of a pipeline, updated from the same `StreamUpdate`s as the window
+pub struct TuiState {
+    blocks: Vec<StreamBlock>,
+    units: BTreeMap<u32, (u32, UnitState)>,
+    passed: BTreeMap<u32, u32>,
+    dropped: BTreeMap<u32, u32>,
+    arrived: Vec<u32>,
+    /// The failed unit whose arrival ended the stream
+    error: Option<u32>,
+    started: Instant,
+    finished: bool,
+    interactive: bool,
+}
+
+impl TuiState {
+    pub fn new(blocks: Vec<StreamBlock>) -> Self {
+        Self {
+            blocks,
+            units: BTreeMap::new(),
+            passed: BTreeMap::new(),
+            dropped: BTreeMap::new(),
+            arrived: Vec::new(),
+            error: None,
+            started: Instant::now(),
+            finished: false,
+            interactive: false,
+        }
+    }
+
+    pub fn update(&mut self, update: &StreamUpdate) {
+        match update {
+            StreamUpdate::Created(created) => {
+                self.units
+                    .insert(created.id, (created.block_id, UnitState::Queued));
+            }
+            StreamUpdate::AdvanceBlock(advance) => {
+                // a filter passes its units on unchanged, so their `from_block_id` is the block
+                // before it, the block the unit was last seen entering is the one it leaves
+                let from_block_id = self
+                    .units
+                    .get(&advance.id)
+                    .map(|(block_id, _)| *block_id)
+                    .unwrap_or(advance.from_block_id);
+
+                // leave first, a full `buffer_unordered` frees the slot before it is taken again
+                if let Some(block) = self.block_mut(from_block_id) {
+                    block.leave(advance.id);
+                    if let StreamBlock::FilterBlock(_) = block {
+                        *self.passed.entry(from_block_id).or_default() += 1;
+                    }
+                }
+                if let Some(block) = self.block_mut(advance.block_id) {
+                    block.enter(advance.id);
+                }
+
+                if matches!(self.block_mut(advance.block_id), Some(StreamBlock::Sink(_))) {
+                    if let Some((_, UnitState::Failed)) = self.units.remove(&advance.id) {
+                        self.error = Some(advance.id);
+                    }
+                    self.arrived.push(advance.id);
+                } else {
+                    self.units
+                        .insert(advance.id, (advance.block_id, UnitState::Pending));
+                }
+            }
+            StreamUpdate::ChangeValue(change) => {
+                let Some((_, state)) = self.units.get_mut(&change.id) else {
+                    return;
+                };
+
+                *state = match change.value {
+                    UnitValueKind::PendingFuture(_) => UnitState::Pending,
+                    UnitValueKind::RunningFuture(progress) => UnitState::Running(progress),
+                    UnitValueKind::Error => UnitState::Failed,
+                    UnitValueKind::Value(_) | UnitValueKind::Shape(_) | UnitValueKind::Label(_) => {
+                        return
+                    }
+                };
+            }
+            StreamUpdate::FilteredOut(filtered) => {
+                if let Some((block_id, _)) = self.units.remove(&filtered.id) {
+                    *self.dropped.entry(block_id).or_default() += 1;
+                }
+            }
+            StreamUpdate::Dropped(dropped) => {
+                if let Some((block_id, _)) = self.units.remove(&dropped.id) {
+                    if let Some(block) = self.block_mut(block_id) {
+                        block.leave(dropped.id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn block_mut(&mut self, id: u32) -> Option<&mut StreamBlock> {
+        self.blocks.iter_mut().find(|block| block.id() == id)
+    }
+
+    fn unit_line(&self, id: u32) -> Line<'static> {
+        let state = self
+            .units
+            .get(&id)
+            .map(|(_, state)| *state)
+            .unwrap_or(UnitState::Queued);
+
+        let glyphs = match state {
+            UnitState::Queued => Span::raw(""),
+            UnitState::Pending => Span::styled(
+                format!("{} pending", "·".repeat(PROGRESS_WIDTH)),
+                Style::new().dark_gray(),
+            ),
+            UnitState::Failed => Span::styled(
+                format!("{} failed", "█".repeat(PROGRESS_WIDTH)),
+                Style::new().red(),
+            ),
+            UnitState::Running(progress) if progress >= 1. => Span::styled(
+                format!("{} done", "█".repeat(PROGRESS_WIDTH)),
+                Style::new().green(),
+            ),
+            UnitState::Running(progress) => {
+                let filled = (progress * PROGRESS_WIDTH as f32) as usize;
+                Span::raw(format!(
+                    "{}{} {:>3.0}%",
+                    "█".repeat(filled),
+                    "░".repeat(PROGRESS_WIDTH - filled),
+                    progress * 100.
+                ))
+            }
+        };
+
+        Line::from(vec![Span::raw(format!("#{id:<3} ")), glyphs])
+    }
+
+    fn units_in(&self, block_id: u32) -> Vec<u32> {
+        self.units
+            .iter()
+            .filter(|(_, (unit_block_id, _))| *unit_block_id == block_id)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Title and content of a block's box
+    fn block_lines(&self, block: &StreamBlock) -> (String, Vec<Line<'static>>) {
+        match block {
+            StreamBlock::Source(source) => {
+                let queued = self.units_in(source.id);
+                let mut lines = vec![Line::from(format!("{} queued", queued.len()))];
+                lines.extend(queued.iter().map(|id| Line::from(format!("#{id}"))));
+                ("source".to_string(), lines)
+            }
+            StreamBlock::MapBuffer(buffer) => {
+                // queue order, the head has to finish before anything behind it can leave
+                let lines = buffer
+                    .units
+                    .iter()
+                    .enumerate()
+                    .map(|(i, id)| {
+                        let mut line = self.unit_line(*id);
+                        line.spans.insert(0, Span::raw(format!("{}. ", i + 1)));
+                        line
+                    })
+                    .collect();
+                (
+                    format!(
+                        "{} {}/{}",
+                        block.name(),
+                        buffer.units.len(),
+                        buffer.buffered
+                    ),
+                    lines,
+                )
+            }
+            StreamBlock::MapBufferUnordered(buffer) => {
+                let occupied = buffer.slots.iter().filter(|slot| slot.is_some()).count();
+                let lines = buffer
+                    .slots
+                    .iter()
+                    .enumerate()
+                    .map(|(i, slot)| match slot {
+                        Some(id) => {
+                            let mut line = self.unit_line(*id);
+                            line.spans.insert(0, Span::raw(format!("[{i}] ")));
+                            line
+                        }
+                        None => Line::styled(format!("[{i}] ·"), Style::new().dark_gray()),
+                    })
+                    .collect();
+                (
+                    format!("{} {}/{}", block.name(), occupied, buffer.buffered),
+                    lines,
+                )
+            }
+            StreamBlock::FilterBlock(filter) => {
+                let passed = self.passed.get(&filter.id).copied().unwrap_or_default();
+                let dropped = self.dropped.get(&filter.id).copied().unwrap_or_default();
+
+                let mut lines = self
+                    .units_in(filter.id)
+                    .into_iter()
+                    .map(|id| self.unit_line(id))
+                    .collect::<Vec<_>>();
+                lines.push(Line::from(format!("passed {passed} dropped {dropped}")));
+                (block.name(), lines)
+            }
+            StreamBlock::Sink(_) => {
+                let mut lines = vec![Line::from(format!("{} arrived", self.arrived.len()))];
+                if let Some(error) = self.error {
+                    lines.push(Line::styled(format!("error #{error}"), Style::new().red()));
+                }
+                lines.extend(
+                    self.arrived
+                        .iter()
+                        .rev()
+                        .map(|id| Line::from(format!("#{id}"))),
+                );
+                ("sink".to_string(), lines)
+            }
+        }
+    }
+
+    /// Rows needed to show every block without cutting its content
+    fn height(&self) -> u16 {
+        let content = self
+            .blocks
+            .iter()
+            .map(|block| self.block_lines(block).1.len())
+            .max()
+            .unwrap_or_default();
+
+        content as u16 + 2 + 1
+    }
+
+    pub fn render(&self, area: Rect, buf: &mut Buffer) {
+        let [blocks_area, status_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+
+        let constraints = self.blocks.iter().map(|block| match block {
+            StreamBlock::Source(_) | StreamBlock::Sink(_) => Constraint::Length(12),
+            _ => Constraint::Min(24),
+        });
+        let areas = Layout::horizontal(constraints).split(blocks_area);
+
+        for (block, area) in self.blocks.iter().zip(areas.iter()) {
+            let (title, lines) = self.block_lines(block);
+            Paragraph::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(title)
+                        .border_style(Style::new().fg(tui_color(block.color()))),
+                )
+                .render(*area, buf);
+        }
+
+        let mut status = format!(
+            "{:.2}s  {}",
+            self.started.elapsed().as_secs_f32(),
+            if self.finished { "finished" } else { "running" }
+        );
+        if self.interactive {
+            status.push_str(", q to quit");
+        }
+        Line::styled(status, Style::new().dark_gray()).render(status_area, buf);
+    }
+
+    /// The current frame as plain text, for pasting into tickets
+    pub fn text_frame(&self, width: u16) -> String {
+        let area = Rect::new(0, 0, width, self.height());
+        let mut buf = Buffer::empty(area);
+        self.render(area, &mut buf);
+
+        let mut text = String::new();
+        for y in 0..area.height {
+            let line = (0..area.width)
+                .map(|x| buf.get(x, y).symbol())
+                .collect::<String>();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+fn tui_color(color: BevyColor) -> Color {
+    let [r, g, b, _] = color.as_rgba_u8();
+    Color::Rgb(r, g, b)
+}
+
+/// Render a pipeline in the terminal until `q`, `esc` or `ctrl-c`
+pub fn run(blocks: Vec<StreamBlock>, rx: Receiver<StreamUpdate>) -> io::Result<()> {
+    let mut state = TuiState::new(blocks);
+    state.interactive = true;
+
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+
+    let result = (|| loop {
+        loop {
+            match rx.try_recv() {
+                Ok(update) => state.update(&update),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    state.finished = true;
+                    break;
+                }
+            }
+        }
+
+        terminal.draw(|frame| state.render(frame.size(), frame.buffer_mut()))?;
+
+        if event::poll(Duration::from_millis(50))? {
+            if let Event::Key(key) = event::read()? {
+                let quit = key.kind == KeyEventKind::Press
+                    && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                        || (key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL)));
+                if quit {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+
+    disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen)?;
+
+    result
+}
+
+/// Print a plain-text frame whenever the pipeline changed, at most once per `every`,
+/// until the pipeline finishes
+pub fn dump(blocks: Vec<StreamBlock>, rx: Receiver<StreamUpdate>, every: Duration) {
+    let width = crossterm::terminal::size()
+        .map(|(width, _)| width)
+        .unwrap_or(DUMP_WIDTH);
+
+    let mut state = TuiState::new(blocks);
+    let mut changed = false;
+    let mut next_frame = Instant::now() + every;
+
+    loop {
+        match rx.recv_timeout(next_frame.saturating_duration_since(Instant::now())) {
+            Ok(update) => {
+                state.update(&update);
+                changed = true;
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if Instant::now() >= next_frame {
+            if changed {
+                print!("{}", state.text_frame(width));
+                changed = false;
+            }
+            next_frame += every;
+        }
+    }
+
+    state.finished = true;
+    print!("{}", state.text_frame(width));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        stream_vis::{FilterBlock, FilterKind, SinkBlock, SourceBlock},
+        timeline::tests::run,
+        FilteredOutEvent, UnitAdvanceBlockEvent, UnitCreatedEvent,
+    };
+
+    use super::*;
+
+    fn state_after(blocks: Vec<StreamBlock>, updates: &[StreamUpdate]) -> TuiState {
+        let mut state = TuiState::new(blocks);
+        for update in updates {
+            state.update(update);
+        }
+        state
+    }
+
+    #[test]
+    fn shows_the_buffer_queue_in_order() {
+        let (blocks, events) = run();
+        let updates = events
+            .iter()
+            .take_while(|event| event.at.as_millis() <= 20)
+            .map(|event| event.update.clone())
+            .collect::<Vec<_>>();
+
+        let frame = state_after(blocks, &updates).text_frame(DUMP_WIDTH);
+        assert!(frame.contains(".map(500ms).buffer(1) 2/1"), "{frame}");
+        assert!(frame.contains("1. #1   ████░░░░  50%"), "{frame}");
+        assert!(frame.contains("2. #2   ········ pending"), "{frame}");
+        assert!(frame.contains("0 arrived"), "{frame}");
+        assert!(frame.trim_end().ends_with("running"), "{frame}");
+    }
+
+    #[test]
+    fn counts_units_through_a_filter() {
+        let blocks = vec![
+            StreamBlock::Source(SourceBlock { id: 0 }),
+            StreamBlock::FilterBlock(FilterBlock {
+                id: 2,
+                duration: Some(Duration::from_millis(100)),
+                filter_ratio: None,
+                predicate: None,
+                kind: FilterKind::Filter,
+                failure_ratio: None,
+            }),
+            StreamBlock::Sink(SinkBlock { id: 3 }),
+        ];
+        let created = |id| {
+            StreamUpdate::Created(UnitCreatedEvent {
+                id,
+                block_id: 0,
+                value: UnitValueKind::Value(BevyColor::WHITE),
+            })
+        };
+        let advance = |id, from_block_id, block_id| {
+            StreamUpdate::AdvanceBlock(UnitAdvanceBlockEvent {
+                id,
+                block_id,
+                from_block_id,
+            })
+        };
+
+        let mut state = state_after(
+            blocks,
+            &[
+                created(1),
+                created(2),
+                created(3),
+                advance(1, 0, 2),
+                advance(2, 0, 2),
+                // filters pass their units on as coming from the block before them
+                advance(1, 0, 3),
+                StreamUpdate::FilteredOut(FilteredOutEvent { id: 2 }),
+            ],
+        );
+        state.finished = true;
+
+        let frame = state.text_frame(DUMP_WIDTH);
+        assert!(frame.contains("1 queued"), "{frame}");
+        assert!(frame.contains("passed 1 dropped 1"), "{frame}");
+        assert!(frame.contains("1 arrived"), "{frame}");
+        assert!(frame.trim_end().ends_with("finished"), "{frame}");
+    }
+}
@@ -0,0 +1,69 @@
+//! Stand-in for a real service: connects to a visualizer started with
+//! `cargo run -- --listen 127.0.0.1:7878` and simulates a `buffered(3)` map followed by a filter.
+//!
+//! usage: `cargo run -p stream-vis-client --example harness [addr]`
+use std::{thread, time::Duration};
+
+use stream_vis_client::{Block, Client, COLORS};
+
+const UNITS: u32 = 9;
+const BUFFERED: u32 = 3;
+const STEPS: u32 = 5;
+
+fn main() -> std::io::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7878".to_string());
+
+    let mut client = Client::connect_tcp(addr)?;
+
+    client.pipeline(&[
+        Block::MapBuffered {
+            duration: Some(Duration::from_millis(500)),
+            buffered: BUFFERED as usize,
+        },
+        Block::Filter {
+            duration: Some(Duration::from_millis(300)),
+        },
+    ])?;
+
+    for batch_start in (0..UNITS).step_by(BUFFERED as usize) {
+        let batch = batch_start..(batch_start + BUFFERED).min(UNITS);
+
+        for unit in batch.clone() {
+            client.created(unit, 0)?;
+            client.advance(unit, 0, 1)?;
+            client.pending(unit, COLORS[2])?;
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        // the futures of a batch run concurrently, each one a bit slower than the previous
+        for step in 0..STEPS {
+            for (i, unit) in batch.clone().enumerate() {
+                let progress = (step as f32 / STEPS as f32) * (1. - i as f32 * 0.15);
+                client.running(unit, progress)?;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        for unit in batch.clone() {
+            client.running(unit, 1.)?;
+        }
+
+        for unit in batch {
+            client.advance(unit, 1, 2)?;
+            client.pending(unit, COLORS[3])?;
+            for step in 0..=STEPS {
+                client.running(unit, step as f32 / STEPS as f32)?;
+                thread::sleep(Duration::from_millis(60));
+            }
+
+            if unit % 2 == 0 {
+                client.advance(unit, 2, 3)?;
+            } else {
+                client.filtered(unit)?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Service instrumented with plain `tracing` spans, visualized through `StreamVisLayer`.
+//!
+//! usage: `cargo run -p stream-vis-client --features tracing --example tracing [addr]`
+use std::{thread, time::Duration};
+
+use stream_vis_client::{
+    tracing_layer::{StageKind, StreamVisLayer},
+    Client,
+};
+use tracing::{info, info_span};
+use tracing_subscriber::prelude::*;
+
+fn main() -> std::io::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7878".to_string());
+
+    let layer = StreamVisLayer::new(Client::connect_tcp(addr)?, "request")
+        .stage("fetch", StageKind::Map { buffered: 1 })
+        .stage("authorize", StageKind::Filter);
+
+    tracing_subscriber::registry().with(layer).init();
+
+    for i in 0..8 {
+        let request = info_span!("request", i);
+        let _request = request.enter();
+
+        {
+            let fetch = info_span!("fetch");
+            let _fetch = fetch.enter();
+            for step in 1..5 {
+                thread::sleep(Duration::from_millis(80));
+                info!(progress = step as f64 / 5.);
+            }
+        }
+
+        {
+            let authorize = info_span!("authorize");
+            let _authorize = authorize.enter();
+            thread::sleep(Duration::from_millis(200));
+            if i % 3 == 0 {
+                info!(filtered = true, "unauthorized");
+            }
+        }
+    }
+
+    // the layer writes from its own thread, give it time to send the last events
+    thread::sleep(Duration::from_millis(100));
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! Tiny client for feeding a running `rust-stream-vis --listen` instance.
+//!
+//! The wire format is line based text, one message per line:
+//!
+//! ```text
+//! source <block>
+//! map_buffered <block> <duration_ms|-> <buffered>
+//! map_buffer_unordered <block> <duration_ms|-> <buffered>
+//! filter <block> <duration_ms|->
+//! sink <block>
+//! start
+//! created <unit> <block>
+//! advance <unit> <from_block> <to_block>
+//! pending <unit> <r> <g> <b>
+//! running <unit> <progress>
+//! value <unit> <r> <g> <b>
+//! shape <unit> square|circle|diamond
+//! label <unit> <text>
+//! error <unit>
+//! filtered <unit>
+//! dropped <unit>
+//! ```
+//!
+//! A connection first describes its pipeline, ends the description with `start`,
+//! and then streams unit events. A `-` duration stands for futures of unknown
+//! length, and runs of whitespace in labels are collapsed into single spaces.
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+
+#[cfg(feature = "tracing")]
+pub mod tracing_layer;
+
+/// A single stage of the visualized pipeline, with the base duration of its futures if known
+#[derive(Clone, Copy, Debug)]
+pub enum Block {
+    MapBuffered {
+        duration: Option<Duration>,
+        buffered: usize,
+    },
+    MapBufferUnordered {
+        duration: Option<Duration>,
+        buffered: usize,
+    },
+    Filter {
+        duration: Option<Duration>,
+    },
+}
+
+/// Outline a unit is drawn with, the same shapes the visualizer draws
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shape {
+    #[default]
+    Square,
+    Circle,
+    Diamond,
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Shape::Square => "square",
+            Shape::Circle => "circle",
+            Shape::Diamond => "diamond",
+        })
+    }
+}
+
+/// Colors used for the pending future stroke, the same palette the visualizer uses
+pub const COLORS: [(f32, f32, f32); 4] = [
+    (0.50, 0.27, 0.45),
+    (0.66, 0.39, 0.39),
+    (0.61, 0.27, 0.27),
+    (0.26, 0.46, 0.42),
+];
+
+pub struct Client<W: Write> {
+    writer: W,
+}
+
+impl Client<BufWriter<TcpStream>> {
+    pub fn connect_tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(BufWriter::new(stream)))
+    }
+}
+
+#[cfg(unix)]
+impl Client<BufWriter<UnixStream>> {
+    pub fn connect_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(UnixStream::connect(path)?)))
+    }
+}
+
+impl<W: Write> Client<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Describe the pipeline. Block ids are assigned in order: the source is block 0,
+    /// `blocks` follow from 1, and the sink is `blocks.len() + 1`
+    pub fn pipeline(&mut self, blocks: &[Block]) -> io::Result<()> {
+        self.line(format_args!("source 0"))?;
+
+        for (i, block) in blocks.iter().enumerate() {
+            let id = i + 1;
+            match block {
+                Block::MapBuffered { duration, buffered } => self.line(format_args!(
+                    "map_buffered {} {} {}",
+                    id,
+                    millis(duration),
+                    buffered
+                ))?,
+                Block::MapBufferUnordered { duration, buffered } => self.line(format_args!(
+                    "map_buffer_unordered {} {} {}",
+                    id,
+                    millis(duration),
+                    buffered
+                ))?,
+                Block::Filter { duration } => {
+                    self.line(format_args!("filter {} {}", id, millis(duration)))?
+                }
+            }
+        }
+
+        self.line(format_args!("sink {}", blocks.len() + 1))?;
+        self.line(format_args!("start"))?;
+        self.writer.flush()
+    }
+
+    pub fn created(&mut self, unit: u32, block: u32) -> io::Result<()> {
+        self.event(format_args!("created {} {}", unit, block))
+    }
+
+    pub fn advance(&mut self, unit: u32, from_block: u32, to_block: u32) -> io::Result<()> {
+        self.event(format_args!("advance {} {} {}", unit, from_block, to_block))
+    }
+
+    pub fn pending(&mut self, unit: u32, (r, g, b): (f32, f32, f32)) -> io::Result<()> {
+        self.event(format_args!("pending {} {} {} {}", unit, r, g, b))
+    }
+
+    pub fn running(&mut self, unit: u32, progress: f32) -> io::Result<()> {
+        self.event(format_args!("running {} {}", unit, progress))
+    }
+
+    pub fn value(&mut self, unit: u32, (r, g, b): (f32, f32, f32)) -> io::Result<()> {
+        self.event(format_args!("value {} {} {} {}", unit, r, g, b))
+    }
+
+    pub fn shape(&mut self, unit: u32, shape: Shape) -> io::Result<()> {
+        self.event(format_args!("shape {} {}", unit, shape))
+    }
+
+    /// Show `label` on the unit in place of its id, when the visualizer shows unit labels.
+    /// Runs of whitespace are shown as a single space. Line breaks are rejected since they'd end
+    /// the message, and blank labels since the visualizer would reject the message
+    pub fn label(&mut self, unit: u32, label: &str) -> io::Result<()> {
+        if label.trim().is_empty() || label.contains(['\n', '\r']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a label cannot be blank or contain line breaks",
+            ));
+        }
+        self.event(format_args!("label {} {}", unit, label))
+    }
+
+    /// The unit's future failed, turning it red
+    pub fn error(&mut self, unit: u32) -> io::Result<()> {
+        self.event(format_args!("error {}", unit))
+    }
+
+    pub fn filtered(&mut self, unit: u32) -> io::Result<()> {
+        self.event(format_args!("filtered {}", unit))
+    }
+
+    /// The unit was dropped along with the stream, an error having ended it
+    pub fn dropped(&mut self, unit: u32) -> io::Result<()> {
+        self.event(format_args!("dropped {}", unit))
+    }
+
+    fn event(&mut self, args: std::fmt::Arguments) -> io::Result<()> {
+        self.line(args)?;
+        self.writer.flush()
+    }
+
+    fn line(&mut self, args: std::fmt::Arguments) -> io::Result<()> {
+        self.writer.write_fmt(args)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+fn millis(duration: &Option<Duration>) -> String {
+    duration.map_or("-".to_string(), |duration| duration.as_millis().to_string())
+}
@@ -0,0 +1,569 @@
+//! `tracing_subscriber::Layer` turning spans into visualization events.
+//!
+//! Every span named like the configured unit span becomes a stream unit. Spans named like one
+//! of the configured stages, nested anywhere below a unit span, move that unit into the stage's
+//! block: creating the span marks the unit pending, entering it marks its future running and
+//! closing it marks the future done. Events recorded inside a stage can report progress with a
+//! `progress` field between 0 and 1, and drop the unit with `filtered = true`.
+//!
+//! The block list is inferred from the order stages first show up in. The pipeline is sent to
+//! the visualizer once every configured stage was seen or the first unit span closed, events
+//! until then are held back.
+//!
+//! Events are written by a separate thread, so a slow visualizer never blocks the traced tasks.
+//! Once `EVENT_BUFFER` events are waiting for it, new ones are dropped.
+use std::{
+    fmt,
+    io::Write,
+    mem,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{Block, Client, COLORS};
+
+#[derive(Clone, Copy, Debug)]
+pub enum StageKind {
+    Map { buffered: usize },
+    MapUnordered { buffered: usize },
+    Filter,
+}
+
+impl StageKind {
+    // span durations are only known when they close, blocks are labeled as user futures
+    fn block(&self) -> Block {
+        match *self {
+            StageKind::Map { buffered } => Block::MapBuffered {
+                duration: None,
+                buffered,
+            },
+            StageKind::MapUnordered { buffered } => Block::MapBufferUnordered {
+                duration: None,
+                buffered,
+            },
+            StageKind::Filter => Block::Filter { duration: None },
+        }
+    }
+}
+
+/// Events waiting for the writer thread before new ones are dropped
+pub const EVENT_BUFFER: usize = 1024;
+
+pub struct StreamVisLayer<W: Write> {
+    unit_span: &'static str,
+    stages: Vec<(&'static str, StageKind)>,
+    next_unit: AtomicU32,
+    /// Handed to the writer thread when it starts, with the first event
+    client: Mutex<Option<Client<W>>>,
+    events: OnceLock<SyncSender<UnitEvent>>,
+}
+
+struct Writer<W: Write> {
+    client: Client<W>,
+    stages: Vec<(&'static str, StageKind)>,
+    order: Vec<&'static str>,
+    started: bool,
+    backlog: Vec<UnitEvent>,
+}
+
+enum UnitEvent {
+    Created(u32),
+    Entered {
+        unit: u32,
+        from: Option<&'static str>,
+        stage: &'static str,
+    },
+    Running {
+        unit: u32,
+        progress: f32,
+    },
+    Left {
+        unit: u32,
+        stage: Option<&'static str>,
+        filtered: bool,
+    },
+}
+
+struct UnitSpan {
+    id: u32,
+    stage: Option<&'static str>,
+    filtered: bool,
+}
+
+struct StageSpan {
+    unit: u32,
+    entered: bool,
+}
+
+impl<W: Write> StreamVisLayer<W> {
+    pub fn new(client: Client<W>, unit_span: &'static str) -> Self {
+        Self {
+            unit_span,
+            stages: Vec::new(),
+            next_unit: AtomicU32::new(0),
+            client: Mutex::new(Some(client)),
+            events: OnceLock::new(),
+        }
+    }
+
+    /// Visualize spans named `name` as a stage of the pipeline
+    pub fn stage(mut self, name: &'static str, kind: StageKind) -> Self {
+        self.stages.push((name, kind));
+        self
+    }
+}
+
+impl<W: Write + Send + 'static> StreamVisLayer<W> {
+    fn emit(&self, event: UnitEvent) {
+        let events = self.events.get_or_init(|| {
+            let (tx, rx) = sync_channel(EVENT_BUFFER);
+            let writer = Writer {
+                client: self.client.lock().unwrap().take().unwrap(),
+                stages: self.stages.clone(),
+                order: Vec::new(),
+                started: false,
+                backlog: Vec::new(),
+            };
+            thread::spawn(move || writer.run(rx));
+            tx
+        });
+
+        // dropping events beats stalling the traced service when the visualizer falls behind
+        _ = events.try_send(event);
+    }
+}
+
+impl<W: Write> Writer<W> {
+    fn run(mut self, events: Receiver<UnitEvent>) {
+        for event in events {
+            self.emit(event);
+        }
+    }
+
+    fn discover(&mut self, stage: &'static str) {
+        if !self.order.contains(&stage) {
+            self.order.push(stage);
+        }
+    }
+
+    fn emit(&mut self, event: UnitEvent) {
+        if let UnitEvent::Entered { stage, .. } = event {
+            self.discover(stage);
+        }
+
+        if self.started {
+            self.send(event);
+            return;
+        }
+
+        let unit_done = matches!(event, UnitEvent::Left { .. });
+        self.backlog.push(event);
+
+        if unit_done || self.order.len() == self.stages.len() {
+            self.start();
+        }
+    }
+
+    fn start(&mut self) {
+        self.started = true;
+
+        let blocks = self
+            .order
+            .iter()
+            .filter_map(|stage| self.kind(stage))
+            .map(|kind| kind.block())
+            .collect::<Vec<_>>();
+        _ = self.client.pipeline(&blocks);
+
+        for event in mem::take(&mut self.backlog) {
+            self.send(event);
+        }
+    }
+
+    fn kind(&self, stage: &str) -> Option<StageKind> {
+        self.stages
+            .iter()
+            .find(|(name, _)| *name == stage)
+            .map(|(_, kind)| *kind)
+    }
+
+    // stages that first showed up after the pipeline was sent have no block
+    fn block_id(&self, stage: &str) -> Option<u32> {
+        self.order
+            .iter()
+            .position(|name| *name == stage)
+            .map(|i| i as u32 + 1)
+    }
+
+    fn send(&mut self, event: UnitEvent) {
+        // the visualization is best effort, a gone visualizer must not break the traced service
+        _ = match event {
+            UnitEvent::Created(unit) => self.client.created(unit, 0),
+            UnitEvent::Entered { unit, from, stage } => {
+                let Some(block_id) = self.block_id(stage) else {
+                    return;
+                };
+                let from_block_id = from.and_then(|from| self.block_id(from)).unwrap_or(0);
+
+                _ = self.client.advance(unit, from_block_id, block_id);
+                self.client
+                    .pending(unit, COLORS[block_id as usize % COLORS.len()])
+            }
+            UnitEvent::Running { unit, progress } => self.client.running(unit, progress),
+            UnitEvent::Left {
+                unit,
+                stage,
+                filtered,
+            } => {
+                let sink_id = self.order.len() as u32 + 1;
+                let from_block_id = stage.and_then(|stage| self.block_id(stage)).unwrap_or(0);
+
+                // a unit that ends its life in a filter which is not the last block was dropped by it
+                let dropped_by_filter = matches!(
+                    stage.and_then(|stage| self.kind(stage)),
+                    Some(StageKind::Filter)
+                ) && from_block_id + 1 < sink_id;
+
+                if filtered || dropped_by_filter {
+                    self.client.filtered(unit)
+                } else {
+                    self.client.advance(unit, from_block_id, sink_id)
+                }
+            }
+        };
+    }
+}
+
+impl<S, W> Layer<S> for StreamVisLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: Write + Send + 'static,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let name = attrs.metadata().name();
+
+        if name == self.unit_span {
+            let unit = self.next_unit.fetch_add(1, Ordering::Relaxed);
+
+            span.extensions_mut().insert(UnitSpan {
+                id: unit,
+                stage: None,
+                filtered: false,
+            });
+            self.emit(UnitEvent::Created(unit));
+            return;
+        }
+
+        if self.stages.iter().all(|(stage, _)| *stage != name) {
+            return;
+        }
+
+        let Some(unit_span) = span.scope().skip(1).find(|s| s.name() == self.unit_span) else {
+            return;
+        };
+
+        let (unit, from) = {
+            let mut extensions = unit_span.extensions_mut();
+            let Some(unit) = extensions.get_mut::<UnitSpan>() else {
+                return;
+            };
+            (unit.id, unit.stage.replace(name))
+        };
+
+        span.extensions_mut().insert(StageSpan {
+            unit,
+            entered: false,
+        });
+
+        self.emit(UnitEvent::Entered {
+            unit,
+            from,
+            stage: name,
+        });
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        // async spans are entered on every poll, only the first one starts the future
+        let unit = {
+            let mut extensions = span.extensions_mut();
+            match extensions.get_mut::<StageSpan>() {
+                Some(stage) if !stage.entered => {
+                    stage.entered = true;
+                    stage.unit
+                }
+                _ => return,
+            }
+        };
+
+        self.emit(UnitEvent::Running { unit, progress: 0. });
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = UnitEventVisitor::default();
+        event.record(&mut visitor);
+
+        if visitor.progress.is_none() && visitor.filtered.is_none() {
+            return;
+        }
+
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+
+        for span in span.scope() {
+            let mut extensions = span.extensions_mut();
+
+            if let Some(stage) = extensions.get_mut::<StageSpan>() {
+                let unit = stage.unit;
+                drop(extensions);
+
+                // completion is reported when the stage span closes
+                if let Some(progress) = visitor.progress.take() {
+                    self.emit(UnitEvent::Running {
+                        unit,
+                        progress: (progress as f32).clamp(0., 0.99),
+                    });
+                }
+                continue;
+            }
+
+            if let Some(unit) = extensions.get_mut::<UnitSpan>() {
+                if let Some(filtered) = visitor.filtered {
+                    unit.filtered = filtered;
+                }
+                return;
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let event = {
+            let extensions = span.extensions();
+            if let Some(stage) = extensions.get::<StageSpan>() {
+                UnitEvent::Running {
+                    unit: stage.unit,
+                    progress: 1.,
+                }
+            } else if let Some(unit) = extensions.get::<UnitSpan>() {
+                UnitEvent::Left {
+                    unit: unit.id,
+                    stage: unit.stage,
+                    filtered: unit.filtered,
+                }
+            } else {
+                return;
+            }
+        };
+
+        self.emit(event);
+    }
+}
+
+#[derive(Default)]
+struct UnitEventVisitor {
+    progress: Option<f64>,
+    filtered: Option<bool>,
+}
+
+impl Visit for UnitEventVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "progress" {
+            self.progress = Some(value);
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "filtered" {
+            self.filtered = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{mpsc, Arc},
+        time::{Duration, Instant},
+    };
+
+    use tracing::{info, info_span};
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    /// Output shared with the test, the writer thread drops its clone when it's done
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Lines written while `f` runs with a layer visualizing `request` spans through `stages`
+    fn traced(stages: &[(&'static str, StageKind)], f: impl FnOnce()) -> Vec<String> {
+        let output = Shared::default();
+        let layer = stages.iter().fold(
+            StreamVisLayer::new(Client::new(output.clone()), "request"),
+            |layer, (name, kind)| layer.stage(name, *kind),
+        );
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), f);
+
+        let started = Instant::now();
+        while Arc::strong_count(&output.0) > 1 {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "writer never finished"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let output = output.0.lock().unwrap();
+        String::from_utf8(output.clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// A `request` span going through `fetch` and `authorize` spans in the order of `stages`
+    fn request(i: u32, stages: &[&str], filtered: bool) {
+        let _request = info_span!("request", i).entered();
+        for stage in stages {
+            let _stage = match *stage {
+                "fetch" => info_span!("fetch"),
+                _ => info_span!("authorize"),
+            }
+            .entered();
+            info!(progress = 0.5);
+            if filtered {
+                info!(filtered = true);
+            }
+        }
+    }
+
+    fn pending(unit: u32, block: usize) -> String {
+        let (r, g, b) = COLORS[block % COLORS.len()];
+        format!("pending {unit} {r} {g} {b}")
+    }
+
+    #[test]
+    fn blocks_follow_the_order_stages_show_up_in() {
+        let lines = traced(
+            &[
+                ("authorize", StageKind::Filter),
+                ("fetch", StageKind::Map { buffered: 2 }),
+            ],
+            || request(0, &["fetch", "authorize"], false),
+        );
+
+        assert_eq!(
+            lines,
+            [
+                "source 0".to_string(),
+                "map_buffered 1 - 2".to_string(),
+                "filter 2 -".to_string(),
+                "sink 3".to_string(),
+                "start".to_string(),
+                "created 0 0".to_string(),
+                "advance 0 0 1".to_string(),
+                pending(0, 1),
+                "running 0 0".to_string(),
+                "running 0 0.5".to_string(),
+                "running 0 1".to_string(),
+                "advance 0 1 2".to_string(),
+                pending(0, 2),
+                "running 0 0".to_string(),
+                "running 0 0.5".to_string(),
+                "running 0 1".to_string(),
+                "advance 0 2 3".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn units_ending_in_a_filter_were_dropped_by_it() {
+        let lines = traced(
+            &[
+                ("authorize", StageKind::Filter),
+                ("fetch", StageKind::Map { buffered: 1 }),
+            ],
+            || {
+                request(0, &["authorize", "fetch"], false);
+                request(1, &["authorize"], false);
+                request(2, &["authorize", "fetch"], true);
+            },
+        );
+
+        let ends = lines
+            .iter()
+            .filter(|line| line.starts_with("filtered") || line.starts_with("advance 0 2"))
+            .collect::<Vec<_>>();
+        assert_eq!(ends, ["advance 0 2 3", "filtered 1", "filtered 2"]);
+    }
+
+    /// Stalls every write until the test releases it
+    struct Stalled(mpsc::Receiver<()>);
+
+    impl Write for Stalled {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            _ = self.0.recv();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_stalled_visualizer_does_not_block_tracing() {
+        let (release, stalled) = mpsc::channel();
+        let layer = StreamVisLayer::new(Client::new(Stalled(stalled)), "request")
+            .stage("fetch", StageKind::Map { buffered: 1 });
+
+        let (done, finished) = mpsc::channel();
+        thread::spawn(move || {
+            tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+                for i in 0..EVENT_BUFFER as u32 * 2 {
+                    request(i, &["fetch"], false);
+                }
+            });
+            _ = done.send(());
+        });
+
+        let finished = finished.recv_timeout(Duration::from_secs(5));
+        drop(release);
+        assert!(finished.is_ok(), "tracing blocked on the visualizer");
+    }
+}